@@ -1,18 +1,34 @@
 use colored::Colorize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
 use crate::{Op, Value};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum BlockLinkState {
     Linked,
     Nothing,
 }
 
-#[derive(Debug)]
+/// A named variable on the stack, used when debugging.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Local {
+    pub name: String,
+    /// The slot relative to the start of the stackframe.
+    pub slot: usize,
+    /// The first op where the variable can be read.
+    pub start: usize,
+    /// The op where the variable is popped, `None` if it lives
+    /// until the function returns.
+    pub end: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Block {
     pub upvalues: Vec<(usize, bool)>,
+    /// The names of the upvalues, in the same order as `upvalues`.
+    pub upvalue_names: Vec<String>,
     pub linking: BlockLinkState,
 
     pub namespace: usize,
@@ -22,12 +38,14 @@ pub struct Block {
     pub ops: Vec<Op>,
     pub last_line_offset: usize,
     pub line_offsets: HashMap<usize, usize>,
+    pub locals: Vec<Local>,
 }
 
 impl Block {
     pub fn new(name: &str, namespace: usize, file: &Path) -> Self {
         Self {
             upvalues: Vec::new(),
+            upvalue_names: Vec::new(),
             linking: BlockLinkState::Nothing,
 
             namespace,
@@ -37,6 +55,7 @@ impl Block {
             ops: Vec::new(),
             last_line_offset: 0,
             line_offsets: HashMap::new(),
+            locals: Vec::new(),
         }
     }
 
@@ -56,6 +75,17 @@ impl Block {
         0
     }
 
+    /// The variables a user can see at the op. Hidden variables
+    /// have names starting with '/', and slot 0 is the function itself.
+    pub fn locals_at(&self, ip: usize) -> impl Iterator<Item = &Local> {
+        self.locals.iter().filter(move |local| {
+            local.slot != 0
+                && !local.name.starts_with('/')
+                && local.start <= ip
+                && local.end.map(|end| ip < end).unwrap_or(true)
+        })
+    }
+
     pub fn debug_print(&self, constants: Option<&[Value]>) {
         println!("     === {} ===", self.name.blue());
         for (i, s) in self.ops.iter().enumerate() {
@@ -0,0 +1,50 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::{upvalue::UpValue, value::Value, Frame};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CoroutineState {
+    /// Waits to be resumed, or to be started.
+    Suspended,
+    Running,
+    /// The function has returned, the coroutine can't be resumed again.
+    Done,
+}
+
+/// A function that can stop in the middle, and later continue
+/// where it stopped.
+///
+/// While the coroutine is suspended, the part of the stack it uses is
+/// kept here. The offsets of the frames and the slots of the upvalues are
+/// relative to where the function is on the stack.
+#[derive(Debug)]
+pub struct Coroutine {
+    pub state: CoroutineState,
+    /// Starts out with only the function.
+    pub stack: Vec<Value>,
+    /// Empty until the coroutine is started.
+    pub frames: Vec<Frame>,
+    /// The variables on the stack that are captured. They are
+    /// closed while the coroutine is suspended.
+    pub upvalues: Vec<(usize, Rc<RefCell<UpValue>>)>,
+}
+
+impl Coroutine {
+    pub fn new(function: Value) -> Self {
+        Self {
+            state: CoroutineState::Suspended,
+            stack: vec![function],
+            frames: Vec::new(),
+            upvalues: Vec::new(),
+        }
+    }
+
+    pub fn is_started(&self) -> bool {
+        !self.frames.is_empty()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == CoroutineState::Done
+    }
+}
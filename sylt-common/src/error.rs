@@ -73,6 +73,21 @@ pub enum RuntimeError {
     AssertFailed,
     InvalidProgram,
     Unreachable,
+
+    /// The program ran more ops than it was allowed to.
+    OpLimit(usize),
+    /// The program called more functions in a row than it was allowed to.
+    CallDepthLimit(usize),
+    StackLimit(usize),
+    HeapLimit(usize),
+
+    /// A coroutine was resumed after its function returned.
+    ResumeFinished,
+    /// A coroutine resumed itself, or a coroutine that resumed it.
+    ResumeRunning,
+    /// Yielded outside of a coroutine, or from a function
+    /// called by an external function.
+    YieldOutsideCoroutine,
 }
 
 #[derive(Debug, Clone)]
@@ -157,6 +172,62 @@ pub enum TypeError {
     UnknownConstraintArgument(String),
 
     UnknownVariant(String, String),
+
+    /// The values that aren't covered by the patterns of a case or loop.
+    NonExhaustive(Vec<String>),
+
+    /// A function can reach the end without returning this type.
+    MissingReturn(Type),
+}
+
+/// Things that are allowed, but probably mistakes.
+///
+/// Every warning has a name, which is used to allow it with a
+/// `// allow(name)` comment.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    UnusedVariable(String),
+    UnusedImport(String),
+    /// (Blob, field)
+    UnusedField(String, String),
+    ShadowedGlobal(String),
+    UnreachableCode,
+    UnreachablePattern,
+    /// An allow comment with a name that isn't a warning.
+    UnknownWarning(String),
+}
+
+impl Warning {
+    /// One of each warning.
+    fn all() -> [Warning; 7] {
+        [
+            Warning::UnusedVariable(String::new()),
+            Warning::UnusedImport(String::new()),
+            Warning::UnusedField(String::new(), String::new()),
+            Warning::ShadowedGlobal(String::new()),
+            Warning::UnreachableCode,
+            Warning::UnreachablePattern,
+            Warning::UnknownWarning(String::new()),
+        ]
+    }
+
+    /// The names of all warnings.
+    pub fn names() -> Vec<&'static str> {
+        Warning::all().iter().map(Warning::name).collect()
+    }
+
+    /// The name used to allow the warning.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Warning::UnusedVariable(_) => "unused_variable",
+            Warning::UnusedImport(_) => "unused_import",
+            Warning::UnusedField(..) => "unused_field",
+            Warning::ShadowedGlobal(_) => "shadowed_global",
+            Warning::UnreachableCode => "unreachable_code",
+            Warning::UnreachablePattern => "unreachable_pattern",
+            Warning::UnknownWarning(_) => "unknown_warning",
+        }
+    }
 }
 
 // TODO(ed): Switch to spans for the whole compiler?
@@ -197,7 +268,52 @@ pub enum Error {
         message: Option<String>,
     },
 
-    LuaError(String),
+    /// Lua failed to run the program. The positions are the Sylt lines
+    /// that what Lua printed points to, the first is where it failed.
+    LuaError {
+        stderr: String,
+        positions: Vec<(PathBuf, usize)>,
+    },
+
+    /// A bytecode file that cannot be loaded.
+    BytecodeError {
+        file: PathBuf,
+        message: String,
+    },
+    /// A bytecode file calls an extern function that isn't linked.
+    MissingExternFunction {
+        file: PathBuf,
+        name: String,
+    },
+
+    /// The program has an `external` definition the host doesn't supply.
+    MissingExternal {
+        file: PathBuf,
+        name: String,
+    },
+
+    /// A change that cannot be swapped into a running program.
+    ReloadError {
+        file: PathBuf,
+        message: String,
+    },
+
+    /// Something a program that embeds Sylt asked for isn't there.
+    EngineError(String),
+
+    /// Not an error, but probably a mistake.
+    Warning {
+        kind: Warning,
+        file: PathBuf,
+        span: Span,
+        message: Option<String>,
+    },
+}
+
+impl Error {
+    pub fn is_warning(&self) -> bool {
+        matches!(self, Error::Warning { .. })
+    }
 }
 
 impl fmt::Display for Error {
@@ -206,8 +322,49 @@ impl fmt::Display for Error {
             Error::NoFileGiven => {
                 write!(f, "No file to run")
             }
-            Error::LuaError(stderr) => {
-                write!(f, "Lua failed to run, \n:stderr:\n{}", stderr)
+            Error::LuaError { stderr, positions } => match positions.first() {
+                Some((file, line)) => {
+                    write!(f, "{}: ", "Lua error".red())?;
+                    write!(f, "{}\n", file_line_display(file, *line))?;
+                    for line in stderr.trim_end().split('\n') {
+                        write!(f, "{}{}\n", INDENT, line)?;
+                    }
+
+                    write_source_line_at(f, file, *line)
+                }
+                None => write!(f, "Lua failed to run, \n:stderr:\n{}", stderr),
+            },
+            Error::BytecodeError { file, message } => {
+                write!(f, "{}: ", "Bytecode error".red())?;
+                write!(f, "{}\n", file.display())?;
+                write!(f, "{}{}\n", INDENT, message)
+            }
+            Error::MissingExternFunction { file, name } => {
+                write!(f, "{}: ", "Bytecode error".red())?;
+                write!(f, "{}\n", file.display())?;
+                write!(
+                    f,
+                    "{}The program needs the extern function '{}', which isn't linked\n",
+                    INDENT, name
+                )
+            }
+            Error::MissingExternal { file, name } => {
+                write!(f, "{}: ", "Link error".red())?;
+                write!(f, "{}\n", file.display())?;
+                write!(
+                    f,
+                    "{}The program needs the external value '{}', which isn't supplied\n",
+                    INDENT, name
+                )
+            }
+            Error::EngineError(message) => {
+                write!(f, "{}: ", "Engine error".red())?;
+                write!(f, "{}\n", message)
+            }
+            Error::ReloadError { file, message } => {
+                write!(f, "{}: ", "Reload error".red())?;
+                write!(f, "{}\n", file.display())?;
+                write!(f, "{}{}\n", INDENT, message)
             }
             Error::FileNotFound(path) => {
                 write!(f, "File '{}' not found", path.display())
@@ -281,6 +438,20 @@ impl fmt::Display for Error {
                 }
                 Ok(())
             }
+            Error::Warning { kind, file, span, message } => {
+                write!(f, "{}: ", "warning".yellow())?;
+                write!(f, "{}\n", file_line_display(file, span.line_start))?;
+                write!(f, "{}{}\n", INDENT, kind)?;
+
+                if let Some(message) = message {
+                    for line in message.split('\n') {
+                        write!(f, "{}{}\n", INDENT, line)?;
+                    }
+                }
+                write!(f, "{}Allow it with '// allow({})'\n", INDENT, kind.name())?;
+
+                write_source_span_at(f, file, *span)
+            }
             Error::CompileError { file, span, message } => {
                 write!(f, "{}: ", "compile error".red())?;
                 write!(f, "{}\n", file_line_display(file, span.line_start))?;
@@ -348,6 +519,27 @@ impl fmt::Display for RuntimeError {
             RuntimeError::Unreachable => {
                 write!(f, "Reached unreachable code")
             }
+            RuntimeError::OpLimit(limit) => {
+                write!(f, "Ran out of ops, the limit is {}", limit)
+            }
+            RuntimeError::CallDepthLimit(limit) => {
+                write!(f, "Too many nested calls, the limit is {}", limit)
+            }
+            RuntimeError::StackLimit(limit) => {
+                write!(f, "The stack is too large, the limit is {} values", limit)
+            }
+            RuntimeError::HeapLimit(limit) => {
+                write!(f, "The heap is too large, the limit is {} values", limit)
+            }
+            RuntimeError::ResumeFinished => {
+                write!(f, "Cannot resume a coroutine that has returned")
+            }
+            RuntimeError::ResumeRunning => {
+                write!(f, "Cannot resume a coroutine that is running")
+            }
+            RuntimeError::YieldOutsideCoroutine => {
+                write!(f, "Can only yield from a coroutine")
+            }
         }
     }
 }
@@ -444,6 +636,48 @@ impl fmt::Display for TypeError {
                     enum_name, var_name
                 )
             }
+
+            TypeError::NonExhaustive(missing) => {
+                write!(f, "The patterns don't cover {}", missing.join(", "))
+            }
+
+            TypeError::MissingReturn(ty) => {
+                write!(f, "The function can end without returning a '{:?}'", ty)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::UnusedVariable(name) => {
+                write!(f, "The variable '{}' is never used", name)
+            }
+
+            Warning::UnusedImport(name) => {
+                write!(f, "The import '{}' is never used", name)
+            }
+
+            Warning::UnusedField(blob, field) => {
+                write!(f, "The field '{}.{}' is never used", blob, field)
+            }
+
+            Warning::ShadowedGlobal(name) => {
+                write!(f, "The local variable '{}' hides a global", name)
+            }
+
+            Warning::UnreachableCode => {
+                write!(f, "This code is never run")
+            }
+
+            Warning::UnreachablePattern => {
+                write!(f, "This branch can never be reached")
+            }
+
+            Warning::UnknownWarning(name) => {
+                write!(f, "There is no warning called '{}'", name)
+            }
         }
     }
 }
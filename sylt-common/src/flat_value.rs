@@ -18,6 +18,7 @@ pub enum FlatValue {
     List(Vec<FlatValueID>),
     Set(HashSet<FlatValueID>),
     Dict(HashMap<FlatValueID, FlatValueID>),
+    Range(i64, i64),
     Float(f64),
     Int(i64),
     Bool(bool),
@@ -101,6 +102,7 @@ impl FlatValue {
                     })
                     .collect(),
             ),
+            Value::Range(a, b) => FlatValue::Range(*a, *b),
             Value::Float(f) => FlatValue::Float(*f),
             Value::Int(i) => FlatValue::Int(*i),
             Value::Bool(b) => FlatValue::Bool(*b),
@@ -116,7 +118,8 @@ impl FlatValue {
                 *slot,
             ),
             Value::ExternFunction(slot) => FlatValue::ExternFunction(*slot),
-            Value::Nil => FlatValue::Nil,
+            // Coroutines are part of a running program, which can't be sent.
+            Value::Coroutine(_) | Value::Nil => FlatValue::Nil,
         };
         pack[id] = val;
         id
@@ -133,6 +136,7 @@ impl FlatValue {
             FlatValue::List(_) => Value::List(Rc::new(RefCell::new(Vec::new()))),
             FlatValue::Set(_) => Value::Set(Rc::new(RefCell::new(HashSet::new()))),
             FlatValue::Dict(_) => Value::Dict(Rc::new(RefCell::new(HashMap::new()))),
+            FlatValue::Range(a, b) => Value::Range(a, b),
             FlatValue::Float(f) => Value::Float(f),
             FlatValue::Int(i) => Value::Int(i),
             FlatValue::Bool(b) => Value::Bool(b),
@@ -0,0 +1,297 @@
+//! A cycle collector for the values the VM allocates.
+//!
+//! Values are reference counted, which frees most of them right away.
+//! Containers that end up referencing themselves keep each other alive
+//! though, so the collector keeps a weak reference to every container. When
+//! it runs it marks everything that can be reached from the roots - the
+//! containers that are still alive but weren't marked are only kept alive by
+//! cycles, and are emptied to break them.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::{coroutine::Coroutine, upvalue::UpValue, value::Value, Block, CoroutineState};
+
+/// How many objects are tracked before the first collection.
+const FIRST_COLLECTION: usize = 1024;
+
+/// Numbers describing the work of the collector.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GcStats {
+    /// Tracked objects that are still alive.
+    pub allocated: usize,
+    /// How many times the collector has run.
+    pub collections: usize,
+    /// How many objects have been freed by breaking cycles.
+    pub freed: usize,
+}
+
+/// The code that values can still run.
+#[derive(Default)]
+pub struct ReachableCode {
+    /// The blocks of the functions.
+    pub functions: HashSet<usize>,
+    /// The blocks that suspended coroutines are in the middle of.
+    pub frames: Vec<Rc<RefCell<Block>>>,
+}
+
+/// An object that can be part of a cycle.
+enum Tracked {
+    Blob(Weak<RefCell<HashMap<String, Value>>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Set(Weak<RefCell<HashSet<Value>>>),
+    Dict(Weak<RefCell<HashMap<Value, Value>>>),
+    UpValue(Weak<RefCell<UpValue>>),
+    Coroutine(Weak<RefCell<Coroutine>>),
+}
+
+/// An unreachable object, kept alive while the cycles are broken.
+enum Garbage {
+    Value(Value),
+    UpValue(Rc<RefCell<UpValue>>),
+}
+
+impl Tracked {
+    fn is_alive(&self) -> bool {
+        match self {
+            Tracked::Blob(b) => b.strong_count() > 0,
+            Tracked::List(l) => l.strong_count() > 0,
+            Tracked::Set(s) => s.strong_count() > 0,
+            Tracked::Dict(d) => d.strong_count() > 0,
+            Tracked::UpValue(u) => u.strong_count() > 0,
+            Tracked::Coroutine(c) => c.strong_count() > 0,
+        }
+    }
+
+    /// How many values the object holds, zero if it has been freed.
+    fn size(&self) -> usize {
+        match self {
+            Tracked::Blob(b) => b.upgrade().map(|b| b.borrow().len()),
+            Tracked::List(l) => l.upgrade().map(|l| l.borrow().len()),
+            Tracked::Set(s) => s.upgrade().map(|s| s.borrow().len()),
+            Tracked::Dict(d) => d.upgrade().map(|d| d.borrow().len() * 2),
+            Tracked::UpValue(u) => u.upgrade().map(|_| 1),
+            Tracked::Coroutine(c) => c.upgrade().map(|c| c.borrow().stack.len()),
+        }
+        .unwrap_or(0)
+    }
+
+    fn upgrade(&self) -> Option<Garbage> {
+        match self {
+            Tracked::Blob(b) => b.upgrade().map(|b| Garbage::Value(Value::Blob(b))),
+            Tracked::List(l) => l.upgrade().map(|l| Garbage::Value(Value::List(l))),
+            Tracked::Set(s) => s.upgrade().map(|s| Garbage::Value(Value::Set(s))),
+            Tracked::Dict(d) => d.upgrade().map(|d| Garbage::Value(Value::Dict(d))),
+            Tracked::UpValue(u) => u.upgrade().map(Garbage::UpValue),
+            Tracked::Coroutine(c) => c.upgrade().map(|c| Garbage::Value(Value::Coroutine(c))),
+        }
+    }
+}
+
+impl Garbage {
+    /// Drops all values the object references.
+    fn clear(&self) {
+        // The old values are dropped after the borrow ends.
+        match self {
+            Garbage::Value(Value::Blob(b)) => drop(std::mem::take(&mut *b.borrow_mut())),
+            Garbage::Value(Value::List(l)) => drop(std::mem::take(&mut *l.borrow_mut())),
+            Garbage::Value(Value::Set(s)) => drop(std::mem::take(&mut *s.borrow_mut())),
+            Garbage::Value(Value::Dict(d)) => drop(std::mem::take(&mut *d.borrow_mut())),
+            Garbage::Value(Value::Coroutine(c)) => {
+                let mut coroutine = c.borrow_mut();
+                coroutine.state = CoroutineState::Done;
+                let parts = (
+                    std::mem::take(&mut coroutine.stack),
+                    std::mem::take(&mut coroutine.frames),
+                    std::mem::take(&mut coroutine.upvalues),
+                );
+                drop(coroutine);
+                drop(parts);
+            }
+            Garbage::Value(_) => unreachable!("Only containers are tracked"),
+            Garbage::UpValue(u) => {
+                let value = std::mem::replace(&mut u.borrow_mut().value, Value::Nil);
+                drop(value);
+            }
+        }
+    }
+}
+
+pub struct Collector {
+    /// Every tracked object, by address.
+    objects: HashMap<usize, Tracked>,
+    /// The number of objects that triggers the next collection.
+    next_collection: usize,
+
+    collections: usize,
+    freed: usize,
+}
+
+impl Collector {
+    pub fn new() -> Self {
+        Self {
+            objects: HashMap::new(),
+            next_collection: FIRST_COLLECTION,
+
+            collections: 0,
+            freed: 0,
+        }
+    }
+
+    /// Starts tracking the value if it's a container.
+    pub fn track(&mut self, value: &Value) {
+        let tracked = match value {
+            Value::Blob(b) => Tracked::Blob(Rc::downgrade(b)),
+            Value::List(l) => Tracked::List(Rc::downgrade(l)),
+            Value::Set(s) => Tracked::Set(Rc::downgrade(s)),
+            Value::Dict(d) => Tracked::Dict(Rc::downgrade(d)),
+            Value::Coroutine(c) => Tracked::Coroutine(Rc::downgrade(c)),
+            _ => return,
+        };
+        self.objects.insert(value.unique_id(), tracked);
+    }
+
+    /// Starts tracking an upvalue, closed upvalues can hold on to the
+    /// function they're captured by.
+    pub fn track_upvalue(&mut self, upvalue: &Rc<RefCell<UpValue>>) {
+        self.objects.insert(
+            Rc::as_ptr(upvalue) as usize,
+            Tracked::UpValue(Rc::downgrade(upvalue)),
+        );
+    }
+
+    /// Whether enough objects have been allocated since the last collection.
+    pub fn should_collect(&self) -> bool {
+        self.objects.len() >= self.next_collection
+    }
+
+    /// Frees every tracked object that can't be reached from the roots or
+    /// the open upvalues.
+    pub fn collect<'a>(
+        &mut self,
+        roots: impl Iterator<Item = &'a Value>,
+        upvalues: impl Iterator<Item = &'a Rc<RefCell<UpValue>>>,
+    ) {
+        self.collections += 1;
+        self.objects.retain(|_, object| object.is_alive());
+
+        let mut marked = HashSet::new();
+        for upvalue in upvalues {
+            marked.insert(Rc::as_ptr(upvalue) as usize);
+        }
+        let mut code = ReachableCode::default();
+        mark(roots.cloned().collect(), &mut marked, &mut code);
+
+        // Everything is kept alive until all the cycles are broken, so
+        // nothing is freed while it's being cleared.
+        let garbage: Vec<_> = self
+            .objects
+            .iter()
+            .filter(|(id, _)| !marked.contains(id))
+            .filter_map(|(_, object)| object.upgrade())
+            .collect();
+        for object in garbage.iter() {
+            object.clear();
+        }
+        self.freed += garbage.len();
+        drop(garbage);
+
+        self.objects.retain(|_, object| object.is_alive());
+        self.next_collection = FIRST_COLLECTION.max(self.objects.len() * 2);
+    }
+
+    /// The number of values stored in the tracked objects.
+    pub fn heap_size(&self) -> usize {
+        self.objects.values().map(Tracked::size).sum()
+    }
+
+    pub fn stats(&self) -> GcStats {
+        GcStats {
+            allocated: self.objects.values().filter(|o| o.is_alive()).count(),
+            collections: self.collections,
+            freed: self.freed,
+        }
+    }
+}
+
+/// Finds the code that can be reached from the values.
+pub fn reachable_code<'a>(values: impl Iterator<Item = &'a Value>) -> ReachableCode {
+    let mut code = ReachableCode::default();
+    mark(values.cloned().collect(), &mut HashSet::new(), &mut code);
+    code
+}
+
+/// Marks all objects that are reachable from the values, and
+/// remembers the code they can run.
+fn mark(mut todo: Vec<Value>, marked: &mut HashSet<usize>, code: &mut ReachableCode) {
+    while let Some(value) = todo.pop() {
+        match &value {
+            Value::Blob(b) => {
+                if marked.insert(value.unique_id()) {
+                    todo.extend(b.borrow().values().cloned());
+                }
+            }
+            Value::List(l) => {
+                if marked.insert(value.unique_id()) {
+                    todo.extend(l.borrow().iter().cloned());
+                }
+            }
+            Value::Set(s) => {
+                if marked.insert(value.unique_id()) {
+                    todo.extend(s.borrow().iter().cloned());
+                }
+            }
+            Value::Dict(d) => {
+                if marked.insert(value.unique_id()) {
+                    for (k, v) in d.borrow().iter() {
+                        todo.push(k.clone());
+                        todo.push(v.clone());
+                    }
+                }
+            }
+            Value::Tuple(t) => {
+                if marked.insert(value.unique_id()) {
+                    todo.extend(t.iter().cloned());
+                }
+            }
+            Value::Variant(_, v) => {
+                todo.push(Value::clone(v));
+            }
+            Value::Function(upvalues, block) => {
+                code.functions.insert(*block);
+                if marked.insert(value.unique_id()) {
+                    for upvalue in upvalues.iter() {
+                        if marked.insert(Rc::as_ptr(upvalue) as usize) {
+                            let upvalue = upvalue.borrow();
+                            if upvalue.is_closed() {
+                                todo.push(upvalue.value.clone());
+                            }
+                        }
+                    }
+                }
+            }
+            Value::Coroutine(c) => {
+                if marked.insert(value.unique_id()) {
+                    let coroutine = c.borrow();
+                    todo.extend(coroutine.stack.iter().cloned());
+                    code.frames
+                        .extend(coroutine.frames.iter().map(|frame| Rc::clone(&frame.block)));
+                    for (_, upvalue) in coroutine.upvalues.iter() {
+                        if marked.insert(Rc::as_ptr(upvalue) as usize) {
+                            todo.push(upvalue.borrow().value.clone());
+                        }
+                    }
+                }
+            }
+            Value::Ty(_)
+            | Value::Range(..)
+            | Value::Float(_)
+            | Value::Int(_)
+            | Value::Bool(_)
+            | Value::String(_)
+            | Value::ExternFunction(_)
+            | Value::Nil => {}
+        }
+    }
+}
@@ -1,6 +1,8 @@
 pub mod block;
+pub mod coroutine;
 pub mod error;
 pub mod flat_value;
+pub mod gc;
 pub mod op;
 pub mod prog;
 pub mod ty;
@@ -11,8 +13,10 @@ use std::borrow::Cow;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-pub use block::{Block, BlockLinkState};
+pub use block::{Block, BlockLinkState, Local};
+pub use coroutine::{Coroutine, CoroutineState};
 pub use error::Error;
+pub use gc::GcStats;
 pub use op::{Op, OpResult};
 pub use prog::BytecodeProg;
 pub use ty::Type;
@@ -23,6 +27,14 @@ pub use value::Value;
 /// [sylt_macro::extern_function].
 pub type RustFunction = fn(RuntimeContext) -> Result<Value, error::RuntimeError>;
 
+/// An external function that can hold state. [RustFunction]s
+/// are turned into these when a program is run.
+pub type RustClosure = Rc<dyn Fn(RuntimeContext) -> Result<Value, error::RuntimeError>>;
+
+/// Gives the value of an `external` definition when the program is linked.
+/// Extern functions the value holds are looked up by name with the argument.
+pub type ExternalValue = Rc<dyn Fn(&dyn Fn(&str) -> Option<Value>) -> Value>;
+
 #[derive(Debug)]
 pub struct Frame {
     pub stack_offset: usize,
@@ -37,6 +49,10 @@ pub trait Machine {
     fn eval_op(&mut self, op: Op) -> Result<OpResult, Error>;
     fn eval_call(&mut self, callable: Value, args: &[&Value]) -> Result<Value, Error>;
     fn args(&self) -> &[String];
+    fn gc_stats(&self) -> GcStats;
+    fn collect_garbage(&mut self);
+    /// Keeps the value alive while something outside of the machine holds it.
+    fn hold(&self, value: &Value);
 }
 
 pub struct RuntimeContext<'m> {
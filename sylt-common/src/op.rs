@@ -1,8 +1,14 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::Value;
+
 /// Ops are operations that the virtual
 /// machine carries out when running the
 /// "byte-code".
 ///
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
 pub enum Op {
     /// This instruction should never be run.
     /// Finding it in a program is a critical error.
@@ -53,6 +59,11 @@ pub enum Op {
     ///
     /// {A, B, C, D, A, E} - Dict(6) - {D(A:E, C:D)}
     Dict(usize),
+    /// Creates a new [Value::Range] from two integers,
+    /// the end is not included in the range.
+    ///
+    /// {A, B} - Range - {A..B}
+    Range,
     /// Creates a new [Value::Variant] with the tag and value as given by the stack.
     /// Note that the tag should be pushed after the value.
     ///
@@ -78,6 +89,20 @@ pub enum Op {
     ///
     /// {T, I, V} - Index - {}
     AssignIndex,
+    /// Readies a container to be looped over, the
+    /// elements are then read with [Op::IterNext].
+    /// Sets are turned into tuples, and dicts into
+    /// tuples of key-value pairs.
+    ///
+    /// {A} - Iter - {A, 0}
+    Iter,
+    /// Pushes the next element of the container
+    /// readied by [Op::Iter], or jumps to the
+    /// given op, after the loop, if there are
+    /// no elements left.
+    ///
+    /// {A, N} - IterNext(after) - {A, N+1, A[N]}
+    IterNext(usize),
     /// Looks up a field by the given name
     /// and replaces the parent with it.
     /// Currently only expects [Value::Blob].
@@ -122,6 +147,43 @@ pub enum Op {
     ///
     /// {A, B} - Div - {A / B}
     Div,
+    /// Takes the remainder of dividing the two top elements
+    /// on the stack, using the function [op::rem]. The result
+    /// has the same sign as B and is then pushed.
+    ///
+    /// {A, B} - Rem - {A % B}
+    Rem,
+    /// Divides the two top elements on the stack and rounds
+    /// towards negative infinity, using the function [op::floor_div].
+    /// The result is then pushed.
+    ///
+    /// {A, B} - FloorDiv - {A div B}
+    FloorDiv,
+    /// Bitwise and of the two top elements on the stack,
+    /// using the function [op::bit_and]. The result is pushed.
+    ///
+    /// {A, B} - BitAnd - {A & B}
+    BitAnd,
+    /// Bitwise or of the two top elements on the stack,
+    /// using the function [op::bit_or]. The result is pushed.
+    ///
+    /// {A, B} - BitOr - {A | B}
+    BitOr,
+    /// Bitwise xor of the two top elements on the stack,
+    /// using the function [op::bit_xor]. The result is pushed.
+    ///
+    /// {A, B} - BitXor - {A ^ B}
+    BitXor,
+    /// Shifts A to the left by B bits,
+    /// using the function [op::shift_left]. The result is pushed.
+    ///
+    /// {A, B} - ShiftLeft - {A << B}
+    ShiftLeft,
+    /// Shifts A to the right by B bits, keeping the sign,
+    /// using the function [op::shift_right]. The result is pushed.
+    ///
+    /// {A, B} - ShiftRight - {A >> B}
+    ShiftRight,
     /// Negates the top element on the stack.
     ///
     /// {A} - Neg - {-A}
@@ -155,6 +217,13 @@ pub enum Op {
     /// {A} - JmpFalse(n) - {}
     JmpFalse(usize),
     /// Sets the instruction pointer
+    /// to the given value, if the
+    /// topmost value is true, also
+    /// pops this value.
+    ///
+    /// {A} - JmpTrue(n) - {}
+    JmpTrue(usize),
+    /// Sets the instruction pointer
     /// to the given value and pops
     /// the given amount of values.
     ///
@@ -243,17 +312,40 @@ pub enum Op {
     /// then replaced with the result.
     ///
     /// Callable things are: [Value::Blob], [Value::Function],
-    /// and [Value::ExternFunction].
+    /// [Value::ExternFunction] and [Value::Coroutine]. Calling
+    /// a coroutine resumes it.
     ///
     /// {F, A, B} - Call(2) - {F(A, B)}
     Call(usize),
 
+    /// Calls "something" like [Op::Call], and
+    /// then returns what it returns. Functions
+    /// reuse the current stackframe, so deep
+    /// recursion doesn't grow the stack.
+    ///
+    /// {F, A, B} - TailCall(2) - {..., F(A, B)}
+    TailCall(usize),
+
     /// Pops the current stackframe and replaces
     /// slot 0 with the top value. Also pops
     /// upvalues.
     ///
     /// {F, A, B} - Return - {..., B}
     Return,
+
+    /// Makes a coroutine that runs the
+    /// function on top of the stack.
+    ///
+    /// {F} - Coroutine - {C}
+    Coroutine,
+
+    /// Suspends the running coroutine, and
+    /// gives the top value to whoever resumed it.
+    /// The value is replaced with the value the
+    /// coroutine is resumed with.
+    ///
+    /// {A} - Yield - {B}
+    Yield,
 }
 
 #[derive(Eq, PartialEq)]
@@ -264,3 +356,215 @@ pub enum OpResult {
     #[doc(hidden)]
     Continue,
 }
+
+// The operators that can be applied to values. An operator returns
+// [Value::Nil] if it can't be applied, e.g. when dividing by zero or
+// when the result doesn't fit in an int.
+
+fn tuple_bin_op(a: &Rc<Vec<Value>>, b: &Rc<Vec<Value>>, f: fn(&Value, &Value) -> Value) -> Value {
+    Value::Tuple(Rc::new(
+        a.iter().zip(b.iter()).map(|(a, b)| f(a, b)).collect(),
+    ))
+}
+
+fn tuple_un_op(a: &Rc<Vec<Value>>, f: fn(&Value) -> Value) -> Value {
+    Value::Tuple(Rc::new(a.iter().map(f).collect()))
+}
+
+pub fn neg(value: &Value) -> Value {
+    match value {
+        Value::Float(a) => Value::Float(-*a),
+        Value::Int(a) => a.checked_neg().map_or(Value::Nil, Value::Int),
+        Value::Tuple(a) => tuple_un_op(a, neg),
+        _ => Value::Nil,
+    }
+}
+
+pub fn not(value: &Value) -> Value {
+    match value {
+        Value::Bool(a) => Value::Bool(!*a),
+        Value::Tuple(a) => tuple_un_op(a, not),
+        _ => Value::Nil,
+    }
+}
+
+pub fn add(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+        (Value::Int(a), Value::Int(b)) => a.checked_add(*b).map_or(Value::Nil, Value::Int),
+        (Value::String(a), Value::String(b)) => Value::String(Rc::from(format!("{}{}", a, b))),
+        (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, add),
+        _ => Value::Nil,
+    }
+}
+
+pub fn sub(a: &Value, b: &Value) -> Value {
+    add(a, &neg(b))
+}
+
+pub fn mul(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => Value::Float(a * b),
+        (Value::Int(a), Value::Int(b)) => a.checked_mul(*b).map_or(Value::Nil, Value::Int),
+        (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, mul),
+        _ => Value::Nil,
+    }
+}
+
+pub fn div(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => Value::Float(a / b),
+        (Value::Int(a), Value::Int(b)) => a.checked_div(*b).map_or(Value::Nil, Value::Int),
+        (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, div),
+        _ => Value::Nil,
+    }
+}
+
+/// The remainder has the same sign as `b`, so `a == b * (a div b) + a % b`.
+///
+/// Unlike `/`, a zero `b` is an error for floats too.
+pub fn rem(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) if *b != 0.0 => Value::Float(a - b * (a / b).floor()),
+        (Value::Int(a), Value::Int(b)) => match a.checked_rem(*b) {
+            Some(r) if r != 0 && (r < 0) != (*b < 0) => Value::Int(r + b),
+            Some(r) => Value::Int(r),
+            None => Value::Nil,
+        },
+        (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, rem),
+        _ => Value::Nil,
+    }
+}
+
+pub fn floor_div(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) if *b != 0.0 => Value::Float((a / b).floor()),
+        (Value::Int(a), Value::Int(b)) => match (a.checked_div(*b), a.checked_rem(*b)) {
+            (Some(q), Some(r)) if r != 0 && (r < 0) != (*b < 0) => Value::Int(q - 1),
+            (Some(q), _) => Value::Int(q),
+            _ => Value::Nil,
+        },
+        (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, floor_div),
+        _ => Value::Nil,
+    }
+}
+
+pub fn bit_and(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Value::Int(a & b),
+        _ => Value::Nil,
+    }
+}
+
+pub fn bit_or(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Value::Int(a | b),
+        _ => Value::Nil,
+    }
+}
+
+pub fn bit_xor(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Value::Int(a ^ b),
+        _ => Value::Nil,
+    }
+}
+
+/// Shifting by a negative amount or by 64 or more bits is an error.
+pub fn shift_left(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) if (0..64).contains(b) => Value::Int(a << b),
+        _ => Value::Nil,
+    }
+}
+
+/// Shifts keep the sign of `a`, so `-8 >> 1 == -4`.
+pub fn shift_right(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) if (0..64).contains(b) => Value::Int(a >> b),
+        _ => Value::Nil,
+    }
+}
+
+pub fn eq(a: &Value, b: &Value) -> Value {
+    eq_blobs(a, b, &mut HashSet::new())
+}
+
+/// Compares the values, `comparing` holds the pairs of blobs that are
+/// already being compared.
+fn eq_blobs(a: &Value, b: &Value, comparing: &mut HashSet<(usize, usize)>) -> Value {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => Value::Bool(a == b),
+        (Value::Int(a), Value::Int(b)) => Value::Bool(a == b),
+        (Value::String(a), Value::String(b)) => Value::Bool(a == b),
+        (Value::Bool(a), Value::Bool(b)) => Value::Bool(a == b),
+        (Value::Tuple(a), Value::Tuple(b)) => Value::Bool(a == b),
+        (Value::Nil, Value::Nil) => Value::Bool(true),
+        (Value::List(a), Value::List(b)) => Value::Bool(a == b),
+        (Value::Set(a), Value::Set(b)) => Value::Bool(a == b),
+        (Value::Dict(a), Value::Dict(b)) => Value::Bool(a == b),
+        (Value::Range(a, b), Value::Range(c, d)) => Value::Bool(a == c && b == d),
+        (Value::Blob(a), Value::Blob(b)) => {
+            // Like the blobs of the Lua backend, blobs with the same fields are
+            // the same. Functions aren't compared, so blobs with them are only
+            // the same as themselves. Blobs that reference themselves lead back
+            // to a pair that is already being compared - if it differs, that
+            // comparison finds it.
+            let pair = (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize);
+            let same = Rc::ptr_eq(a, b) || !comparing.insert(pair) || {
+                let (a, b) = (a.borrow(), b.borrow());
+                a.len() == b.len()
+                    && a.iter().all(|(field, x)| {
+                        matches!(
+                            b.get(field).map(|y| eq_blobs(x, y, comparing)),
+                            Some(Value::Bool(true))
+                        )
+                    })
+            };
+            Value::Bool(same)
+        }
+        (Value::Variant(a, _), Value::Variant(b, _)) if a != b => Value::Bool(false),
+        (Value::Variant(_, a), Value::Variant(_, b)) => eq_blobs(a, b, comparing),
+        _ => Value::Nil,
+    }
+}
+
+pub fn less(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => Value::Bool(a < b),
+        (Value::Float(a), Value::Int(b)) => Value::Bool(*a < (*b as f64)),
+        (Value::Int(a), Value::Float(b)) => Value::Bool((*a as f64) < *b),
+        (Value::Int(a), Value::Int(b)) => Value::Bool(a < b),
+        (Value::String(a), Value::String(b)) => Value::Bool(a < b),
+        (Value::Bool(a), Value::Bool(b)) => Value::Bool(a < b),
+        (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => a
+            .iter()
+            .zip(b.iter())
+            .find_map(|(a, b)| match eq(a, b) {
+                Value::Bool(false) => Some(less(a, b)),
+                _ => None,
+            })
+            .unwrap_or(Value::Bool(false)),
+        _ => Value::Nil,
+    }
+}
+
+pub fn greater(a: &Value, b: &Value) -> Value {
+    less(b, a)
+}
+
+pub fn and(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => Value::Bool(*a && *b),
+        (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, and),
+        _ => Value::Nil,
+    }
+}
+
+pub fn or(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => Value::Bool(*a || *b),
+        (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, or),
+        _ => Value::Nil,
+    }
+}
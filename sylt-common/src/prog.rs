@@ -1,18 +1,269 @@
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use sylt_tokenizer::Span;
 
-use crate::{Block, RustFunction, Value};
+use crate::{Block, Error, ExternalValue, RustFunction, Type, Value};
+
+/// The first bytes of every bytecode file.
+const BYTECODE_MAGIC: &[u8; 4] = b"SYB\0";
+
+/// Bumped every time the layout of bytecode files changes,
+/// files with another version have to be compiled again.
+pub const BYTECODE_VERSION: u32 = 7;
+
+/// A global or a blob, as it was defined in a file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Symbol {
+    pub file: PathBuf,
+    pub name: String,
+    /// Where a global lives on the stack, or the constant that holds a blob.
+    pub slot: usize,
+    /// [Type::Unknown] if the program wasn't typechecked.
+    pub ty: Type,
+    /// The constant a global is defined as, if the definition compiles to
+    /// only that constant - like a function does.
+    pub constant: Option<usize>,
+}
 
 #[derive(Clone)]
 pub struct BytecodeProg {
     pub blocks: Vec<Rc<RefCell<Block>>>,
     pub functions: Vec<RustFunction>,
+    /// The names of the extern functions, in the same order as `functions`.
+    pub function_names: Vec<String>,
     pub constants: Vec<Value>,
     pub strings: Vec<String>,
+    /// Used to tell if a new version of the program can replace this one
+    /// while it runs.
+    pub globals: Vec<Symbol>,
+    pub blobs: Vec<Symbol>,
+    /// The `external` definitions, their slot is the constant
+    /// that holds the value the host gives.
+    pub externals: Vec<Symbol>,
+}
+
+/// What is stored in a bytecode file, after the magic bytes and the version.
+#[derive(Deserialize, Serialize)]
+struct BytecodeFile {
+    /// The extern functions the program calls. [Value::ExternFunction]
+    /// points into this list.
+    externs: Vec<String>,
+    blocks: Vec<Rc<RefCell<Block>>>,
+    constants: Vec<Value>,
+    strings: Vec<String>,
+    globals: Vec<Symbol>,
+    blobs: Vec<Symbol>,
+    /// Linked again when the file is loaded, their constants are nil.
+    externals: Vec<Symbol>,
+}
+
+impl BytecodeProg {
+    /// Writes the program to the bytecode file format.
+    ///
+    /// Function pointers cannot be stored, so only the names
+    /// of the extern functions that are used are written down.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut externs = Vec::new();
+        let mut slots = HashMap::new();
+        let linked: Vec<_> = self
+            .externals
+            .iter()
+            .map(|external| external.slot)
+            .collect();
+        let constants = self
+            .constants
+            .iter()
+            .enumerate()
+            .map(|(i, constant)| match constant {
+                // The externals are linked again when the file is loaded.
+                _ if linked.contains(&i) => Value::Nil,
+                Value::ExternFunction(slot) => {
+                    let slot = *slots.entry(*slot).or_insert_with(|| {
+                        externs.push(self.function_names[*slot].clone());
+                        externs.len() - 1
+                    });
+                    Value::ExternFunction(slot)
+                }
+                constant => constant.clone(),
+            })
+            .collect();
+
+        let file = BytecodeFile {
+            externs,
+            blocks: self.blocks.clone(),
+            constants,
+            strings: self.strings.clone(),
+            globals: self.globals.clone(),
+            blobs: self.blobs.clone(),
+            externals: self.externals.clone(),
+        };
+
+        let mut bytes = BYTECODE_MAGIC.to_vec();
+        bytes.extend_from_slice(&BYTECODE_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, &file).expect("Failed to serialize the program");
+        bytes
+    }
+
+    /// Reads a program written by [BytecodeProg::to_bytes], and links
+    /// the extern functions it uses by name.
+    pub fn from_bytes(
+        path: &Path,
+        bytes: &[u8],
+        functions: &[(String, RustFunction, String)],
+    ) -> Result<Self, Error> {
+        let error = |message: String| Error::BytecodeError { file: path.to_path_buf(), message };
+
+        if bytes.len() < 8 || &bytes[0..4] != BYTECODE_MAGIC {
+            return Err(error("Not a Sylt bytecode file".to_string()));
+        }
+        let mut version = [0; 4];
+        version.copy_from_slice(&bytes[4..8]);
+        let version = u32::from_le_bytes(version);
+        if version != BYTECODE_VERSION {
+            return Err(error(format!(
+                "The file has version {}, but only version {} can be run - compile it again",
+                version, BYTECODE_VERSION
+            )));
+        }
+
+        let file: BytecodeFile = bincode::deserialize(&bytes[8..])
+            .map_err(|e| error(format!("The file is broken: {}", e)))?;
+
+        let functions = file
+            .externs
+            .iter()
+            .map(|name| {
+                functions
+                    .iter()
+                    .find(|(other, _, _)| other == name)
+                    .map(|(_, f, _)| *f)
+                    .ok_or_else(|| Error::MissingExternFunction {
+                        file: path.to_path_buf(),
+                        name: name.clone(),
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            blocks: file.blocks,
+            functions,
+            function_names: file.externs,
+            constants: file.constants,
+            strings: file.strings,
+            globals: file.globals,
+            blobs: file.blobs,
+            externals: file.externals,
+        })
+    }
+
+    /// Gives the `external` definitions their values, which are looked up
+    /// by name. It's an error if one of them is missing. The extern functions
+    /// the values hold are linked from `functions`, if they aren't already.
+    pub fn link_externals(
+        &mut self,
+        functions: &[(String, RustFunction, String)],
+        externals: &[(String, ExternalValue, String)],
+    ) -> Result<(), Vec<Error>> {
+        let linked = RefCell::new((&mut self.functions, &mut self.function_names));
+        let function = |name: &str| {
+            let mut linked = linked.borrow_mut();
+            let (linked, names) = &mut *linked;
+            if let Some(slot) = names.iter().position(|other| other == name) {
+                return Some(Value::ExternFunction(slot));
+            }
+            let (_, f, _) = functions.iter().find(|(other, _, _)| other == name)?;
+            linked.push(*f);
+            names.push(name.to_string());
+            Some(Value::ExternFunction(names.len() - 1))
+        };
+
+        let mut errors = Vec::new();
+        for external in self.externals.iter() {
+            match externals.iter().find(|(name, _, _)| *name == external.name) {
+                Some((_, value, _)) => self.constants[external.slot] = value(&function),
+                None => errors.push(Error::MissingExternal {
+                    file: external.file.clone(),
+                    name: external.name.clone(),
+                }),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Where the lines of a compiled Lua program come from.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    /// The statement each Lua line was written for, the first
+    /// line is at index 0. The lines of the preamble have none.
+    pub lines: Vec<Option<(PathBuf, Span)>>,
+}
+
+impl SourceMap {
+    /// Where the Lua line comes from, lines are counted from 1 like Lua does.
+    pub fn lookup(&self, line: usize) -> Option<&(PathBuf, Span)> {
+        self.lines.get(line.checked_sub(1)?)?.as_ref()
+    }
+
+    /// Replaces every `<chunk>:<line>` in what Lua printed with the Sylt
+    /// file and line it points to. The Sylt positions are also given, in
+    /// the order they were found.
+    pub fn rewrite(&self, chunk: &str, text: &str) -> (String, Vec<(PathBuf, usize)>) {
+        let pattern = format!("{}:", chunk);
+        let mut rewritten = String::new();
+        let mut positions = Vec::new();
+        let mut rest = text;
+        while let Some(at) = rest.find(&pattern) {
+            let after = &rest[at + pattern.len()..];
+            let digits = after
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(after.len());
+            rewritten.push_str(&rest[..at]);
+            let line = after[..digits].parse().ok();
+            match line.and_then(|line| self.lookup(line)) {
+                Some((file, span)) => {
+                    rewritten.push_str(&format!("{}:{}", file.display(), span.line_start));
+                    positions.push((file.clone(), span.line_start));
+                }
+                None => rewritten.push_str(&rest[at..at + pattern.len() + digits]),
+            }
+            rest = &after[digits..];
+        }
+        rewritten.push_str(rest);
+        (rewritten, positions)
+    }
+}
+
+/// One line per Lua line, `<file>:<line>:<column>` or `-` for the
+/// lines that don't come from a statement.
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in self.lines.iter() {
+            match line {
+                Some((file, span)) => writeln!(
+                    f,
+                    "{}:{}:{}",
+                    file.display(),
+                    span.line_start,
+                    span.col_start
+                )?,
+                None => writeln!(f, "-")?,
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
 pub enum Prog {
     Bytecode(BytecodeProg),
-    Lua,
+    Lua(SourceMap),
 }
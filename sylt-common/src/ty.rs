@@ -24,6 +24,7 @@ pub enum Type {
     List(Box<Type>),
     Set(Box<Type>),
     Dict(Box<Type>, Box<Type>),
+    Range,
     Function(Vec<Type>, Box<Type>),
     Blob(String, BTreeMap<String, Type>),
     Enum(String, BTreeMap<String, Type>),
@@ -71,6 +72,7 @@ impl Display for Type {
             Type::List(name) => write!(f, "[{}]", name),
             Type::Set(name) => write!(f, "{{{}}}", name),
             Type::Dict(key, value) => write!(f, "{{{}: {}}}", key, value),
+            Type::Range => write!(f, "range"),
             Type::Function(args, ret) => {
                 write!(f, "(fn ")?;
                 for (i, n) in args.iter().enumerate() {
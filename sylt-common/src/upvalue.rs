@@ -37,4 +37,11 @@ impl UpValue {
         self.slot = 0;
         self.value = value;
     }
+
+    /// Moves the value back to the stack, the opposite of [UpValue::close].
+    /// Returns the value that goes in the slot.
+    pub fn open(&mut self, slot: usize) -> Value {
+        self.slot = slot;
+        std::mem::replace(&mut self.value, Value::Nil)
+    }
 }
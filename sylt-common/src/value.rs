@@ -5,7 +5,7 @@ use std::fmt::{Debug, Display};
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
-use crate::{ty::Type, upvalue::UpValue};
+use crate::{coroutine::Coroutine, ty::Type, upvalue::UpValue};
 
 #[derive(Clone, Deserialize, Serialize)]
 pub enum Value {
@@ -16,6 +16,8 @@ pub enum Value {
     List(Rc<RefCell<Vec<Value>>>),
     Set(Rc<RefCell<HashSet<Value>>>),
     Dict(Rc<RefCell<HashMap<Value, Value>>>),
+    /// The integers from the first number up to, but not including, the second.
+    Range(i64, i64),
     Float(f64),
     Int(i64),
     Bool(bool),
@@ -23,6 +25,10 @@ pub enum Value {
     Function(Rc<Vec<Rc<RefCell<UpValue>>>>, usize),
     ExternFunction(usize),
     Nil,
+    /// Can't be sent, since it holds part of a running program. Comes
+    /// last, so the other values keep their numbers when serialized.
+    #[serde(skip)]
+    Coroutine(Rc<RefCell<Coroutine>>),
 }
 
 impl From<&str> for Value {
@@ -59,6 +65,8 @@ impl PartialEq<Value> for Value {
             (Value::List(a), Value::List(b)) => a == b,
             (Value::Set(a), Value::Set(b)) => a == b,
             (Value::Dict(a), Value::Dict(b)) => a == b,
+            (Value::Range(a, b), Value::Range(c, d)) => a == c && b == d,
+            (Value::Variant(a, x), Value::Variant(b, y)) => a == b && x == y,
             (Value::Nil, Value::Nil) => true,
             _ => false,
         }
@@ -80,6 +88,14 @@ impl Hash for Value {
             Value::Bool(a) => a.hash(state),
             Value::String(a) => a.hash(state),
             Value::Tuple(a) => a.hash(state),
+            Value::Range(a, b) => {
+                a.hash(state);
+                b.hash(state);
+            }
+            Value::Variant(a, b) => {
+                a.hash(state);
+                b.hash(state);
+            }
             Value::Nil => state.write_i8(0),
             _ => {}
         };
@@ -96,6 +112,7 @@ impl Value {
             Value::Ty(ty) => ty as *const _ as usize,
             Value::Float(f) => f as *const _ as usize,
             Value::Int(i) => i as *const _ as usize,
+            Value::Range(i, _) => i as *const _ as usize,
             Value::Bool(b) => b as *const _ as usize,
             Value::Blob(v) => Rc::as_ptr(v) as usize,
             Value::Variant(v, _) => Rc::as_ptr(v) as usize,
@@ -105,6 +122,7 @@ impl Value {
             Value::Dict(v) => Rc::as_ptr(v) as usize,
             Value::Function(v, _) => Rc::as_ptr(v) as usize,
             Value::Tuple(v) => Rc::as_ptr(v) as usize,
+            Value::Coroutine(v) => Rc::as_ptr(v) as usize,
             Value::Nil => 0, // TODO(ed): This is not a valid pointer - right?
             Value::ExternFunction(slot) => slot + 2,
         }
@@ -158,6 +176,7 @@ impl Value {
             }
             Value::Float(f) => write!(fmt, "{:?}", f),
             Value::Int(i) => write!(fmt, "{}", i),
+            Value::Range(a, b) => write!(fmt, "{}..{}", a, b),
             Value::Bool(b) => write!(fmt, "{}", b),
             Value::String(s) => write!(fmt, "\"{}\"", s),
             Value::List(v) => {
@@ -221,6 +240,7 @@ impl Value {
                 write!(fmt, "<fn #{}>", block)
             }
             Value::ExternFunction(slot) => write!(fmt, "<extern fn {}>", slot),
+            Value::Coroutine(_) => write!(fmt, "<coroutine>"),
             Value::Nil => write!(fmt, "nil"),
         }
     }
@@ -1,12 +1,13 @@
 use std::rc::Rc;
 use sylt_common::error::Error;
-use sylt_common::{Block, Op, Value};
+use sylt_common::{Block, Local, Op, Value};
 use sylt_parser::expression::ComparisonKind;
 use sylt_parser::{
-    Assignable, AssignableKind, CaseBranch, Expression, ExpressionKind, Op as ParserOp, Span,
-    Statement, StatementKind, TypeAssignable, TypeAssignableKind,
+    Assignable, AssignableKind, CaseBranch, Expression, ExpressionKind, Op as ParserOp, Pattern,
+    PatternKind, Span, Statement, StatementKind, TypeAssignable, TypeAssignableKind,
 };
 
+use crate::fold::Unfoldable;
 use crate::*;
 
 #[derive(Debug, Copy, Clone)]
@@ -16,6 +17,15 @@ struct LoopFrame {
     stack_size: usize,
 }
 
+/// One step from the value a `case` matches on to a part of it.
+#[derive(Debug, Copy, Clone)]
+enum PatternStep {
+    /// An element of a tuple.
+    Index(usize),
+    /// The value a variant holds.
+    Value,
+}
+
 #[derive(Debug, Copy, Clone)]
 struct BytecodeContext {
     block_slot: BlockID,
@@ -82,6 +92,30 @@ impl<'t> BytecodeCompiler<'t> {
         }
     }
 
+    /// Pops the frame, and stores what the block captures in the block.
+    fn pop_frame_and_block(&mut self, ctx: BytecodeContext) {
+        let upvalues = self.compiler.pop_frame(ctx.into()).upvalues;
+        let block = &mut self.blocks[ctx.block_slot];
+        block.upvalue_names = upvalues.iter().map(|u| u.name.clone()).collect();
+        block.upvalues = upvalues
+            .into_iter()
+            .map(|u| (u.parent, u.upupvalue))
+            .collect();
+    }
+
+    /// Defines a local variable, and remembers where it is for debuggers.
+    fn define(&mut self, name: &str, span: Span, ctx: BytecodeContext) -> VarSlot {
+        let slot = self.compiler.define(name, span);
+        let start = self.next_ip(ctx);
+        self.blocks[ctx.block_slot].locals.push(Local {
+            name: name.to_string(),
+            slot,
+            start,
+            end: None,
+        });
+        slot
+    }
+
     fn emit_pop_until_size(&mut self, ctx: BytecodeContext, span: Span, target_size: usize) {
         let vars: Vec<_> = self.compiler.frames[ctx.frame]
             .variables
@@ -100,6 +134,12 @@ impl<'t> BytecodeCompiler<'t> {
     }
 
     fn pop_until_size(&mut self, ctx: BytecodeContext, span: Span, target_size: usize) {
+        let end = self.next_ip(ctx);
+        for local in self.blocks[ctx.block_slot].locals.iter_mut() {
+            if local.slot >= target_size && local.end.is_none() {
+                local.end = Some(end);
+            }
+        }
         self.emit_pop_until_size(ctx, span, target_size);
         self.compiler.frames[ctx.frame]
             .variables
@@ -161,6 +201,35 @@ impl<'t> BytecodeCompiler<'t> {
         None
     }
 
+    /// Compiles a call that is returned right away to a [Op::TailCall],
+    /// which also returns. Returns false if the expression isn't a call.
+    fn tail_call(&mut self, expression: &Expression, ctx: BytecodeContext) -> bool {
+        let ass = match &expression.kind {
+            ExpressionKind::Get(ass) => ass,
+            _ => return false,
+        };
+        match &ass.kind {
+            AssignableKind::Call(f, expr) => {
+                self.assignable(f, ctx);
+                for expr in expr.iter() {
+                    self.expression(expr, ctx);
+                }
+                self.add_op(ctx, ass.span, Op::TailCall(expr.len()));
+            }
+            AssignableKind::ArrowCall(pre, f, expr) => {
+                self.expression(pre, ctx);
+                self.assignable(f, ctx);
+                self.add_op(ctx, ass.span, Op::Swap);
+                for expr in expr.iter() {
+                    self.expression(expr, ctx);
+                }
+                self.add_op(ctx, ass.span, Op::TailCall(expr.len() + 1));
+            }
+            _ => return false,
+        }
+        true
+    }
+
     fn type_assignable(&mut self, ass: &TypeAssignable, ctx: BytecodeContext) -> Option<usize> {
         use TypeAssignableKind::*;
 
@@ -208,6 +277,19 @@ impl<'t> BytecodeCompiler<'t> {
         use ComparisonKind::*;
         use ExpressionKind::*;
 
+        match self.compiler.fold(expression, ctx.into()) {
+            Ok(value) => {
+                let op = self.compiler.constant(value);
+                self.add_op(ctx, expression.span, op);
+                return;
+            }
+            Err(Unfoldable::Error(span, message)) => {
+                error!(self.compiler, span, "{}", message);
+                return;
+            }
+            Err(Unfoldable::NotConstant) => {}
+        }
+
         match &expression.kind {
             Get(a) => {
                 self.assignable(a, ctx);
@@ -217,6 +299,13 @@ impl<'t> BytecodeCompiler<'t> {
             Sub(a, b) => self.bin_op(a, b, &[Op::Sub], expression.span, ctx),
             Mul(a, b) => self.bin_op(a, b, &[Op::Mul], expression.span, ctx),
             Div(a, b) => self.bin_op(a, b, &[Op::Div], expression.span, ctx),
+            Rem(a, b) => self.bin_op(a, b, &[Op::Rem], expression.span, ctx),
+            FloorDiv(a, b) => self.bin_op(a, b, &[Op::FloorDiv], expression.span, ctx),
+            BitAnd(a, b) => self.bin_op(a, b, &[Op::BitAnd], expression.span, ctx),
+            BitOr(a, b) => self.bin_op(a, b, &[Op::BitOr], expression.span, ctx),
+            BitXor(a, b) => self.bin_op(a, b, &[Op::BitXor], expression.span, ctx),
+            ShiftLeft(a, b) => self.bin_op(a, b, &[Op::ShiftLeft], expression.span, ctx),
+            ShiftRight(a, b) => self.bin_op(a, b, &[Op::ShiftRight], expression.span, ctx),
 
             Comparison(a, cmp, b) => match cmp {
                 Equals => self.bin_op(a, b, &[Op::Equal], expression.span, ctx),
@@ -261,6 +350,34 @@ impl<'t> BytecodeCompiler<'t> {
             }
             Not(a) => self.un_op(a, &[Op::Not], expression.span, ctx),
 
+            Yield(a) => self.un_op(a, &[Op::Yield], expression.span, ctx),
+            Coroutine(a) => self.un_op(a, &[Op::Coroutine], expression.span, ctx),
+            ToStr(a) => match self.compiler.functions.get("as_str") {
+                Some((slot, _, _)) => {
+                    let as_str = self.compiler.constant(Value::ExternFunction(*slot));
+                    self.add_op(ctx, expression.span, as_str);
+                    self.un_op(a, &[Op::Call(1)], expression.span, ctx);
+                }
+                None => {
+                    error!(
+                        self.compiler,
+                        expression.span,
+                        "Strings can only be interpolated with 'as_str' from the standard library"
+                    );
+                }
+            },
+
+            Range { start, end, inclusive } => {
+                self.expression(start, ctx);
+                self.expression(end, ctx);
+                if *inclusive {
+                    let one = self.compiler.constant(Value::Int(1));
+                    self.add_op(ctx, expression.span, one);
+                    self.add_op(ctx, expression.span, Op::Add);
+                }
+                self.add_op(ctx, expression.span, Op::Range);
+            }
+
             Parenthesis(expr) => self.expression(expr, ctx),
 
             IfExpression { condition, pass, fail } => {
@@ -285,25 +402,19 @@ impl<'t> BytecodeCompiler<'t> {
                 // === Frame begin ===
                 let inner_ctx = self.push_frame_and_block(ctx, &name, expression.span);
                 for (ident, _) in params.iter() {
-                    let param = self.compiler.define(&ident.name, ident.span);
+                    let param = self.define(&ident.name, ident.span, inner_ctx);
                     self.compiler.activate(param);
                 }
 
                 self.statement(&body, inner_ctx);
 
-                if !all_paths_return(&body) {
-                    let nil = self.compiler.constant(Value::Nil);
-                    self.add_op(inner_ctx, body.span, nil);
-                    self.add_op(inner_ctx, body.span, Op::Return);
-                }
+                // The typechecker makes sure only functions that return
+                // nil can reach the end.
+                let nil = self.compiler.constant(Value::Nil);
+                self.add_op(inner_ctx, body.span, nil);
+                self.add_op(inner_ctx, body.span, Op::Return);
 
-                self.blocks[inner_ctx.block_slot].upvalues = self
-                    .compiler
-                    .pop_frame(inner_ctx.into())
-                    .upvalues
-                    .into_iter()
-                    .map(|u| (u.parent, u.upupvalue))
-                    .collect();
+                self.pop_frame_and_block(inner_ctx);
                 let function = Value::Function(Rc::new(Vec::new()), inner_ctx.block_slot);
                 // === Frame end ===
 
@@ -321,7 +432,7 @@ impl<'t> BytecodeCompiler<'t> {
 
                 // Set self to nil so that we can capture it.
                 self.push(Value::Nil, expression.span, inner_ctx);
-                let slot = self.compiler.define("self", expression.span);
+                let slot = self.define("self", expression.span, inner_ctx);
                 self.compiler.activate(slot);
 
                 // Initialize the blob. This may capture self.
@@ -338,13 +449,7 @@ impl<'t> BytecodeCompiler<'t> {
                 self.read_identifier("self", expression.span, inner_ctx, inner_ctx.namespace);
                 self.add_op(inner_ctx, expression.span, Op::Return);
 
-                self.blocks[inner_ctx.block_slot].upvalues = self
-                    .compiler
-                    .pop_frame(inner_ctx.into())
-                    .upvalues
-                    .into_iter()
-                    .map(|u| (u.parent, u.upupvalue))
-                    .collect();
+                self.pop_frame_and_block(inner_ctx);
                 let function = Value::Function(Rc::new(Vec::new()), inner_ctx.block_slot);
 
                 // Call the closure.
@@ -401,11 +506,9 @@ impl<'t> BytecodeCompiler<'t> {
                     let op = Op::ReadGlobal(*slot);
                     self.add_op(ctx, span, op);
                 }
-                Some(Name::External) => {
-                    error!(
-                        self.compiler,
-                        span, "External values aren't allowed when compiling to byte-code "
-                    );
+                Some(Name::External(slot)) => {
+                    let op = Op::ReadGlobal(*slot);
+                    self.add_op(ctx, span, op);
                 }
                 Some(Name::Blob(blob)) => {
                     let op = Op::Constant(*blob);
@@ -436,6 +539,17 @@ impl<'t> BytecodeCompiler<'t> {
         None
     }
 
+    /// Remembers that the global is defined as the constant, so reloading
+    /// the program can replace it.
+    fn global_constant(&mut self, name: &str, namespace: usize, constant: usize) {
+        if let Some(Name::Global(slot)) = self.compiler.namespaces[namespace].get(name) {
+            let slot = *slot;
+            if let Some(global) = self.compiler.globals.iter_mut().find(|g| g.slot == slot) {
+                global.constant = Some(constant);
+            }
+        }
+    }
+
     fn set_identifier(&mut self, name: &str, span: Span, ctx: BytecodeContext, namespace: usize) {
         match self.compiler.resolve_and_capture(name, ctx.frame, span) {
             Ok(Lookup::Upvalue(up)) => {
@@ -470,6 +584,123 @@ impl<'t> BytecodeCompiler<'t> {
         }
     }
 
+    /// Reads the part of the value in `slot` that `path` leads to.
+    fn read_pattern_path(
+        &mut self,
+        slot: VarSlot,
+        path: &[PatternStep],
+        span: Span,
+        ctx: BytecodeContext,
+    ) {
+        self.add_op(ctx, span, Op::ReadLocal(slot));
+        for step in path {
+            match step {
+                PatternStep::Index(index) => {
+                    self.add_op(ctx, span, Op::GetConstIndex(*index as i64));
+                }
+                PatternStep::Value => {
+                    self.add_op(ctx, span, Op::TagSplit);
+                    self.add_op(ctx, span, Op::Pop);
+                }
+            }
+        }
+    }
+
+    /// Emits the checks for a pattern. Every check leaves a boolean on
+    /// the stack followed by a jump that has to be patched to where the
+    /// next branch starts, these jumps are added to `next_jumps`.
+    fn pattern_test(
+        &mut self,
+        pattern: &Pattern,
+        slot: VarSlot,
+        path: &mut Vec<PatternStep>,
+        next_jumps: &mut Vec<usize>,
+        ctx: BytecodeContext,
+    ) {
+        let span = pattern.span;
+        let literal = match &pattern.kind {
+            PatternKind::Wildcard | PatternKind::Bind(_) => None,
+            PatternKind::Int(i) => Some(Value::Int(*i)),
+            PatternKind::Str(s) => Some(Value::String(Rc::new(s.clone()))),
+            PatternKind::Bool(b) => Some(Value::Bool(*b)),
+            PatternKind::Tuple(patterns) => {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    path.push(PatternStep::Index(i));
+                    self.pattern_test(pattern, slot, path, next_jumps, ctx);
+                    path.pop();
+                }
+                None
+            }
+            PatternKind::Variant(variant, value) => {
+                self.read_pattern_path(slot, path, span, ctx);
+                self.add_op(ctx, span, Op::TagSplit);
+                self.add_op(ctx, span, Op::Swap);
+                self.add_op(ctx, span, Op::Pop);
+                self.push(variant.name.as_str().into(), span, ctx);
+                self.add_op(ctx, span, Op::Equal);
+                next_jumps.push(self.add_op(ctx, span, Op::Illegal));
+
+                if let Some(value) = value {
+                    path.push(PatternStep::Value);
+                    self.pattern_test(value, slot, path, next_jumps, ctx);
+                    path.pop();
+                }
+                None
+            }
+            PatternKind::As(pattern, _) => {
+                self.pattern_test(pattern, slot, path, next_jumps, ctx);
+                None
+            }
+        };
+
+        if let Some(literal) = literal {
+            self.read_pattern_path(slot, path, span, ctx);
+            self.push(literal, span, ctx);
+            self.add_op(ctx, span, Op::Equal);
+            next_jumps.push(self.add_op(ctx, span, Op::Illegal));
+        }
+    }
+
+    /// Defines the variables bound by a pattern that has matched.
+    fn pattern_bind(
+        &mut self,
+        pattern: &Pattern,
+        slot: VarSlot,
+        path: &mut Vec<PatternStep>,
+        ctx: BytecodeContext,
+    ) {
+        match &pattern.kind {
+            PatternKind::Wildcard
+            | PatternKind::Int(_)
+            | PatternKind::Str(_)
+            | PatternKind::Bool(_)
+            | PatternKind::Variant(_, None) => {}
+            PatternKind::Bind(ident) => {
+                self.read_pattern_path(slot, path, ident.span, ctx);
+                let var = self.define(&ident.name, ident.span, ctx);
+                self.compiler.activate(var);
+            }
+            PatternKind::Tuple(patterns) => {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    path.push(PatternStep::Index(i));
+                    self.pattern_bind(pattern, slot, path, ctx);
+                    path.pop();
+                }
+            }
+            PatternKind::Variant(_, Some(value)) => {
+                path.push(PatternStep::Value);
+                self.pattern_bind(value, slot, path, ctx);
+                path.pop();
+            }
+            PatternKind::As(pattern, ident) => {
+                self.pattern_bind(pattern, slot, path, ctx);
+                self.read_pattern_path(slot, path, ident.span, ctx);
+                let var = self.define(&ident.name, ident.span, ctx);
+                self.compiler.activate(var);
+            }
+        }
+    }
+
     fn statement(&mut self, statement: &Statement, ctx: BytecodeContext) {
         use StatementKind::*;
         self.compiler.panic = false;
@@ -483,25 +714,42 @@ impl<'t> BytecodeCompiler<'t> {
             | EmptyStatement => {}
 
             #[rustfmt::skip]
-            Definition { ident, value, .. } => {
+            Definition { ident, kind, value, .. } => {
+                if ctx.frame == 0 {
+                    self.compiler
+                        .define_constant_global(ident, *kind, value, ctx.namespace);
+                }
+                let start = self.next_ip(ctx);
                 self.expression(value, ctx);
 
                 if ctx.frame == 0 {
                     // Global
+                    if let [Op::Constant(constant)] = self.blocks[ctx.block_slot].ops[start..] {
+                        self.global_constant(&ident.name, ctx.namespace, constant);
+                    }
                     self.set_identifier(&ident.name, statement.span, ctx, ctx.namespace);
                 } else {
                     // Local variable
-                    let slot = self.compiler.define(&ident.name, statement.span);
+                    let slot = self.define(&ident.name, statement.span, ctx);
                     self.compiler.activate(slot);
                 }
             }
 
-            ExternalDefinition { .. } => {
-                // TODO(ed): Should they be? Is this how we should type the standard library?
-                error!(
-                    self.compiler,
-                    statement.span, "External values aren't allowed when compiling to byte-code "
-                );
+            ExternalDefinition { ident, .. } => {
+                let slot = match self.compiler.namespaces[ctx.namespace].get(&ident.name) {
+                    Some(Name::External(slot)) if ctx.frame == 0 => *slot,
+                    _ => {
+                        error!(
+                            self.compiler,
+                            statement.span, "External definitions must lie in the outmost scope"
+                        );
+                        return;
+                    }
+                };
+                // The host gives the value when the program is linked.
+                let constant = self.compiler.external(&ident.name, ctx.namespace, slot);
+                self.add_op(ctx, statement.span, Op::Constant(constant));
+                self.add_op(ctx, statement.span, Op::AssignGlobal(slot));
             }
 
             #[rustfmt::skip]
@@ -634,43 +882,105 @@ impl<'t> BytecodeCompiler<'t> {
                 self.patch(ctx, break_from, Op::Jmp(out));
             }
 
+            LoopIn { pattern, iterable, body } => {
+                // The container and how far we've come are hidden variables.
+                //        .. iterable ..
+                //        Iter
+                //  start: IterNext(break)
+                //        Jmp(over)
+                //  break: Jmp(end)
+                //   over: .. loop ..
+                //         Jmp(start)
+                //    end: Pop, Pop
+                let outer_size = self.compiler.frames[ctx.frame].variables.len();
+                self.expression(iterable, ctx);
+                self.add_op(ctx, iterable.span, Op::Iter);
+                let iterable_slot = self.define("/iterable/", iterable.span, ctx);
+                self.compiler.activate(iterable_slot);
+                let index_slot = self.define("/index/", iterable.span, ctx);
+                self.compiler.activate(index_slot);
+                let stack_size = self.compiler.frames[ctx.frame].variables.len();
+
+                let start = self.add_op(ctx, iterable.span, Op::Illegal);
+                self.add_op(ctx, iterable.span, Op::Jmp(start + 3));
+                let break_from = self.add_op(ctx, iterable.span, Op::Illegal);
+                self.patch(ctx, start, Op::IterNext(break_from));
+
+                let element_slot = self.define("/element/", pattern.span, ctx);
+                self.compiler.activate(element_slot);
+                self.pattern_bind(pattern, element_slot, &mut Vec::new(), ctx);
+
+                self.loops.push(LoopFrame {
+                    continue_addr: start,
+                    break_addr: break_from,
+                    stack_size,
+                });
+                self.statement(body, ctx);
+                self.loops.pop();
+
+                self.pop_until_size(ctx, body.span, stack_size);
+                self.add_op(ctx, body.span, Op::Jmp(start));
+                let out = self.next_ip(ctx);
+                self.patch(ctx, break_from, Op::Jmp(out));
+                self.pop_until_size(ctx, statement.span, outer_size);
+            }
+
             Case { to_match, branches, fall_through } => {
                 self.expression(to_match, ctx);
-                self.add_op(ctx, to_match.span, Op::TagSplit);
+                // The value we match on lives in a hidden variable, so the
+                // patterns can look at it as many times as they want.
+                let outer_size = self.compiler.frames[ctx.frame].variables.len();
+                let to_match_slot = self.define("/case/", to_match.span, ctx);
+                self.compiler.activate(to_match_slot);
+                let size = self.compiler.frames[ctx.frame].variables.len();
 
                 let mut out_jumps = Vec::new();
-                for CaseBranch { pattern: Identifier { name, span }, variable, body } in
-                    branches.iter()
-                {
-                    let tag = self.compiler.constant(name.as_str().into());
-                    self.add_op(ctx, *span, Op::Copy(1));
-                    self.add_op(ctx, *span, tag);
-                    self.add_op(ctx, *span, Op::Equal);
-                    let next_jump = self.add_op(ctx, *span, Op::Illegal);
-                    // Pop original tag
-                    self.add_op(ctx, *span, Op::Pop);
-                    if let Some(Identifier { name, span }) = &variable {
-                        let slot = self.compiler.define(name, *span);
-                        self.compiler.activate(slot);
-
-                        self.statement(body, ctx);
-
-                        self.compiler.frames.last_mut().unwrap().variables.pop();
-                    } else {
-                        self.statement(body, ctx);
+                for CaseBranch { pattern, guard, body } in branches.iter() {
+                    let span = pattern.span;
+                    let mut next_jumps = Vec::new();
+                    self.pattern_test(
+                        pattern,
+                        to_match_slot,
+                        &mut Vec::new(),
+                        &mut next_jumps,
+                        ctx,
+                    );
+                    self.pattern_bind(pattern, to_match_slot, &mut Vec::new(), ctx);
+
+                    let guard_jump = guard.as_ref().map(|guard| {
+                        self.expression(guard, ctx);
+                        self.add_op(ctx, guard.span, Op::Illegal)
+                    });
+
+                    self.statement(body, ctx);
+                    self.emit_pop_until_size(ctx, span, size);
+                    out_jumps.push(self.add_op(ctx, span, Op::Illegal));
+
+                    if let Some(guard_jump) = guard_jump {
+                        let guard_failed = self.next_ip(ctx);
+                        self.patch(ctx, guard_jump, Op::JmpFalse(guard_failed));
+                        self.emit_pop_until_size(ctx, span, size);
                     }
-                    // Pop value
-                    self.add_op(ctx, *span, Op::Pop);
-                    out_jumps.push(self.add_op(ctx, *span, Op::Illegal));
+                    self.compiler.frames[ctx.frame].variables.truncate(size);
+
                     let next = self.next_ip(ctx);
-                    self.patch(ctx, next_jump, Op::JmpFalse(next));
+                    for jump in next_jumps {
+                        self.patch(ctx, jump, Op::JmpFalse(next));
+                    }
+                }
+                match fall_through {
+                    Some(fall_through) => self.statement(fall_through, ctx),
+                    // The typechecker makes sure we never get here.
+                    None => {
+                        self.add_op(ctx, statement.span, Op::Unreachable);
+                    }
                 }
-                self.statement(fall_through, ctx);
 
                 let out = self.next_ip(ctx);
                 for jmp in out_jumps {
                     self.patch(ctx, jmp, Op::Jmp(out));
                 }
+                self.pop_until_size(ctx, statement.span, outer_size);
             }
 
             If { condition, pass, fail } => {
@@ -718,8 +1028,10 @@ impl<'t> BytecodeCompiler<'t> {
             }
 
             Ret { value } => {
-                self.expression(value, ctx);
-                self.add_op(ctx, statement.span, Op::Return);
+                if !self.tail_call(value, ctx) {
+                    self.expression(value, ctx);
+                    self.add_op(ctx, statement.span, Op::Return);
+                }
             }
         }
     }
@@ -758,36 +1070,19 @@ impl<'t> BytecodeCompiler<'t> {
         self.add_op(ctx, span, nil);
         self.add_op(ctx, span, Op::Return);
 
-        self.compiler.pop_frame(ctx.into());
-    }
-}
-
-// TODO(ed): This should move to the typechecker - since we always want this check.
-fn all_paths_return(statement: &Statement) -> bool {
-    match &statement.kind {
-        StatementKind::Assignment { .. }
-        | StatementKind::Blob { .. }
-        | StatementKind::Enum { .. }
-        | StatementKind::Break
-        | StatementKind::Continue
-        | StatementKind::Definition { .. }
-        | StatementKind::EmptyStatement
-        | StatementKind::ExternalDefinition { .. }
-        | StatementKind::IsCheck { .. }
-        | StatementKind::StatementExpression { .. }
-        | StatementKind::Unreachable
-        | StatementKind::FromUse { .. }
-        | StatementKind::Use { .. } => false,
-
-        StatementKind::If { pass, fail, .. } => all_paths_return(pass) && all_paths_return(fail),
-
-        StatementKind::Case { branches, fall_through, .. } => {
-            branches.iter().all(|b| all_paths_return(&b.body)) && all_paths_return(fall_through)
-        }
-
-        StatementKind::Loop { body, .. } => all_paths_return(body),
-        StatementKind::Block { statements } => statements.iter().any(all_paths_return),
+        // The globals live in the stackframe of the preamble.
+        let globals = self.compiler.frames[ctx.frame]
+            .variables
+            .iter()
+            .map(|var| Local {
+                name: var.name.clone(),
+                slot: var.slot,
+                start: 0,
+                end: None,
+            })
+            .collect();
+        self.blocks[ctx.block_slot].locals = globals;
 
-        StatementKind::Ret { .. } => true,
+        self.compiler.pop_frame(ctx.into());
     }
 }
@@ -4,19 +4,40 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use sylt_common::error::Error;
-use sylt_common::prog::{BytecodeProg, Prog};
-use sylt_common::{Op, RustFunction, Type, Value};
+use sylt_common::prog::{BytecodeProg, Prog, SourceMap, Symbol};
+use sylt_common::{ExternalValue, Op, RustFunction, Type, Value};
 use sylt_parser::statement::NameIdentifier;
 use sylt_parser::{Identifier, Span, StatementKind, Type as ParserType, AST};
 
 mod bytecode;
 mod dependency;
+mod exhaustiveness;
+mod fold;
 mod lua;
+mod peephole;
 mod ty;
 mod typechecker;
 
+pub use lua::{LUA_RUNTIME, LUA_RUNTIME_AS_MODULE, LUA_RUNTIME_MODULE};
+
 type VarSlot = usize;
 
+/// How a program is compiled.
+#[derive(Default)]
+pub struct CompileOptions {
+    /// Checks the types before compiling.
+    pub typecheck: bool,
+    /// Fails the compilation if there are warnings.
+    pub deny_warnings: bool,
+    /// Optimizes the bytecode.
+    pub optimize: bool,
+    /// Compiles to Lua, which is written here, instead of to bytecode.
+    pub lua_file: Option<Box<dyn Write>>,
+    /// Writes the Lua as a module that requires [LUA_RUNTIME_MODULE]
+    /// and returns the globals of the main file, instead of calling `start`.
+    pub lua_module: bool,
+}
+
 #[derive(Debug, Clone)]
 struct Variable {
     name: String,
@@ -83,7 +104,7 @@ type BlobID = usize;
 type BlockID = usize;
 #[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 enum Name {
-    External,
+    External(ConstantID),
     Global(ConstantID),
     Blob(BlobID),
     Enum(BlobID),
@@ -119,12 +140,22 @@ struct Compiler {
 
     frames: Vec<Frame>,
     functions: HashMap<String, (usize, RustFunction, ParserType)>,
+    /// The types of the external values the host supplies.
+    external_types: HashMap<String, ParserType>,
 
     panic: bool,
     errors: Vec<Error>,
 
     strings: Vec<String>,
     constants: Vec<Value>,
+    /// The globals defined with `::` that are known at compile time.
+    constant_globals: HashMap<ConstantID, Value>,
+
+    /// What is defined in the files, given to the program
+    /// so it can be reloaded.
+    globals: Vec<Symbol>,
+    blobs: Vec<Symbol>,
+    externals: Vec<Symbol>,
 
     values: HashMap<Value, usize>,
 }
@@ -162,12 +193,18 @@ impl Compiler {
 
             frames: Vec::new(),
             functions: HashMap::new(),
+            external_types: HashMap::new(),
 
             panic: false,
             errors: Vec::new(),
 
             strings: Vec::new(),
             constants: Vec::new(),
+            constant_globals: HashMap::new(),
+
+            globals: Vec::new(),
+            blobs: Vec::new(),
+            externals: Vec::new(),
 
             values: HashMap::new(),
         }
@@ -211,6 +248,30 @@ impl Compiler {
         Op::Constant(slot)
     }
 
+    /// Makes room for the value of an `external` definition, which is
+    /// filled in when the program is linked.
+    fn external(&mut self, name: &str, namespace: NamespaceID, slot: VarSlot) -> ConstantID {
+        let file = self.file_from_namespace(namespace).to_path_buf();
+        // Not shared with other constants, since it's replaced.
+        self.constants.push(Value::Nil);
+        let constant = self.constants.len() - 1;
+        let ty = match self.globals.iter_mut().find(|global| global.slot == slot) {
+            Some(global) => {
+                global.constant = Some(constant);
+                global.ty.clone()
+            }
+            None => Type::Unknown,
+        };
+        self.externals.push(Symbol {
+            file,
+            name: name.to_string(),
+            slot: constant,
+            ty,
+            constant: None,
+        });
+        constant
+    }
+
     fn resolve_and_capture(&mut self, name: &str, frame: usize, span: Span) -> Result<Lookup, ()> {
         // Frame 0 has globals which cannot be captured.
         if frame == 0 {
@@ -233,7 +294,11 @@ impl Compiler {
 
         let up = match self.resolve_and_capture(name, frame - 1, span) {
             Ok(Lookup::Upvalue(up)) => Upvalue::loft(&up),
-            Ok(Lookup::Variable(var)) => Upvalue::capture(&var),
+            Ok(Lookup::Variable(var)) => {
+                // The variable has to be closed when it goes out of scope.
+                self.frames[frame - 1].variables[var.slot].captured = true;
+                Upvalue::capture(&var)
+            }
             _ => {
                 return Err(());
             }
@@ -264,11 +329,12 @@ impl Compiler {
 
     fn compile(
         mut self,
-        typecheck: bool,
-        lua_file: Option<Box<dyn Write>>,
+        options: CompileOptions,
+        mut warnings: Vec<Error>,
         tree: AST,
         functions: &[(String, RustFunction, String)],
-    ) -> Result<Prog, Vec<Error>> {
+        externals: &[(String, ExternalValue, String)],
+    ) -> Result<(Prog, Vec<Error>), Vec<Error>> {
         assert!(!tree.modules.is_empty(), "Cannot compile an empty program");
         let name = "/preamble/";
         let start_span = tree.modules[0].1.span;
@@ -290,8 +356,14 @@ impl Compiler {
             self.functions.len(),
             num_functions
         );
+        self.external_types = externals
+            .iter()
+            .map(|(name, _, sig)| (name.clone(), parse_signature(name, sig)))
+            .collect();
 
-        let statements = match dependency::initialization_order(&tree, &self) {
+        let (statements, mut dependency_warnings) = dependency::initialization_order(&tree, &self);
+        warnings.append(&mut dependency_warnings);
+        let statements = match statements {
             Ok(statements) => statements,
             Err(statements) => {
                 statements.iter().for_each(|(statement, _)| {
@@ -304,26 +376,56 @@ impl Compiler {
             return Err(self.errors);
         }
 
-        if typecheck {
-            typechecker::solve(&statements, &self.namespace_id_to_path, &self.functions)?;
+        if options.typecheck {
+            let (mut typecheck_warnings, types) = typechecker::solve(
+                &statements,
+                &self.namespace_id_to_path,
+                &self.functions,
+                &self.external_types,
+                !options.lua_module,
+            )?;
+            warnings.append(&mut typecheck_warnings);
+
+            let namespaces: HashMap<_, _> = self
+                .namespace_id_to_path
+                .iter()
+                .map(|(namespace, path)| (path.clone(), *namespace))
+                .collect();
+            for symbol in self.globals.iter_mut().chain(self.blobs.iter_mut()) {
+                let key = (namespaces[&symbol.file], symbol.name.clone());
+                if let Some(ty) = types.get(&key) {
+                    symbol.ty = ty.clone();
+                }
+            }
         }
 
-        if let Some(lua_file) = lua_file {
-            let mut lua_compiler = lua::LuaCompiler::new(&mut self, Box::new(lua_file));
+        warnings.retain(|warning| !tree.is_allowed(warning));
+        if options.deny_warnings && !warnings.is_empty() {
+            return Err(warnings);
+        }
+
+        if let Some(lua_file) = options.lua_file {
+            let mut lua_compiler =
+                lua::LuaCompiler::new(&mut self, Box::new(lua_file), options.lua_module);
 
             lua_compiler.preamble(Span::zero(0), 0);
             for (statement, namespace) in statements.iter() {
                 lua_compiler.compile(statement, *namespace);
             }
             lua_compiler.postamble(Span::zero(0));
+            let lines = lua_compiler.finish();
 
             if !self.errors.is_empty() {
                 return Err(self.errors);
             }
 
-            Ok(Prog::Lua)
+            let lines = lines
+                .into_iter()
+                .map(|span| span.map(|span| (self.file_from_namespace(span.file_id).into(), span)))
+                .collect();
+            Ok((Prog::Lua(SourceMap { lines }), warnings))
         } else {
-            let blocks = {
+            let mut blocks = {
                 let mut bytecode_compiler = bytecode::BytecodeCompiler::new(&mut self);
                 bytecode_compiler.preamble(start_span, num_constants);
 
@@ -334,20 +436,29 @@ impl Compiler {
                 bytecode_compiler.postamble(start_span);
                 bytecode_compiler.blocks
             };
+            if options.optimize {
+                blocks.iter_mut().for_each(peephole::optimize);
+            }
 
             if !self.errors.is_empty() {
                 return Err(self.errors);
             }
 
-            Ok(Prog::Bytecode(BytecodeProg {
+            let mut prog = BytecodeProg {
                 blocks: blocks
                     .into_iter()
                     .map(|x| Rc::new(RefCell::new(x)))
                     .collect(),
                 functions: functions.iter().map(|(_, f, _)| *f).collect(),
+                function_names: functions.iter().map(|(name, _, _)| name.clone()).collect(),
                 constants: self.constants,
                 strings: self.strings,
-            }))
+                globals: self.globals,
+                blobs: self.blobs,
+                externals: self.externals,
+            };
+            prog.link_externals(functions, externals)?;
+            Ok((Prog::Bytecode(prog), warnings))
         }
     }
 
@@ -384,6 +495,13 @@ impl Compiler {
                         let blob =
                             self.constant(Value::Ty(Type::Blob(name.clone(), Default::default())));
                         if let Op::Constant(slot) = blob {
+                            self.blobs.push(Symbol {
+                                file: path.clone(),
+                                name: name.clone(),
+                                slot,
+                                ty: Type::Unknown,
+                                constant: None,
+                            });
                             (Name::Blob(slot), name.clone(), statement.span)
                         } else {
                             unreachable!()
@@ -414,13 +532,27 @@ impl Compiler {
                         let var = self.define(name, statement.span);
                         self.activate(var);
                         num_constants += 1;
+                        self.globals.push(Symbol {
+                            file: path.clone(),
+                            name: name.clone(),
+                            slot: var,
+                            ty: Type::Unknown,
+                            constant: None,
+                        });
                         (Name::Global(var), name.clone(), statement.span)
                     }
                     ExternalDefinition { ident: Identifier { name, .. }, .. } => {
                         let var = self.define(name, statement.span);
                         self.activate(var);
                         num_constants += 1;
-                        (Name::External, name.clone(), statement.span)
+                        self.globals.push(Symbol {
+                            file: path.clone(),
+                            name: name.clone(),
+                            slot: var,
+                            ty: Type::Unknown,
+                            constant: None,
+                        });
+                        (Name::External(var), name.clone(), statement.span)
                     }
 
                     // Handled later since we need type information.
@@ -502,11 +634,20 @@ fn parse_signature(func_name: &str, sig: &str) -> ParserType {
     }
 }
 
+/// Compiles the program, as the [CompileOptions] say.
+///
+/// The `warnings` from parsing are returned together with the warnings
+/// found here, next to the program. They are returned as errors if
+/// `deny_warnings` is set.
+///
+/// The `externals` give the values of the `external` definitions
+/// when compiling to bytecode, Lua reads them from its globals.
 pub fn compile(
-    typecheck: bool,
-    lua_file: Option<Box<dyn Write>>,
+    options: CompileOptions,
+    warnings: Vec<Error>,
     prog: AST,
     functions: &[(String, RustFunction, String)],
-) -> Result<Prog, Vec<Error>> {
-    Compiler::new().compile(typecheck, lua_file, prog, functions)
+    externals: &[(String, ExternalValue, String)],
+) -> Result<(Prog, Vec<Error>), Vec<Error>> {
+    Compiler::new().compile(options, warnings, prog, functions, externals)
 }
@@ -1,16 +1,34 @@
 use crate::{Compiler, Name};
 use std::collections::btree_map::Entry::{Occupied, Vacant};
 use std::collections::{BTreeMap, BTreeSet, HashMap};
+use sylt_common::error::{Error, Warning};
 use sylt_parser::statement::NameIdentifier;
 use sylt_parser::{
-    Assignable, AssignableKind, Expression, ExpressionKind, Identifier, Statement, StatementKind,
-    Type as ParserType, TypeAssignable, TypeAssignableKind, TypeKind, AST,
+    Assignable, AssignableKind, Expression, ExpressionKind, Identifier, Pattern, PatternKind,
+    Statement, StatementKind, Type as ParserType, TypeAssignable, TypeAssignableKind, TypeKind,
+    AST,
 };
 
+/// What is found on the way that isn't a dependency, used for warnings.
+#[derive(Default)]
+struct Usage {
+    /// The `use`d namespaces that are accessed.
+    namespaces: BTreeSet<(String, usize)>,
+    /// The names of all fields that are accessed.
+    fields: BTreeSet<String>,
+    /// The names of all blobs that are created. Blobs that are never
+    /// created describe external values, so their fields aren't checked.
+    blobs: BTreeSet<String>,
+    warnings: Vec<Error>,
+}
+
 struct Context<'a> {
     compiler: &'a Compiler,
     namespace: usize,
     variables: Vec<String>,
+    /// If we're in a function, where all variables are local.
+    in_function: bool,
+    usage: &'a mut Usage,
 }
 
 impl Context<'_> {
@@ -20,6 +38,41 @@ impl Context<'_> {
         }
     }
 
+    /// Shadows a local variable, and warns if it hides a global.
+    fn shadow_local(&mut self, variable: &Identifier) {
+        if self.in_function
+            && !self.shadowed(&variable.name)
+            && self.compiler.namespaces[self.namespace].contains_key(&variable.name)
+        {
+            self.usage.warnings.push(Error::Warning {
+                kind: Warning::ShadowedGlobal(variable.name.clone()),
+                file: self.compiler.file_from_namespace(self.namespace).into(),
+                span: variable.span,
+                message: None,
+            });
+        }
+        self.shadow(&variable.name);
+    }
+
+    fn shadow_pattern(&mut self, pattern: &Pattern) {
+        match &pattern.kind {
+            PatternKind::Bind(ident) => self.shadow_local(ident),
+            PatternKind::Tuple(patterns) => patterns
+                .iter()
+                .for_each(|pattern| self.shadow_pattern(pattern)),
+            PatternKind::Variant(_, Some(pattern)) => self.shadow_pattern(pattern),
+            PatternKind::As(pattern, ident) => {
+                self.shadow_pattern(pattern);
+                self.shadow_local(ident);
+            }
+            PatternKind::Wildcard
+            | PatternKind::Int(_)
+            | PatternKind::Str(_)
+            | PatternKind::Bool(_)
+            | PatternKind::Variant(_, None) => {}
+        }
+    }
+
     fn shadowed(&self, variable: &str) -> bool {
         return self.variables.iter().rfind(|&v| v == variable).is_some();
     }
@@ -76,7 +129,12 @@ fn assignable_dependencies(
                         // Might be shadowed here
                         let shadowed = ctx.shadowed(&ident.name);
                         match ctx.compiler.namespaces[ctx.namespace].get(&ident.name) {
-                            Some(Name::Namespace(ns)) if !shadowed => Ok(*ns),
+                            Some(Name::Namespace(ns)) if !shadowed => {
+                                ctx.usage
+                                    .namespaces
+                                    .insert((ident.name.clone(), ctx.namespace));
+                                Ok(*ns)
+                            }
                             _ => Err(()),
                         }
                     }
@@ -88,7 +146,10 @@ fn assignable_dependencies(
                     Some(_) => [(field.name.clone(), namespace)].iter().cloned().collect(),
                     _ => BTreeSet::new(),
                 },
-                Err(_) => assignable_dependencies(ctx, ass),
+                Err(_) => {
+                    ctx.usage.fields.insert(field.name.clone());
+                    assignable_dependencies(ctx, ass)
+                }
             }
         }
         Index(ass, expr) => assignable_dependencies(ctx, ass)
@@ -127,7 +188,12 @@ fn type_assignable_dependencies(
                         }
                     }
                     Read(ident) => match ctx.compiler.namespaces[ctx.namespace].get(&ident.name) {
-                        Some(Name::Namespace(ns)) => Ok(*ns),
+                        Some(Name::Namespace(ns)) => {
+                            ctx.usage
+                                .namespaces
+                                .insert((ident.name.clone(), ctx.namespace));
+                            Ok(*ns)
+                        }
                         _ => Err(()),
                     },
                 }
@@ -194,14 +260,26 @@ fn statement_dependencies(ctx: &mut Context, statement: &Statement) -> BTreeSet<
 
         Case { to_match, branches, fall_through } => [
             dependencies(ctx, to_match),
-            statement_dependencies(ctx, fall_through),
+            fall_through
+                .as_ref()
+                .map(|fall_through| statement_dependencies(ctx, fall_through))
+                .unwrap_or_default(),
         ]
         .iter()
         .cloned()
         .chain(
             branches
                 .iter()
-                .map(|branch| statement_dependencies(ctx, &branch.body))
+                .map(|branch| {
+                    let vars_before = ctx.variables.len();
+                    ctx.shadow_pattern(&branch.pattern);
+                    let mut deps = statement_dependencies(ctx, &branch.body);
+                    if let Some(guard) = &branch.guard {
+                        deps.extend(dependencies(ctx, guard));
+                    }
+                    ctx.variables.truncate(vars_before);
+                    deps
+                })
                 .collect::<BTreeSet<_>>(),
         )
         .flatten()
@@ -212,6 +290,15 @@ fn statement_dependencies(ctx: &mut Context, statement: &Statement) -> BTreeSet<
             .cloned()
             .collect(),
 
+        LoopIn { pattern, iterable, body } => {
+            let deps = dependencies(ctx, iterable);
+            let vars_before = ctx.variables.len();
+            ctx.shadow_pattern(pattern);
+            let body_deps = statement_dependencies(ctx, body);
+            ctx.variables.truncate(vars_before);
+            deps.union(&body_deps).cloned().collect()
+        }
+
         Block { statements } => {
             let vars_before = ctx.variables.len();
             let deps = statements
@@ -224,7 +311,7 @@ fn statement_dependencies(ctx: &mut Context, statement: &Statement) -> BTreeSet<
         }
 
         Definition { ident, value, ty, .. } => {
-            ctx.shadow(&ident.name);
+            ctx.shadow_local(ident);
             dependencies(ctx, value)
                 .union(&type_dependencies(ctx, ty))
                 .cloned()
@@ -284,13 +371,23 @@ fn dependencies(ctx: &mut Context, expression: &Expression) -> BTreeSet<(String,
     match &expression.kind {
         Get(assignable) => assignable_dependencies(ctx, assignable),
 
-        Neg(expr) | Not(expr) | Parenthesis(expr) => dependencies(ctx, expr),
+        Neg(expr) | Not(expr) | Parenthesis(expr) | Yield(expr) | Coroutine(expr) | ToStr(expr) => {
+            dependencies(ctx, expr)
+        }
 
         Comparison(lhs, _, rhs)
+        | Range { start: lhs, end: rhs, .. }
         | Add(lhs, rhs)
         | Sub(lhs, rhs)
         | Mul(lhs, rhs)
         | Div(lhs, rhs)
+        | Rem(lhs, rhs)
+        | FloorDiv(lhs, rhs)
+        | BitAnd(lhs, rhs)
+        | BitOr(lhs, rhs)
+        | BitXor(lhs, rhs)
+        | ShiftLeft(lhs, rhs)
+        | ShiftRight(lhs, rhs)
         | AssertEq(lhs, rhs)
         | And(lhs, rhs)
         | Or(lhs, rhs) => dependencies(ctx, lhs)
@@ -310,26 +407,34 @@ fn dependencies(ctx: &mut Context, expression: &Expression) -> BTreeSet<(String,
         // purposes defining the function requires all dependencies.
         Function { body, params, .. } => {
             let vars_before = ctx.variables.len();
-            params.iter().for_each(|(ident, _)| ctx.shadow(&ident.name));
+            let in_function = std::mem::replace(&mut ctx.in_function, true);
+            params.iter().for_each(|(ident, _)| ctx.shadow_local(ident));
             let type_deps = params
                 .iter()
                 .map(|(_, ty)| type_dependencies(ctx, ty))
                 .flatten()
                 .collect();
             let deps = statement_dependencies(ctx, body);
+            ctx.in_function = in_function;
             ctx.variables.truncate(vars_before);
             [deps, type_deps].iter().flatten().cloned().collect()
         }
-        Blob { blob, fields } => type_assignable_dependencies(ctx, blob)
-            .union(
-                &fields
-                    .iter()
-                    .map(|(_, expr)| dependencies(ctx, expr))
-                    .flatten()
-                    .collect(),
-            )
-            .cloned()
-            .collect(),
+        Blob { blob, fields } => {
+            let name = match &blob.kind {
+                TypeAssignableKind::Read(ident) | TypeAssignableKind::Access(_, ident) => ident,
+            };
+            ctx.usage.blobs.insert(name.name.clone());
+            type_assignable_dependencies(ctx, blob)
+                .union(
+                    &fields
+                        .iter()
+                        .map(|(_, expr)| dependencies(ctx, expr))
+                        .flatten()
+                        .collect(),
+                )
+                .cloned()
+                .collect()
+        }
 
         Tuple(exprs) | List(exprs) | Set(exprs) | Dict(exprs) => exprs
             .iter()
@@ -391,10 +496,16 @@ fn order(
     Ok(ordered)
 }
 
+/// Orders the global statements so every global is initialized before it's
+/// used. Also returns warnings for unused imports, unused blob fields and
+/// local variables that hide globals.
 pub(crate) fn initialization_order<'a>(
     tree: &'a AST,
     compiler: &Compiler,
-) -> Result<Vec<(&'a Statement, usize)>, Vec<(&'a Statement, usize)>> {
+) -> (
+    Result<Vec<(&'a Statement, usize)>, Vec<(&'a Statement, usize)>>,
+    Vec<Error>,
+) {
     let path_to_namespace_id: HashMap<_, _> = compiler
         .namespace_id_to_path
         .iter()
@@ -402,13 +513,20 @@ pub(crate) fn initialization_order<'a>(
         .collect();
     let mut to_order = BTreeMap::new();
     let mut is_checks = Vec::new();
+    let mut usage = Usage::default();
     for (path, module) in tree.modules.iter() {
         let namespace = path_to_namespace_id[path];
         for statement in module.statements.iter() {
             use StatementKind::*;
             match &statement.kind {
                 FromUse { imports, .. } => {
-                    let mut ctx = Context { compiler, namespace, variables: Vec::new() };
+                    let mut ctx = Context {
+                        compiler,
+                        namespace,
+                        variables: Vec::new(),
+                        in_function: false,
+                        usage: &mut usage,
+                    };
                     imports.iter().for_each(|(ident, alias)| {
                         let name = &alias.as_ref().unwrap_or(ident).name;
                         to_order.insert(
@@ -433,7 +551,13 @@ pub(crate) fn initialization_order<'a>(
                 }
                 | ExternalDefinition { ident: Identifier { name, .. }, .. }
                 | Definition { ident: Identifier { name, .. }, .. } => {
-                    let mut ctx = Context { compiler, namespace, variables: Vec::new() };
+                    let mut ctx = Context {
+                        compiler,
+                        namespace,
+                        variables: Vec::new(),
+                        in_function: false,
+                        usage: &mut usage,
+                    };
                     to_order.insert(
                         (name.clone(), namespace),
                         (
@@ -449,8 +573,72 @@ pub(crate) fn initialization_order<'a>(
             }
         }
     }
-    return order(to_order).map(|mut o| {
+
+    let used = to_order
+        .values()
+        .flat_map(|(deps, _)| deps.iter().cloned())
+        .collect();
+    let warnings = unused(tree, compiler, &used, usage);
+    let ordered = order(to_order).map(|mut o| {
         o.extend(is_checks);
         o
     });
+    (ordered, warnings)
+}
+
+/// Finds the imports and blob fields that are never used. `used` are the
+/// globals that something depends on.
+fn unused(
+    tree: &AST,
+    compiler: &Compiler,
+    used: &BTreeSet<(String, usize)>,
+    usage: Usage,
+) -> Vec<Error> {
+    let mut warnings = usage.warnings;
+    for (path, module) in tree.modules.iter() {
+        let namespace = compiler
+            .namespace_id_to_path
+            .iter()
+            .find_map(|(ns, p)| if p == path { Some(*ns) } else { None })
+            .unwrap();
+        let mut warn = |kind, span| {
+            warnings.push(Error::Warning { kind, file: path.clone(), span, message: None })
+        };
+
+        for statement in module.statements.iter() {
+            use StatementKind::*;
+            match &statement.kind {
+                Use { name, .. } => {
+                    let ident = match name {
+                        NameIdentifier::Implicit(ident) | NameIdentifier::Alias(ident) => ident,
+                    };
+                    if !usage.namespaces.contains(&(ident.name.clone(), namespace)) {
+                        warn(Warning::UnusedImport(ident.name.clone()), ident.span);
+                    }
+                }
+
+                FromUse { imports, .. } => {
+                    for (ident, alias) in imports.iter() {
+                        let ident = alias.as_ref().unwrap_or(ident);
+                        if !used.contains(&(ident.name.clone(), namespace)) {
+                            warn(Warning::UnusedImport(ident.name.clone()), ident.span);
+                        }
+                    }
+                }
+
+                Blob { name, fields } if usage.blobs.contains(name) => {
+                    let mut fields: Vec<_> = fields.iter().collect();
+                    fields.sort_by_key(|(field, _)| *field);
+                    for (field, ty) in fields {
+                        if !usage.fields.contains(field) {
+                            warn(Warning::UnusedField(name.clone(), field.clone()), ty.span);
+                        }
+                    }
+                }
+
+                _ => {}
+            }
+        }
+    }
+    warnings
 }
@@ -0,0 +1,232 @@
+//! Checks if the branches of a `case` cover every value, and if any
+//! branch can never be reached.
+//!
+//! This is the usefulness algorithm from "Warnings for pattern matching"
+//! by Luc Maranget. A row of patterns is useful if there is a value it
+//! matches that none of the rows before it match. A `case` covers every
+//! value if a wildcard after the last branch is useless, and a branch can
+//! never be reached if it is useless.
+use std::fmt;
+use sylt_parser::{Pattern, PatternKind};
+
+/// The most examples of uncovered values we look for.
+const MAX_WITNESSES: usize = 10;
+
+/// Something that builds values, e.g. a variant or a tuple.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constructor {
+    Variant(String),
+    Tuple(usize),
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl Constructor {
+    /// The number of values the constructor holds.
+    fn arity(&self) -> usize {
+        match self {
+            Constructor::Variant(_) => 1,
+            Constructor::Tuple(size) => *size,
+            Constructor::Bool(_) | Constructor::Int(_) | Constructor::Str(_) => 0,
+        }
+    }
+}
+
+/// A [Pattern] with the bindings removed.
+#[derive(Debug, Clone)]
+pub enum Pat {
+    Wild,
+    Constructor(Constructor, Vec<Pat>),
+}
+
+impl From<&Pattern> for Pat {
+    fn from(pattern: &Pattern) -> Self {
+        match &pattern.kind {
+            PatternKind::Wildcard | PatternKind::Bind(_) => Pat::Wild,
+            PatternKind::Int(i) => Pat::Constructor(Constructor::Int(*i), Vec::new()),
+            PatternKind::Str(s) => Pat::Constructor(Constructor::Str(s.clone()), Vec::new()),
+            PatternKind::Bool(b) => Pat::Constructor(Constructor::Bool(*b), Vec::new()),
+            PatternKind::Tuple(patterns) => Pat::Constructor(
+                Constructor::Tuple(patterns.len()),
+                patterns.iter().map(Pat::from).collect(),
+            ),
+            PatternKind::Variant(variant, value) => Pat::Constructor(
+                Constructor::Variant(variant.name.clone()),
+                vec![value.as_deref().map_or(Pat::Wild, Pat::from)],
+            ),
+            PatternKind::As(pattern, _) => Pat::from(pattern.as_ref()),
+        }
+    }
+}
+
+impl fmt::Display for Pat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pat::Wild => write!(f, "_"),
+            Pat::Constructor(Constructor::Variant(variant), values) => match &values[0] {
+                Pat::Wild => write!(f, "{}", variant),
+                value @ Pat::Constructor(Constructor::Variant(_), values)
+                    if !matches!(values[0], Pat::Wild) =>
+                {
+                    write!(f, "{} ({})", variant, value)
+                }
+                value => write!(f, "{} {}", variant, value),
+            },
+            Pat::Constructor(Constructor::Tuple(_), values) => {
+                write!(f, "(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                if values.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Pat::Constructor(Constructor::Bool(b), _) => write!(f, "{}", b),
+            Pat::Constructor(Constructor::Int(i), _) => write!(f, "{}", i),
+            Pat::Constructor(Constructor::Str(s), _) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// Answers questions about the types that are matched on.
+pub trait Signature {
+    /// Every constructor of the type, or `None` if there are too many
+    /// to list - like for integers.
+    fn constructors(&mut self, ty: usize) -> Option<Vec<Constructor>>;
+
+    /// The types of the values the constructor holds.
+    fn fields(&mut self, ty: usize, constructor: &Constructor) -> Vec<usize>;
+}
+
+/// Replaces the first pattern of the row with the values it holds, if it
+/// can match the constructor. Wildcards match everything.
+fn specialize(row: &[Pat], constructor: &Constructor) -> Option<Vec<Pat>> {
+    let mut specialized = match &row[0] {
+        Pat::Wild => vec![Pat::Wild; constructor.arity()],
+        Pat::Constructor(other, values) if other == constructor => values.clone(),
+        Pat::Constructor(..) => return None,
+    };
+    specialized.extend_from_slice(&row[1..]);
+    Some(specialized)
+}
+
+/// Puts the values the constructor holds back into the constructor.
+fn rebuild(constructor: &Constructor, mut witness: Vec<Pat>) -> Vec<Pat> {
+    let rest = witness.split_off(constructor.arity());
+    let mut rebuilt = vec![Pat::Constructor(constructor.clone(), witness)];
+    rebuilt.extend(rest);
+    rebuilt
+}
+
+/// Returns examples of values `row` matches that no row in `rows` matches,
+/// an empty list means the row is useless. `tys` are the types of the columns.
+pub fn useful<S: Signature>(
+    signature: &mut S,
+    rows: &[Vec<Pat>],
+    row: &[Pat],
+    tys: &[usize],
+) -> Vec<Vec<Pat>> {
+    if row.is_empty() {
+        return if rows.is_empty() {
+            vec![Vec::new()]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let ty = tys[0];
+    let useful_for = |signature: &mut S, constructor: &Constructor| {
+        let rows: Vec<_> = rows
+            .iter()
+            .filter_map(|r| specialize(r, constructor))
+            .collect();
+        let row = specialize(row, constructor).unwrap();
+        let mut tys_ = signature.fields(ty, constructor);
+        tys_.extend_from_slice(&tys[1..]);
+        useful(signature, &rows, &row, &tys_)
+            .into_iter()
+            .map(|witness| rebuild(constructor, witness))
+            .collect::<Vec<_>>()
+    };
+
+    if let Pat::Constructor(constructor, _) = &row[0] {
+        return useful_for(signature, constructor);
+    }
+
+    let mut used = Vec::new();
+    for r in rows.iter() {
+        if let Pat::Constructor(constructor, _) = &r[0] {
+            if !used.contains(constructor) {
+                used.push(constructor.clone());
+            }
+        }
+    }
+
+    let constructors = signature.constructors(ty);
+    match constructors {
+        Some(constructors) if constructors.iter().all(|c| used.contains(c)) => {
+            let mut witnesses = Vec::new();
+            for constructor in constructors.iter() {
+                witnesses.extend(useful_for(signature, constructor));
+                if witnesses.len() >= MAX_WITNESSES {
+                    break;
+                }
+            }
+            witnesses.truncate(MAX_WITNESSES);
+            witnesses
+        }
+        constructors => {
+            // Only the rows starting with a wildcard can match the
+            // constructors that aren't used.
+            let rows: Vec<_> = rows
+                .iter()
+                .filter(|r| matches!(r[0], Pat::Wild))
+                .map(|r| r[1..].to_vec())
+                .collect();
+            let rest = useful(signature, &rows, &row[1..], &tys[1..]);
+            if rest.is_empty() {
+                return Vec::new();
+            }
+
+            let prepend = |first: Pat| {
+                rest.iter()
+                    .map(|rest| {
+                        let mut witness = vec![first.clone()];
+                        witness.extend_from_slice(rest);
+                        witness
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            // Values built by the unused constructors aren't covered, but
+            // we also look inside the used ones to list everything that's missing.
+            let mut witnesses = Vec::new();
+            match constructors {
+                Some(constructors) => {
+                    for constructor in constructors {
+                        if used.contains(&constructor) {
+                            witnesses.extend(useful_for(signature, &constructor));
+                        } else {
+                            let arity = constructor.arity();
+                            witnesses.extend(prepend(Pat::Constructor(
+                                constructor,
+                                vec![Pat::Wild; arity],
+                            )));
+                        }
+                        if witnesses.len() >= MAX_WITNESSES {
+                            break;
+                        }
+                    }
+                }
+                None => witnesses.extend(prepend(Pat::Wild)),
+            }
+            witnesses.truncate(MAX_WITNESSES);
+            witnesses
+        }
+    }
+}
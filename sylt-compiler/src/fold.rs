@@ -0,0 +1,243 @@
+//! Evaluates expressions at compile time, so both backends can
+//! write the value instead of the code that computes it.
+//!
+//! Only literals, constant globals and operators without side effects
+//! are evaluated. Lists, sets and dicts are left alone since they are
+//! created anew every time the expression runs.
+use std::rc::Rc;
+use sylt_common::{op, Value};
+use sylt_parser::expression::ComparisonKind;
+use sylt_parser::{Assignable, AssignableKind, Expression, ExpressionKind, Identifier, VarKind};
+
+use crate::*;
+
+/// Why an expression can't be replaced with a value.
+pub(crate) enum Unfoldable {
+    /// The value is only known when the program runs.
+    NotConstant,
+    /// The expression always fails, so running it would too.
+    Error(Span, String),
+}
+
+/// Floats that aren't finite can't be stored as constants.
+fn storable(value: &Value) -> bool {
+    match value {
+        Value::Float(f) => f.is_finite(),
+        Value::Tuple(values) => values.iter().all(storable),
+        _ => true,
+    }
+}
+
+/// Is any part of the value zero.
+fn has_zero(value: &Value) -> bool {
+    match value {
+        Value::Int(i) => *i == 0,
+        Value::Float(f) => *f == 0.0,
+        Value::Tuple(values) => values.iter().any(has_zero),
+        _ => false,
+    }
+}
+
+/// Checks the result of an operator, which is nil if it failed.
+fn result(value: Value, span: Span, message: impl FnOnce() -> String) -> Result<Value, Unfoldable> {
+    if value.is_nil() {
+        Err(Unfoldable::Error(span, message()))
+    } else if storable(&value) {
+        Ok(value)
+    } else {
+        Err(Unfoldable::NotConstant)
+    }
+}
+
+impl Compiler {
+    /// Evaluates the expression, if every value in it is known at compile time.
+    pub(crate) fn fold(&self, expression: &Expression, ctx: Context) -> Result<Value, Unfoldable> {
+        use ComparisonKind::*;
+        use ExpressionKind::*;
+
+        let span = expression.span;
+        let bin_op = |a: &Expression, b: &Expression, f: fn(&Value, &Value) -> Value, op: &str| {
+            let a = self.fold(a, ctx)?;
+            let b = self.fold(b, ctx)?;
+            let division = matches!(op, "/" | "%" | "div");
+            result(f(&a, &b), span, || {
+                if division && has_zero(&b) {
+                    format!("Division by zero in '{:?} {} {:?}'", a, op, b)
+                } else {
+                    format!("Cannot compute '{:?} {} {:?}'", a, op, b)
+                }
+            })
+        };
+        let not = |value: Value| result(op::not(&value), span, || "Cannot negate".to_string());
+
+        match &expression.kind {
+            Get(assignable) => self.fold_assignable(assignable, ctx),
+
+            Add(a, b) => bin_op(a, b, op::add, "+"),
+            Sub(a, b) => bin_op(a, b, op::sub, "-"),
+            Mul(a, b) => bin_op(a, b, op::mul, "*"),
+            Div(a, b) => bin_op(a, b, op::div, "/"),
+            Rem(a, b) => bin_op(a, b, op::rem, "%"),
+            FloorDiv(a, b) => bin_op(a, b, op::floor_div, "div"),
+            BitAnd(a, b) => bin_op(a, b, op::bit_and, "&"),
+            BitOr(a, b) => bin_op(a, b, op::bit_or, "|"),
+            BitXor(a, b) => bin_op(a, b, op::bit_xor, "^"),
+            ShiftLeft(a, b) => bin_op(a, b, op::shift_left, "<<"),
+            ShiftRight(a, b) => bin_op(a, b, op::shift_right, ">>"),
+            Neg(a) => {
+                let a = self.fold(a, ctx)?;
+                result(op::neg(&a), span, || format!("Cannot compute '-{:?}'", a))
+            }
+
+            Comparison(a, cmp, b) => match cmp {
+                Equals => bin_op(a, b, op::eq, "=="),
+                NotEquals => not(bin_op(a, b, op::eq, "!=")?),
+                Greater => bin_op(a, b, op::greater, ">"),
+                GreaterEqual => not(bin_op(a, b, op::less, ">=")?),
+                Less => bin_op(a, b, op::less, "<"),
+                LessEqual => not(bin_op(a, b, op::greater, "<=")?),
+                In => Err(Unfoldable::NotConstant),
+            },
+
+            // Short circuits like the bytecode does.
+            And(a, b) => match self.fold(a, ctx)? {
+                Value::Bool(false) => Ok(Value::Bool(false)),
+                _ => self.fold(b, ctx),
+            },
+            Or(a, b) => match self.fold(a, ctx)? {
+                Value::Bool(false) => self.fold(b, ctx),
+                a => Ok(a),
+            },
+            Not(a) => not(self.fold(a, ctx)?),
+
+            Range { start, end, inclusive } => {
+                match (self.fold(start, ctx)?, self.fold(end, ctx)?) {
+                    (Value::Int(start), Value::Int(end)) if *inclusive => end
+                        .checked_add(1)
+                        .map(|end| Value::Range(start, end))
+                        .ok_or_else(|| Unfoldable::Error(span, "The range is too large".into())),
+                    (Value::Int(start), Value::Int(end)) => Ok(Value::Range(start, end)),
+                    _ => Err(Unfoldable::NotConstant),
+                }
+            }
+
+            Parenthesis(a) => self.fold(a, ctx),
+
+            IfExpression { condition, pass, fail } => match self.fold(condition, ctx)? {
+                Value::Bool(true) => self.fold(pass, ctx),
+                Value::Bool(false) => self.fold(fail, ctx),
+                _ => Err(Unfoldable::NotConstant),
+            },
+
+            Tuple(values) => Ok(Value::Tuple(Rc::new(
+                values
+                    .iter()
+                    .map(|value| self.fold(value, ctx))
+                    .collect::<Result<_, _>>()?,
+            ))),
+
+            Float(f) => Ok(Value::Float(*f)),
+            Int(i) => Ok(Value::Int(*i)),
+            Str(s) => Ok(Value::String(Rc::new(s.clone()))),
+            Bool(b) => Ok(Value::Bool(*b)),
+            Nil => Ok(Value::Nil),
+
+            AssertEq(..)
+            | Yield(_)
+            | Coroutine(_)
+            | ToStr(_)
+            | Function { .. }
+            | Blob { .. }
+            | List(_)
+            | Set(_)
+            | Dict(_) => Err(Unfoldable::NotConstant),
+        }
+    }
+
+    /// Remembers the value of a global defined with `::`, if it is
+    /// known at compile time. Reading it can then be folded too.
+    pub(crate) fn define_constant_global(
+        &mut self,
+        ident: &Identifier,
+        kind: VarKind,
+        value: &Expression,
+        namespace: NamespaceID,
+    ) {
+        if !kind.immutable() {
+            return;
+        }
+        let slot = match self.namespaces[namespace].get(&ident.name) {
+            Some(Name::Global(slot)) => *slot,
+            _ => return,
+        };
+        if let Ok(value) = self.fold(value, Context { namespace, frame: 0 }) {
+            self.constant_globals.insert(slot, value);
+        }
+    }
+
+    /// Reads a constant global, or indexes into a constant tuple.
+    fn fold_assignable(&self, assignable: &Assignable, ctx: Context) -> Result<Value, Unfoldable> {
+        match &assignable.kind {
+            AssignableKind::Index(tuple, index) => {
+                match (self.fold_assignable(tuple, ctx)?, self.fold(index, ctx)?) {
+                    // Indexing out of bounds is left for the typechecker to report.
+                    (Value::Tuple(values), Value::Int(index)) if index >= 0 => values
+                        .get(index as usize)
+                        .cloned()
+                        .ok_or(Unfoldable::NotConstant),
+                    _ => Err(Unfoldable::NotConstant),
+                }
+            }
+            AssignableKind::Expression(expression) => self.fold(expression, ctx),
+            _ => self
+                .fold_global(assignable, ctx)
+                .ok_or(Unfoldable::NotConstant),
+        }
+    }
+
+    /// The value of a constant global, if the assignable reads one.
+    fn fold_global(&self, assignable: &Assignable, ctx: Context) -> Option<Value> {
+        let (namespace, ident) = match &assignable.kind {
+            AssignableKind::Read(ident) if !self.is_local(&ident.name, ctx) => {
+                (ctx.namespace, ident)
+            }
+            AssignableKind::Access(namespace, ident) => {
+                (self.fold_namespace(namespace, ctx)?, ident)
+            }
+            _ => return None,
+        };
+        match self.namespaces[namespace].get(&ident.name) {
+            Some(Name::Global(slot)) => self.constant_globals.get(slot).cloned(),
+            _ => None,
+        }
+    }
+
+    /// The namespace the assignable points to, if it points to one.
+    fn fold_namespace(&self, assignable: &Assignable, ctx: Context) -> Option<NamespaceID> {
+        let (namespace, ident) = match &assignable.kind {
+            AssignableKind::Read(ident) if !self.is_local(&ident.name, ctx) => {
+                (ctx.namespace, ident)
+            }
+            AssignableKind::Access(namespace, ident) => {
+                (self.fold_namespace(namespace, ctx)?, ident)
+            }
+            _ => return None,
+        };
+        match self.namespaces[namespace].get(&ident.name) {
+            Some(Name::Namespace(namespace)) => Some(*namespace),
+            _ => None,
+        }
+    }
+
+    /// Is the name a local variable or an upvalue, which hides the globals.
+    /// Frame 0 has the globals.
+    fn is_local(&self, name: &str, ctx: Context) -> bool {
+        self.frames[1..=ctx.frame].iter().any(|frame| {
+            frame
+                .variables
+                .iter()
+                .any(|var| var.active && var.name == name)
+                || frame.upvalues.iter().any(|up| up.name == name)
+        })
+    }
+}
@@ -1,10 +1,11 @@
 use std::io::Write;
 use sylt_parser::expression::ComparisonKind;
 use sylt_parser::{
-    Assignable, AssignableKind, CaseBranch, Expression, ExpressionKind, Op, Span, Statement,
-    StatementKind,
+    Assignable, AssignableKind, CaseBranch, Expression, ExpressionKind, Op, Pattern, PatternKind,
+    Span, Statement, StatementKind,
 };
 
+use crate::fold::Unfoldable;
 use crate::*;
 
 macro_rules! write {
@@ -16,35 +17,138 @@ macro_rules! write {
     };
 }
 
+/// The functions and types every compiled program uses, written before
+/// the program or required by modules.
+pub const LUA_RUNTIME: &str = include_str!("preamble.lua");
+
+/// What modules require [LUA_RUNTIME_AS_MODULE] as, from the directory they are in.
+pub const LUA_RUNTIME_MODULE: &str = "sylt_runtime";
+
+/// [LUA_RUNTIME] as a module, which returns the runtime in a table
+/// instead of writing it to the Lua globals. The runtime is run with
+/// the table as its environment, `setfenv` does it on LuaJIT and the
+/// `_ENV` parameter on Lua 5.2 and newer.
+pub const LUA_RUNTIME_AS_MODULE: &str = concat!(
+    "local __RUNTIME = setmetatable({}, { __index = _G })\n",
+    "__RUNTIME._G = __RUNTIME\n",
+    "local function runtime(_ENV)\n",
+    include_str!("preamble.lua"),
+    "end\n",
+    "if setfenv then setfenv(runtime, __RUNTIME) end\n",
+    "runtime(__RUNTIME)\n",
+    "return __RUNTIME\n",
+);
+
 pub struct LuaCompiler<'t> {
     compiler: &'t mut Compiler,
     loops: Vec<usize>,
     file: Box<dyn Write>,
+    /// Writes a module that returns the globals, where the globals
+    /// are kept in a local table instead of in the Lua globals.
+    module: bool,
+    /// The line that is being written, counted from 0.
+    line: usize,
+    /// The statement that is being written.
+    span: Option<Span>,
+    /// The statement each line that has been written comes from.
+    lines: Vec<Option<Span>>,
 }
 
 impl<'t> LuaCompiler<'t> {
-    pub(crate) fn new(compiler: &'t mut Compiler, file: Box<dyn Write>) -> Self {
-        Self { compiler, loops: Vec::new(), file }
+    pub(crate) fn new(compiler: &'t mut Compiler, file: Box<dyn Write>, module: bool) -> Self {
+        Self {
+            compiler,
+            loops: Vec::new(),
+            file,
+            module,
+            line: 0,
+            span: None,
+            lines: Vec::new(),
+        }
+    }
+
+    /// The statement each line of the output comes from, where
+    /// the first line is at index 0.
+    pub(crate) fn finish(self) -> Vec<Option<Span>> {
+        self.lines
     }
 
     fn write(&mut self, msg: String) {
+        while self.lines.len() <= self.line {
+            self.lines.push(self.span);
+        }
         // We specifically ignore writing ';' to make Love happy
         if msg == ";" {
             let _ = self.file.write_all(b"\n");
+            self.line += 1;
         } else {
             let _ = self.file.write_all(msg.as_ref());
             let _ = self.file.write_all(b" ");
+            self.line += msg.matches('\n').count();
+        }
+        while self.lines.len() < self.line {
+            self.lines.push(self.span);
         }
     }
 
+    /// Writes a statement, and remembers that the lines come from it. A
+    /// line that has more than one statement on it comes from the last
+    /// statement that starts on it.
+    fn mapped<F: FnOnce(&mut Self)>(&mut self, span: Span, write: F) {
+        let outer = self.span.replace(span);
+        if let Some(line) = self.lines.get_mut(self.line) {
+            *line = Some(span);
+        }
+        write(self);
+        self.span = outer;
+    }
+
     fn write_global(&mut self, slot: usize) {
-        write!(self, "GLOBAL_{}", slot);
+        if self.module {
+            write!(self, "__GLOBALS[{}]", slot);
+        } else {
+            write!(self, "GLOBAL_{}", slot);
+        }
     }
 
     fn write_slot(&mut self, slot: VarSlot) {
         write!(self, "local_{}", slot);
     }
 
+    /// Defines the variables bound by a pattern, `value` is the Lua
+    /// expression for the value the pattern matched.
+    fn pattern_bind(&mut self, pattern: &Pattern, value: &str) {
+        match &pattern.kind {
+            PatternKind::Wildcard
+            | PatternKind::Int(_)
+            | PatternKind::Str(_)
+            | PatternKind::Bool(_)
+            | PatternKind::Variant(_, None) => {}
+            PatternKind::Bind(ident) => self.bind(ident, value),
+            PatternKind::Tuple(patterns) => {
+                for (i, pattern) in patterns.iter().enumerate() {
+                    self.pattern_bind(pattern, &format!("{}[{}]", value, i + 1));
+                }
+            }
+            PatternKind::Variant(_, Some(pattern)) => {
+                self.pattern_bind(pattern, &format!("{}[2]", value));
+            }
+            PatternKind::As(pattern, ident) => {
+                self.pattern_bind(pattern, value);
+                self.bind(ident, value);
+            }
+        }
+    }
+
+    fn bind(&mut self, ident: &Identifier, value: &str) {
+        let slot = self.compiler.define(&ident.name, ident.span);
+        self.compiler.activate(slot);
+        write!(self, "local");
+        self.write_slot(slot);
+        write!(self, "= {}", value);
+        write!(self, ";");
+    }
+
     fn assignable(&mut self, ass: &Assignable, ctx: Context) -> Option<usize> {
         use AssignableKind::*;
 
@@ -117,10 +221,52 @@ impl<'t> LuaCompiler<'t> {
         self.expression(&b, ctx);
     }
 
+    /// Writes a binary operator that's implemented as a function in the preamble.
+    fn bin_op_call(&mut self, a: &Expression, b: &Expression, function: &str, ctx: Context) {
+        write!(self, "{}(", function);
+        self.expression(a, ctx);
+        write!(self, ",");
+        self.expression(b, ctx);
+        write!(self, ")");
+    }
+
+    /// Writes a value known at compile time.
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Float(a) => write!(self, "{:?}", a),
+            Value::Bool(a) => write!(self, "{}", a),
+            Value::Int(a) => write!(self, "{}", a),
+            Value::String(a) => write!(self, "\"{}\"", escape_string(a)),
+            Value::Nil => write!(self, "__NIL"),
+            Value::Range(start, end) => write!(self, "__RANGE({}, {})", start, end),
+            Value::Tuple(values) => {
+                write!(self, "__TUPLE { ");
+                for value in values.iter() {
+                    self.value(value);
+                    write!(self, " , ");
+                }
+                write!(self, "}");
+            }
+            _ => unreachable!("Cannot write {:?} as a Lua value", value),
+        }
+    }
+
     fn expression(&mut self, expression: &Expression, ctx: Context) {
         use ComparisonKind::*;
         use ExpressionKind::*;
 
+        match self.compiler.fold(expression, ctx) {
+            Ok(value) => {
+                self.value(&value);
+                return;
+            }
+            Err(Unfoldable::Error(span, message)) => {
+                error!(self.compiler, span, "{}", message);
+                return;
+            }
+            Err(Unfoldable::NotConstant) => {}
+        }
+
         write!(self, "(");
         match &expression.kind {
             Parenthesis(expr) => {
@@ -131,16 +277,17 @@ impl<'t> LuaCompiler<'t> {
                 self.assignable(a, ctx);
             }
 
-            Add(a, b) => {
-                write!(self, "__ADD(");
-                self.expression(a, ctx);
-                write!(self, ",");
-                self.expression(b, ctx);
-                write!(self, ")");
-            }
+            Add(a, b) => self.bin_op_call(a, b, "__ADD", ctx),
             Sub(a, b) => self.bin_op(a, b, "-", ctx),
             Mul(a, b) => self.bin_op(a, b, "*", ctx),
             Div(a, b) => self.bin_op(a, b, "/", ctx),
+            Rem(a, b) => self.bin_op_call(a, b, "__REM", ctx),
+            FloorDiv(a, b) => self.bin_op_call(a, b, "__FLOOR_DIV", ctx),
+            BitAnd(a, b) => self.bin_op_call(a, b, "__BIT_AND", ctx),
+            BitOr(a, b) => self.bin_op_call(a, b, "__BIT_OR", ctx),
+            BitXor(a, b) => self.bin_op_call(a, b, "__BIT_XOR", ctx),
+            ShiftLeft(a, b) => self.bin_op_call(a, b, "__SHIFT_LEFT", ctx),
+            ShiftRight(a, b) => self.bin_op_call(a, b, "__SHIFT_RIGHT", ctx),
 
             Comparison(a, cmp, b) => match cmp {
                 Equals => self.bin_op(a, b, "==", ctx),
@@ -178,6 +325,33 @@ impl<'t> LuaCompiler<'t> {
                 self.expression(a, ctx);
             }
 
+            Yield(a) => {
+                write!(self, "coroutine.yield(");
+                self.expression(a, ctx);
+                write!(self, ")");
+            }
+            Coroutine(a) => {
+                write!(self, "__COROUTINE(");
+                self.expression(a, ctx);
+                write!(self, ")");
+            }
+            ToStr(a) => {
+                write!(self, "as_str(");
+                self.expression(a, ctx);
+                write!(self, ")");
+            }
+
+            Range { start, end, inclusive } => {
+                write!(self, "__RANGE(");
+                self.expression(start, ctx);
+                write!(self, ",");
+                self.expression(end, ctx);
+                if *inclusive {
+                    write!(self, "+ 1");
+                }
+                write!(self, ")");
+            }
+
             IfExpression { condition, pass, fail } => {
                 write!(self, "(function ()");
                 write!(self, "if");
@@ -289,7 +463,7 @@ impl<'t> LuaCompiler<'t> {
             Float(a) => write!(self, "{:?}", a),
             Bool(a) => write!(self, "{}", a),
             Int(a) => write!(self, "{}", a),
-            Str(a) => write!(self, "\"{}\"", a),
+            Str(a) => write!(self, "\"{}\"", escape_string(a)),
             Nil => write!(self, "__NIL"),
         }
         write!(self, ")");
@@ -321,7 +495,7 @@ impl<'t> LuaCompiler<'t> {
                 Some(Name::Enum(enum_)) => {
                     self.write_global(enum_);
                 }
-                Some(Name::External) => {
+                Some(Name::External(_)) => {
                     write!(self, "{}", name);
                 }
                 Some(Name::Namespace(new_namespace)) => {
@@ -381,7 +555,9 @@ impl<'t> LuaCompiler<'t> {
             | FromUse { .. } => return,
 
             #[rustfmt::skip]
-            Definition { ident, value, .. } => {
+            Definition { ident, kind, value, .. } => {
+                self.compiler
+                    .define_constant_global(ident, *kind, value, ctx.namespace);
                 self.set_identifier(&ident.name, statement.span, ctx, ctx.namespace);
                 write!(self, "=");
                 self.compiler.frames.push(Frame::new("/expr/", statement.span));
@@ -400,6 +576,10 @@ impl<'t> LuaCompiler<'t> {
     }
 
     fn statement(&mut self, statement: &Statement, ctx: Context) {
+        self.mapped(statement.span, |lua| lua.statement_kind(statement, ctx));
+    }
+
+    fn statement_kind(&mut self, statement: &Statement, ctx: Context) {
         use StatementKind::*;
         self.compiler.panic = false;
 
@@ -589,41 +769,110 @@ impl<'t> LuaCompiler<'t> {
                 write!(self, ";");
             }
 
+            LoopIn { pattern, iterable, body } => {
+                let ss = self.compiler.frames.last().unwrap().variables.len();
+                let element_slot = self.compiler.define("/element/", pattern.span);
+                self.compiler.activate(element_slot);
+                write!(self, "for");
+                self.write_slot(element_slot);
+                write!(self, "in __ITER(");
+                self.expression(iterable, ctx);
+                write!(self, ")");
+                write!(self, "do");
+                self.loops.push(0);
+                write!(self, ";");
+                // The bindings can't be in the same block as the label, a goto
+                // can't jump into the scope of a local.
+                write!(self, "do");
+                self.pattern_bind(pattern, &format!("local_{}", element_slot));
+                self.statement(body, ctx);
+                write!(self, "end");
+                write!(self, ";");
+                let l = self.loops.len();
+                if self.loops.pop().unwrap() > 0 {
+                    write!(self, "::CONTINUE_{}::", l);
+                    write!(self, ";");
+                }
+                write!(self, "end");
+                write!(self, ";");
+                self.compiler
+                    .frames
+                    .last_mut()
+                    .unwrap()
+                    .variables
+                    .truncate(ss);
+            }
+
             Case { to_match, branches, fall_through } => {
                 // TODO(ed): This code cannot really be made better... Lua has no
                 // switch-statements.
-                write!(self, "local __case_tmp =");
+                let ss = self.compiler.frames.last().unwrap().variables.len();
+                write!(self, "do");
+                write!(self, ";");
+                let to_match_slot = self.compiler.define("/case/", to_match.span);
+                self.compiler.activate(to_match_slot);
+                write!(self, "local");
+                self.write_slot(to_match_slot);
+                write!(self, "=");
                 self.expression(to_match, ctx);
-                // Solves the 0-branches case.
-                write!(self, "if false then");
                 write!(self, ";");
-                for CaseBranch { pattern: Identifier { name, .. }, variable, body } in
-                    branches.iter()
-                {
-                    write!(self, "elseif \"{}\" == __case_tmp[1] then", name);
+                let matched_slot = self.compiler.define("/matched/", to_match.span);
+                self.compiler.activate(matched_slot);
+                write!(self, "local");
+                self.write_slot(matched_slot);
+                write!(self, "= false");
+                write!(self, ";");
+
+                let to_match = format!("local_{}", to_match_slot);
+                let matched = format!("local_{}", matched_slot);
+                for CaseBranch { pattern, guard, body } in branches.iter() {
+                    let mut conditions = vec![format!("not {}", matched)];
+                    pattern_conditions(pattern, &to_match, &mut conditions);
+                    write!(self, "if {} then", conditions.join(" and "));
                     write!(self, ";");
-                    let ss = self.compiler.frames.last().unwrap().variables.len();
-                    if let Some(Identifier { name, span }) = &variable {
-                        let slot = self.compiler.define(name, *span);
-                        self.compiler.activate(slot);
-                        write!(self, "local");
-                        self.write_slot(slot);
-                        write!(self, "= __case_tmp[2]");
+
+                    let branch_ss = self.compiler.frames.last().unwrap().variables.len();
+                    self.pattern_bind(pattern, &to_match);
+                    if let Some(guard) = guard {
+                        write!(self, "if");
+                        self.expression(guard, ctx);
+                        write!(self, "then");
                         write!(self, ";");
                     }
+                    write!(self, "{} = true", matched);
+                    write!(self, ";");
                     self.statement(body, ctx);
+                    if guard.is_some() {
+                        write!(self, "end");
+                        write!(self, ";");
+                    }
                     self.compiler
                         .frames
                         .last_mut()
                         .unwrap()
                         .variables
-                        .truncate(ss);
+                        .truncate(branch_ss);
+
+                    write!(self, "end");
+                    write!(self, ";");
                 }
-                write!(self, "else");
-                self.statement(fall_through, ctx);
+                write!(self, "if not {} then", matched);
+                write!(self, ";");
+                match fall_through {
+                    Some(fall_through) => self.statement(fall_through, ctx),
+                    // The typechecker makes sure we never get here.
+                    None => write!(self, "assert(false, \"unreachable\")"),
+                }
+                write!(self, "end");
                 write!(self, ";");
                 write!(self, "end");
                 write!(self, ";");
+                self.compiler
+                    .frames
+                    .last_mut()
+                    .unwrap()
+                    .variables
+                    .truncate(ss);
             }
 
             If { condition, pass, fail } => {
@@ -657,8 +906,22 @@ impl<'t> LuaCompiler<'t> {
             }
 
             Ret { value } => {
-                write!(self, "return");
-                self.expression(value, ctx);
+                // A return has to end the block in Lua, so the
+                // unreachable code after it is put outside the block.
+                write!(self, "do return");
+                match &value.kind {
+                    // Lua only makes tail calls if the call isn't in parenthesis.
+                    ExpressionKind::Get(ass)
+                        if matches!(
+                            ass.kind,
+                            AssignableKind::Call(..) | AssignableKind::ArrowCall(..)
+                        ) =>
+                    {
+                        self.assignable(ass, ctx);
+                    }
+                    _ => self.expression(value, ctx),
+                }
+                write!(self, "end");
             }
         }
         write!(self, ";");
@@ -666,14 +929,36 @@ impl<'t> LuaCompiler<'t> {
 
     pub fn compile(&mut self, statement: &Statement, namespace: usize) {
         let ctx = Context { namespace, frame: 0 };
-        self.outer_statement(&statement, ctx);
+        self.mapped(statement.span, |lua| lua.outer_statement(&statement, ctx));
     }
 
     pub fn preamble(&mut self, _span: Span, _num_constants: usize) {
-        write!(self, include_str!("preamble.lua"));
+        if self.module {
+            // A module is required by its name, the runtime has the same prefix.
+            write!(
+                self,
+                "local __RUNTIME = require((... or \"\"):match(\"^(.-)[^%.]*$\") .. \"{}\")",
+                LUA_RUNTIME_MODULE
+            );
+            write!(self, ";");
+            // The runtime is looked up through the environment of the module,
+            // so it stays out of the globals of the host.
+            write!(self, "local _ENV = __RUNTIME");
+            write!(self, ";");
+            write!(self, "if setfenv then setfenv(1, __RUNTIME) end");
+            write!(self, ";");
+            write!(self, "local __GLOBALS = {}");
+            write!(self, ";");
+        } else {
+            write!(self, LUA_RUNTIME);
+        }
     }
 
     pub fn postamble(&mut self, span: Span) {
+        if self.module {
+            self.exports();
+            return;
+        }
         let ctx = Context {
             frame: self.compiler.frames.len() - 1,
             namespace: 0,
@@ -682,4 +967,84 @@ impl<'t> LuaCompiler<'t> {
         write!(self, "()");
         write!(self, ";");
     }
+
+    /// Returns the globals, blobs and enums of the main file from a module.
+    /// The table reads and writes the globals, so it sees what the
+    /// functions of the module change.
+    fn exports(&mut self) {
+        let mut exports: Vec<_> = self.compiler.namespaces[0]
+            .iter()
+            .filter_map(|(name, global)| match global {
+                Name::Global(slot) | Name::Blob(slot) | Name::Enum(slot) => {
+                    Some((name.clone(), *slot))
+                }
+                Name::External(_) | Name::Namespace(_) => None,
+            })
+            .collect();
+        exports.sort();
+        write!(self, "local __EXPORTS = {");
+        for (name, slot) in exports {
+            write!(self, "[\"{}\"] = {},", name, slot);
+        }
+        write!(self, "}");
+        write!(self, ";");
+        write!(
+            self,
+            "return setmetatable({}, {
+    __index = function(_, name)
+        local slot = __EXPORTS[name]
+        return slot and __GLOBALS[slot]
+    end,
+    __newindex = function(_, name, value)
+        __GLOBALS[assert(__EXPORTS[name], \"The module has no global \" .. tostring(name))] = value
+    end,
+})"
+        );
+        write!(self, ";");
+    }
+}
+
+/// The Lua conditions that have to hold for `pattern` to match `value`.
+fn pattern_conditions(pattern: &Pattern, value: &str, conditions: &mut Vec<String>) {
+    match &pattern.kind {
+        PatternKind::Wildcard | PatternKind::Bind(_) => {}
+        PatternKind::Int(i) => conditions.push(format!("{} == {}", value, i)),
+        PatternKind::Str(s) => conditions.push(format!("{} == \"{}\"", value, escape_string(s))),
+        PatternKind::Bool(b) => conditions.push(format!("{} == {}", value, b)),
+        PatternKind::Tuple(patterns) => {
+            for (i, pattern) in patterns.iter().enumerate() {
+                pattern_conditions(pattern, &format!("{}[{}]", value, i + 1), conditions);
+            }
+        }
+        PatternKind::Variant(variant, pattern) => {
+            conditions.push(format!("{}[1] == \"{}\"", value, variant.name));
+            if let Some(pattern) = pattern {
+                pattern_conditions(pattern, &format!("{}[2]", value), conditions);
+            }
+        }
+        PatternKind::As(pattern, _) => pattern_conditions(pattern, value, conditions),
+    }
+}
+
+/// Escapes a string so it can be written as a Lua string literal.
+fn escape_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // Lua has no unicode escapes in 5.1, but decimal escapes work.
+            c if c.is_control() => {
+                let mut buf = [0; 4];
+                for byte in c.encode_utf8(&mut buf).bytes() {
+                    out.push_str(&format!("\\{:03}", byte));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
 }
@@ -0,0 +1,190 @@
+//! A peephole optimizer, it looks at a few ops at a time and
+//! rewrites them into fewer ops that do the same thing.
+//!
+//! The ops that are removed are first marked, and then all removed at
+//! once so jumps and line numbers can be moved to the ops that are left.
+use std::collections::{HashMap, HashSet};
+use sylt_common::{Block, Op};
+
+/// Where the op can jump to, if it jumps.
+fn target(op: &Op) -> Option<usize> {
+    match op {
+        Op::Jmp(target)
+        | Op::JmpFalse(target)
+        | Op::JmpTrue(target)
+        | Op::JmpNPop(target, _)
+        | Op::IterNext(target) => Some(*target),
+        _ => None,
+    }
+}
+
+fn set_target(op: &mut Op, to: usize) {
+    match op {
+        Op::Jmp(target)
+        | Op::JmpFalse(target)
+        | Op::JmpTrue(target)
+        | Op::JmpNPop(target, _)
+        | Op::IterNext(target) => *target = to,
+        _ => unreachable!("Op {:?} doesn't jump", op),
+    }
+}
+
+/// The op after these ops is only run if something jumps to it.
+fn ends_flow(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::Jmp(_) | Op::JmpNPop(..) | Op::Return | Op::TailCall(_) | Op::Unreachable
+    )
+}
+
+/// Pushes a value without doing anything else.
+fn pure_push(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::Constant(_) | Op::ReadLocal(_) | Op::ReadUpvalue(_) | Op::ReadGlobal(_)
+    )
+}
+
+/// Makes jumps that land on a `Jmp` go straight to where it goes,
+/// and jumps that land on a `Return` return.
+fn thread_jumps(ops: &mut [Op]) -> bool {
+    let mut changed = false;
+    for i in 0..ops.len() {
+        let mut to = match target(&ops[i]) {
+            Some(to) => to,
+            None => continue,
+        };
+        // Jumps can go around in a loop, those are left as they are.
+        let mut seen = HashSet::new();
+        while let Some(Op::Jmp(next)) = ops.get(to) {
+            if !seen.insert(to) {
+                to = target(&ops[i]).unwrap();
+                break;
+            }
+            to = *next;
+        }
+        if matches!(ops[i], Op::Jmp(_)) && matches!(ops.get(to), Some(Op::Return)) {
+            ops[i] = Op::Return;
+            changed = true;
+        } else if target(&ops[i]) != Some(to) {
+            set_target(&mut ops[i], to);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Marks the ops that can be removed, and rewrites the ones
+/// that can be replaced with something simpler. Returns true
+/// if anything was rewritten.
+fn mark_removed(ops: &mut [Op], removed: &mut [bool]) -> bool {
+    let targets: HashSet<usize> = ops.iter().filter_map(target).collect();
+    let mut rewritten = false;
+    let mut reachable = true;
+    let mut i = 0;
+    while i < ops.len() {
+        reachable |= targets.contains(&i);
+        if !reachable {
+            removed[i] = true;
+            i += 1;
+            continue;
+        }
+
+        // The second op in a pair can only be changed if
+        // nothing jumps to it.
+        let next = if i + 1 < ops.len() && !targets.contains(&(i + 1)) {
+            Some(ops[i + 1])
+        } else {
+            None
+        };
+        match (ops[i], next) {
+            (push, Some(Op::Pop)) if pure_push(&push) => {
+                removed[i] = true;
+                removed[i + 1] = true;
+                i += 2;
+                continue;
+            }
+            (Op::Not, Some(Op::JmpFalse(to))) => {
+                removed[i] = true;
+                ops[i + 1] = Op::JmpTrue(to);
+                i += 1;
+                continue;
+            }
+            (Op::Jmp(to), _) if to == i + 1 => {
+                removed[i] = true;
+                i += 1;
+                continue;
+            }
+            (Op::JmpFalse(to), _) if to == i + 1 => {
+                ops[i] = Op::Pop;
+                rewritten = true;
+            }
+            _ => {}
+        }
+
+        reachable = !ends_flow(&ops[i]);
+        i += 1;
+    }
+    rewritten
+}
+
+/// Removes the marked ops, and moves the jumps, lines and locals
+/// that point to them to the next op that is kept.
+fn remove(block: &mut Block, removed: &[bool]) {
+    let mut moved_to = Vec::with_capacity(block.ops.len() + 1);
+    let mut kept = 0;
+    for is_removed in removed.iter() {
+        moved_to.push(kept);
+        if !is_removed {
+            kept += 1;
+        }
+    }
+    // Jumping to the end of the block is allowed.
+    moved_to.push(kept);
+
+    let mut ops = Vec::with_capacity(kept);
+    let mut line_offsets = HashMap::new();
+    let mut line = 0;
+    let mut last_line = 0;
+    for (i, op) in block.ops.iter().enumerate() {
+        if let Some(offset) = block.line_offsets.get(&i) {
+            line = *offset;
+        }
+        if removed[i] {
+            continue;
+        }
+        if line != last_line {
+            line_offsets.insert(ops.len(), line);
+            last_line = line;
+        }
+        let mut op = *op;
+        if let Some(to) = target(&op) {
+            set_target(&mut op, moved_to[to]);
+        }
+        ops.push(op);
+    }
+
+    for local in block.locals.iter_mut() {
+        local.start = moved_to[local.start];
+        local.end = local.end.map(|end| moved_to[end]);
+    }
+
+    block.ops = ops;
+    block.line_offsets = line_offsets;
+}
+
+/// Optimizes the block until there is nothing more to do.
+pub(crate) fn optimize(block: &mut Block) {
+    loop {
+        let mut removed = vec![false; block.ops.len()];
+        let threaded = thread_jumps(&mut block.ops);
+        let rewritten = mark_removed(&mut block.ops, &mut removed);
+        let any_removed = removed.iter().any(|r| *r);
+        if any_removed {
+            remove(block, &removed);
+        }
+        if !threaded && !rewritten && !any_removed {
+            break;
+        }
+    }
+}
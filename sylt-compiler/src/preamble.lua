@@ -16,6 +16,14 @@ __INDEX = function(o, i)
     if m == nil then
         return o[i]
     end
+    if m._type == "list" and getmetatable(i) == __RANGE_META then
+        assert(i[1] >= 0 and i[1] <= i[2] and i[2] <= #o, "List slice out of range \"" .. tostring(i) .. "\"")
+        local out = {}
+        for x = i[1] + 1, i[2] do
+            out[#out + 1] = o[x]
+        end
+        return __LIST(out)
+    end
     if m._type == "tuple" or m._type == "list" then
         local e = o[i + 1]
         assert(e ~= nil, "Tuple/list index out of range \"" .. i .. "\"")
@@ -59,6 +67,37 @@ __ASSIGN_INDEX = function(o, i, v)
 end
 
 
+-- Returns a function that gives the next element of the container each time
+-- it's called, lists and tuples go through ipairs and sets and dicts through
+-- pairs. The elements of a dict are key-value tuples.
+__ITER = function(o)
+    local m = getmetatable(o)
+    if m._type == "range" then
+        local i = o[1] - 1
+        return function()
+            i = i + 1
+            if i < o[2] then return i end
+            return nil
+        end
+    end
+    if m._type == "set" or m._type == "dict" then
+        local f, s, k = pairs(o)
+        return function()
+            local v
+            k, v = f(s, k)
+            if k == nil then return nil end
+            if m._type == "set" then return k end
+            return __TUPLE({ k, v })
+        end
+    end
+    local f, s, i = ipairs(o)
+    return function()
+        local v
+        i, v = f(s, i)
+        return v
+    end
+end
+
 __ADD = function(a, b)
     if type(a) == "string" and type(b) == "string" then
         return a .. b
@@ -66,6 +105,56 @@ __ADD = function(a, b)
     return a + b
 end
 
+__REM = function(a, b)
+    if type(a) == "table" then
+        local out = {}
+        for x = 1, #a, 1 do
+            out[x] = __REM(a[x], b[x])
+        end
+        return __TUPLE(out)
+    end
+    assert(b ~= 0, "Cannot take the remainder of a division by zero")
+    return a % b
+end
+
+__FLOOR_DIV = function(a, b)
+    if type(a) == "table" then
+        local out = {}
+        for x = 1, #a, 1 do
+            out[x] = __FLOOR_DIV(a[x], b[x])
+        end
+        return __TUPLE(out)
+    end
+    assert(b ~= 0, "Cannot floor divide by zero")
+    return math.floor(a / b)
+end
+
+-- LuaJIT has the bit library, Lua 5.3 and newer have bitwise operators - but
+-- they can't be written here since older versions fail to parse them.
+if bit then
+    __BIT_AND = bit.band
+    __BIT_OR = bit.bor
+    __BIT_XOR = bit.bxor
+    __RAW_SHIFT_LEFT = bit.lshift
+    __RAW_SHIFT_RIGHT = bit.arshift
+else
+    __BIT_AND = load("return function(a, b) return a & b end")()
+    __BIT_OR = load("return function(a, b) return a | b end")()
+    __BIT_XOR = load("return function(a, b) return a ~ b end")()
+    __RAW_SHIFT_LEFT = load("return function(a, b) return a << b end")()
+    __RAW_SHIFT_RIGHT = load("return function(a, b) return a // (1 << b) end")()
+end
+
+__SHIFT_LEFT = function(a, b)
+    assert(b >= 0 and b < 64, "Cannot shift by " .. b .. " bits")
+    return __RAW_SHIFT_LEFT(a, b)
+end
+
+__SHIFT_RIGHT = function(a, b)
+    assert(b >= 0 and b < 64, "Cannot shift by " .. b .. " bits")
+    return __RAW_SHIFT_RIGHT(a, b)
+end
+
 __VARIANT_META = { _type = "variant" }
 __VARIANT_META.__newindex = function()
     assert(false, "Variants are immutable")
@@ -243,6 +332,21 @@ function __DICT(obj)
     return setmetatable(obj, __DICT_META)
 end
 
+-- The integers from o[1] up to, but not including, o[2].
+__RANGE_META = { _type = "range" }
+__RANGE_META.__newindex = function()
+    assert(false, "Cannot assign to range!")
+end
+__RANGE_META.__eq = function(a, b)
+    return a[1] == b[1] and a[2] == b[2]
+end
+__RANGE_META.__tostring = function(a)
+    return tostring(a[1]) .. ".." .. tostring(a[2])
+end
+function __RANGE(start, stop)
+    return setmetatable({ start, stop }, __RANGE_META)
+end
+
 __SET_META = { _type = "set" }
 -- TODO(ed): add - sub - mul?
 __SET_META.__eq = function(a, b)
@@ -281,18 +385,36 @@ function __SET(obj)
 end
 
 __BLOB_META = { _type = "blob" }
+-- The pairs of blobs that are being compared, blobs that reference
+-- themselves lead back to them.
+__COMPARING = {}
 __BLOB_META.__eq = function(a, b)
+    local comparing = __COMPARING[a]
+    if comparing == nil then
+        comparing = {}
+        __COMPARING[a] = comparing
+    elseif comparing[b] then
+        return true
+    end
+    comparing[b] = true
+    local same = true
     for k, v in pairs(a) do
         if b[k] == nil or v ~= b[k] then
-            return false
+            same = false
+            break
         end
     end
     for k, _ in pairs(b) do
         if a[k] == nil then
-            return false
+            same = false
+            break
         end
     end
-    return true
+    comparing[b] = nil
+    if next(comparing) == nil then
+        __COMPARING[a] = nil
+    end
+    return same
 end
 __BLOB_META.__tostring = function(a)
     if __SEEN[a] then
@@ -316,11 +438,34 @@ function __BLOB(obj)
     return setmetatable(obj, __BLOB_META)
 end
 
+-- Coroutines are resumed by calling them, the threads
+-- are kept so 'done' can look them up.
+__COROUTINES = setmetatable({}, { __mode = "k" })
+function __COROUTINE(f)
+    local thread = coroutine.create(f)
+    local resume = function(...)
+        local ok, value = coroutine.resume(thread, ...)
+        if not ok then
+            error(value, 0)
+        end
+        return value
+    end
+    __COROUTINES[resume] = thread
+    return resume
+end
+
 -- std-sylt
 
-function atan2(x, y) return math.atan2(y, x) end
+-- LuaJIT has unpack, math.atan2 and math.pow, Lua 5.4 only has table.unpack
+-- and math.atan, which takes two arguments.
+local unpack = table.unpack or unpack
+local raw_atan2 = math.atan2 or math.atan
+local raw_pow = math.pow or function(x, y) return x ^ y end
+
+function atan2(x, y) return raw_atan2(y, x) end
 function dbg(x) print(x); return x end
 function random_choice(l) return l[math.random(1, #l)] end
+function done(c) return coroutine.status(__COROUTINES[c]) == "dead" end
 
 function varargs(f)
     return function(xs)
@@ -407,7 +552,7 @@ end
 floor = math.floor
 as_char = string.byte
 function as_chars(s)
-    return __LIST(string.byte(s, 1, string.len(s)))
+    return __LIST({ string.byte(s, 1, string.len(s)) })
 end
 
 sqrt = math.sqrt
@@ -429,7 +574,7 @@ max = math.max
 function rem(x, y)
     return math.abs(x % y)
 end
-pow = math.pow
+pow = raw_pow
 function angle(v)
     return atan2(v[1], v[2])
 end
@@ -484,6 +629,16 @@ end
 
 unsafe_force = __IDENTITY
 
+-- Lua collects cycles itself, and doesn't count objects.
+__GC_COLLECTIONS = 0
+function gc_collect()
+    collectgarbage("collect")
+    __GC_COLLECTIONS = __GC_COLLECTIONS + 1
+end
+function gc_stats()
+    return __DICT { allocated = 0, collections = __GC_COLLECTIONS, freed = 0 }
+end
+
 function __CONTAINS(a, b)
     local ty = getmetatable(b)._type
     if ty == "list" then
@@ -500,7 +655,23 @@ function __CONTAINS(a, b)
     if ty == "set" then
         return b[a] ~= nil
     end
+    if ty == "range" then
+        return b[1] <= a and a < b[2]
+    end
     assert(false, "Invalid contains!")
 end
 
+-- A host that runs the program in-process gives the extern functions of
+-- the standard library in __SYLT_STD, they fill in what Lua is missing.
+if __SYLT_STD then
+    for name, f in pairs(__SYLT_STD) do
+        if _G[name] == nil then
+            _G[name] = f
+        end
+    end
+    reflect = __SYLT_STD.reflect
+    debug_assertions = __SYLT_STD.debug_assertions
+    thread_sleep = __SYLT_STD.thread_sleep
+end
+
 -- End Sylt preamble
@@ -18,6 +18,7 @@ pub enum Type {
     List(usize),
     Set(usize),
     Dict(usize, usize),
+    Range,
     Function(Vec<usize>, usize),
     Blob(String, BTreeMap<String, usize>),
     Enum(String, BTreeMap<String, usize>),
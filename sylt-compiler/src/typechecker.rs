@@ -1,14 +1,15 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
-use sylt_common::error::{Error, Helper, TypeError};
+use sylt_common::error::{Error, Helper, TypeError, Warning};
 use sylt_common::{RustFunction, Type as RuntimeType};
 use sylt_parser::statement::NameIdentifier;
 use sylt_parser::{
     expression::ComparisonKind, Assignable, AssignableKind, Expression, ExpressionKind, Identifier,
-    Op as ParserOp, Span, Statement, StatementKind, Type as ParserType, TypeAssignable,
-    TypeAssignableKind, TypeConstraint, TypeKind, VarKind,
+    Op as ParserOp, Pattern, PatternKind, Span, Statement, StatementKind, Type as ParserType,
+    TypeAssignable, TypeAssignableKind, TypeConstraint, TypeKind, VarKind,
 };
 
+use crate::exhaustiveness::{self, Constructor, Pat, Signature};
 use crate::ty::Type;
 use std::collections::{BTreeMap, BTreeSet};
 
@@ -98,6 +99,8 @@ struct Variable {
     ty: usize,
     kind: VarKind,
     span: Span,
+    /// Local variables that are never used are warned about.
+    used: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -124,16 +127,25 @@ enum Constraint {
     Sub(usize),
     Mul(usize),
     Div(usize),
+    Rem(usize),
+    FloorDiv(usize),
+    BitAnd(usize),
+    BitOr(usize),
+    BitXor(usize),
+    ShiftLeft(usize),
+    ShiftRight(usize),
     Equ(usize),
     Cmp(usize),
     CmpEqu(usize),
 
     Neg,
 
-    Indexes(usize),
-    IndexedBy(usize),
-    IndexingGives(usize),
-    GivenByIndex(usize),
+    /// (outer, ret)
+    Indexes(usize, usize),
+    /// (index, ret)
+    IndexedBy(usize, usize),
+    /// (outer, index)
+    GivenByIndex(usize, usize),
     ConstantIndex(i64, usize),
 
     Field(String, usize),
@@ -143,11 +155,28 @@ enum Constraint {
     SameContainer(usize),
     Contains(usize),
     IsContainedIn(usize),
+    Iterable(usize),
 
     Enum,
     Variant(String, usize),
 }
 
+/// A `case` that is checked for missing and unreachable branches once
+/// all the types are known.
+struct CaseCheck {
+    span: Span,
+    to_match: usize,
+    /// The pattern of each branch, and if the branch has a guard.
+    branches: Vec<(Pat, Span, bool)>,
+    fall_through: Option<Span>,
+    /// If the `else` does nothing or only `<!>`, it isn't warned about
+    /// when the branches cover every value.
+    placeholder: bool,
+    /// The pattern of a `loop <pattern> in` is checked like a case
+    /// with one branch, since it has to match every element.
+    is_loop: bool,
+}
+
 struct TypeChecker {
     globals: HashMap<(usize, String), Name>,
     stack: Vec<Variable>,
@@ -156,6 +185,17 @@ struct TypeChecker {
     // TODO(ed): This can probably be removed via some trickery
     file_to_namespace: HashMap<PathBuf, usize>,
     functions: HashMap<String, usize>,
+    /// The types of the external values the host supplies, resolved
+    /// where they are defined.
+    externals: HashMap<String, ParserType>,
+    cases: Vec<CaseCheck>,
+    warnings: Vec<Error>,
+    /// The parameters and return type of the functions that are checked,
+    /// the innermost last. Yields are typed from them.
+    enclosing: Vec<(Vec<usize>, usize)>,
+    /// Programs need a start function, modules that are
+    /// required from Lua don't.
+    needs_start: bool,
 }
 
 #[derive(Clone, Debug, Copy)]
@@ -185,6 +225,11 @@ impl TypeChecker {
                 .map(|(a, b)| (b.clone(), a.clone()))
                 .collect(),
             functions: HashMap::new(),
+            externals: HashMap::new(),
+            cases: Vec::new(),
+            warnings: Vec::new(),
+            enclosing: Vec::new(),
+            needs_start: true,
         };
         res.functions = functions
             .iter()
@@ -460,6 +505,7 @@ impl TypeChecker {
                 RuntimeType::Float => Type::Float,
                 RuntimeType::Bool => Type::Bool,
                 RuntimeType::String => Type::Str,
+                RuntimeType::Range => Type::Range,
                 x => unreachable!("Got an unexpected resolved type '{:?}'", x),
             },
 
@@ -533,13 +579,25 @@ impl TypeChecker {
                 let ss = self.stack.len();
                 let rets = self.push_type(Type::Unknown);
                 let mut any_return = false;
+                let mut reachable = true;
+                let mut warned = false;
                 for stmt in statements.iter() {
+                    if !reachable && !warned && !matches!(stmt.kind, StatementKind::EmptyStatement)
+                    {
+                        self.warning(
+                            stmt.span,
+                            Warning::UnreachableCode,
+                            "The code before it always ends with 'ret', 'break', 'continue' or '<!>'",
+                        );
+                        warned = true;
+                    }
+                    reachable &= reaches_end(stmt);
                     if let Some(ret) = self.statement(stmt, ctx)? {
                         self.unify(span, ctx, rets, ret)?;
                         any_return = true;
                     }
                 }
-                self.stack.truncate(ss);
+                self.pop_scope(ss);
                 if any_return {
                     Ok(Some(rets))
                 } else {
@@ -556,31 +614,54 @@ impl TypeChecker {
 
             StatementKind::Case { to_match, branches, fall_through } => {
                 let to_match = self.expression(to_match, ctx)?;
-                self.add_constraint(to_match, span, Constraint::Enum);
 
                 let ss = self.stack.len();
+                let mut ret = None;
                 for branch in branches.iter() {
-                    if let Some(var) = &branch.variable {
-                        let var = Variable {
-                            ident: var.clone(),
-                            ty: self.push_type(Type::Unknown),
-                            kind: VarKind::Const,
-                            span,
-                        };
-                        self.add_constraint(
-                            to_match,
-                            span,
-                            Constraint::Variant(branch.pattern.name.clone(), var.ty),
-                        );
-                        self.stack.push(var);
+                    self.pattern(&branch.pattern, to_match, ctx)?;
+                    if let Some(guard) = &branch.guard {
+                        let ty = self.expression(guard, ctx)?;
+                        let boolean = self.push_type(Type::Bool);
+                        self.unify(guard.span, ctx, boolean, ty)?;
                     }
-                    self.check_constraints(span, ctx, to_match)?;
-                    self.statement(&branch.body, ctx)?;
-                    self.stack.truncate(ss);
+                    let branch_ret = self.statement(&branch.body, ctx)?;
+                    self.pop_scope(ss);
+                    ret = match (ret, branch_ret) {
+                        (Some(ret), Some(branch_ret)) => {
+                            Some(self.unify(span, ctx, ret, branch_ret)?)
+                        }
+                        (ret, branch_ret) => ret.or(branch_ret),
+                    };
                 }
 
-                self.statement(fall_through, ctx)?;
-                Ok(None)
+                self.cases.push(CaseCheck {
+                    span,
+                    to_match,
+                    branches: branches
+                        .iter()
+                        .map(|branch| {
+                            (
+                                Pat::from(&branch.pattern),
+                                branch.pattern.span,
+                                branch.guard.is_some(),
+                            )
+                        })
+                        .collect(),
+                    fall_through: fall_through.as_ref().map(|fall_through| fall_through.span),
+                    placeholder: matches!(fall_through.as_deref(), Some(body) if is_placeholder(body)),
+                    is_loop: false,
+                });
+
+                let fall_through = match fall_through {
+                    Some(fall_through) => self.statement(fall_through, ctx)?,
+                    None => None,
+                };
+                match (ret, fall_through) {
+                    (Some(ret), Some(fall_through)) => {
+                        Ok(Some(self.unify(span, ctx, ret, fall_through)?))
+                    }
+                    (ret, fall_through) => Ok(ret.or(fall_through)),
+                }
             }
 
             StatementKind::If { condition, pass, fail } => {
@@ -638,6 +719,27 @@ impl TypeChecker {
                 self.statement(body, ctx)
             }
 
+            StatementKind::LoopIn { pattern, iterable, body } => {
+                let iterable = self.expression(iterable, ctx)?;
+                let element = self.push_type(Type::Unknown);
+                self.add_constraint(iterable, span, Constraint::Iterable(element));
+                self.check_constraints(span, ctx, iterable)?;
+
+                let ss = self.stack.len();
+                self.pattern(pattern, element, ctx)?;
+                self.cases.push(CaseCheck {
+                    span: pattern.span,
+                    to_match: element,
+                    branches: vec![(Pat::from(pattern), pattern.span, false)],
+                    fall_through: None,
+                    placeholder: false,
+                    is_loop: true,
+                });
+                let ret = self.statement(body, ctx)?;
+                self.pop_scope(ss);
+                Ok(ret)
+            }
+
             StatementKind::Break => Ok(None),
             StatementKind::Continue => Ok(None),
 
@@ -686,6 +788,7 @@ impl TypeChecker {
                                 ty: self.push_type(Type::Unknown),
                                 kind: VarKind::Const,
                                 span,
+                                used: false,
                             };
                             self.unify(span, ctx, var.ty, other_var.ty)?;
                             self.globals
@@ -742,7 +845,28 @@ impl TypeChecker {
 
             StatementKind::ExternalDefinition { ident, kind, ty } => {
                 let ty = self.resolve_type(span, ctx, ty)?;
-                let var = Variable { ident: ident.clone(), ty, kind: *kind, span };
+                if let Some(supplied) = self.externals.get(&ident.name).cloned() {
+                    let supplied = self.resolve_type(span, ctx, &supplied)?;
+                    self.unify(span, ctx, ty, supplied).or_else(|_| {
+                        err_type_error!(
+                            self,
+                            span,
+                            TypeError::Mismatch {
+                                got: self.bake_type(supplied),
+                                expected: self.bake_type(ty),
+                            },
+                            "The host supplies '{}' with another type",
+                            ident.name
+                        )
+                    })?;
+                }
+                let var = Variable {
+                    ident: ident.clone(),
+                    ty,
+                    kind: *kind,
+                    span,
+                    used: false,
+                };
                 self.globals
                     .insert((ctx.namespace, ident.name.clone()), Name::Global(var));
             }
@@ -755,6 +879,7 @@ impl TypeChecker {
 
             StatementKind::Assignment { .. }
             | StatementKind::Loop { .. }
+            | StatementKind::LoopIn { .. }
             | StatementKind::Break
             | StatementKind::Continue
             | StatementKind::Ret { .. }
@@ -861,12 +986,8 @@ impl TypeChecker {
             }
 
             AssignableKind::Read(ident) => {
-                if let Some(var) = self
-                    .stack
-                    .iter()
-                    .rfind(|v| v.ident.name == ident.name)
-                    .cloned()
-                {
+                if let Some(var) = self.stack.iter_mut().rfind(|v| v.ident.name == ident.name) {
+                    var.used = true;
                     Ok(var.ty)
                 } else {
                     match self
@@ -962,15 +1083,15 @@ impl TypeChecker {
                         self.add_constraint(outer, span, Constraint::ConstantIndex(index, ret));
                     }
                     _ => {
-                        self.add_constraint(index, span, Constraint::Indexes(outer));
-                        self.add_constraint(outer, span, Constraint::IndexedBy(index));
-                        self.add_constraint(outer, span, Constraint::IndexingGives(ret));
-                        self.add_constraint(ret, span, Constraint::GivenByIndex(outer));
+                        self.add_constraint(index, span, Constraint::Indexes(outer, ret));
+                        self.add_constraint(outer, span, Constraint::IndexedBy(index, ret));
+                        self.add_constraint(ret, span, Constraint::GivenByIndex(outer, index));
                     }
                 }
 
                 self.check_constraints(span, ctx, outer)?;
                 self.check_constraints(span, ctx, index)?;
+                self.check_constraints(span, ctx, ret)?;
                 Ok(ret)
             }
 
@@ -991,6 +1112,17 @@ impl TypeChecker {
             ExpressionKind::Sub(a, b) => bin_op!(self, span, ctx, a, b, Constraint::Sub),
             ExpressionKind::Mul(a, b) => bin_op!(self, span, ctx, a, b, Constraint::Mul),
             ExpressionKind::Div(a, b) => bin_op!(self, span, ctx, a, b, Constraint::Div),
+            ExpressionKind::Rem(a, b) => bin_op!(self, span, ctx, a, b, Constraint::Rem),
+            ExpressionKind::FloorDiv(a, b) => bin_op!(self, span, ctx, a, b, Constraint::FloorDiv),
+            ExpressionKind::BitAnd(a, b) => bin_op!(self, span, ctx, a, b, Constraint::BitAnd),
+            ExpressionKind::BitOr(a, b) => bin_op!(self, span, ctx, a, b, Constraint::BitOr),
+            ExpressionKind::BitXor(a, b) => bin_op!(self, span, ctx, a, b, Constraint::BitXor),
+            ExpressionKind::ShiftLeft(a, b) => {
+                bin_op!(self, span, ctx, a, b, Constraint::ShiftLeft)
+            }
+            ExpressionKind::ShiftRight(a, b) => {
+                bin_op!(self, span, ctx, a, b, Constraint::ShiftRight)
+            }
 
             ExpressionKind::Comparison(a, comp, b) => match comp {
                 ComparisonKind::NotEquals | ComparisonKind::Equals => {
@@ -1042,6 +1174,68 @@ impl TypeChecker {
                 self.unify(span, ctx, a, boolean)
             }
 
+            // A coroutine yields what its function returns, and is
+            // resumed with what its function takes.
+            ExpressionKind::Yield(value) => {
+                let (params, ret) = match self.enclosing.last() {
+                    Some(function) => function.clone(),
+                    None => {
+                        return err_type_error!(
+                            self,
+                            span,
+                            TypeError::Exotic,
+                            "Can only yield inside a function"
+                        );
+                    }
+                };
+                let value = self.expression(value, ctx)?;
+                self.unify(span, ctx, ret, value)?;
+                match params.as_slice() {
+                    [] => Ok(self.push_type(Type::Void)),
+                    [param] => Ok(*param),
+                    _ => err_type_error!(
+                        self,
+                        span,
+                        TypeError::Exotic,
+                        "Coroutines are resumed with one value, but the function takes {}",
+                        params.len()
+                    ),
+                }
+            }
+
+            ExpressionKind::Coroutine(function) => {
+                let function = self.expression(function, ctx)?;
+                match self.find_type(function) {
+                    Type::Function(params, _) if params.len() <= 1 => Ok(function),
+                    Type::Function(params, _) => err_type_error!(
+                        self,
+                        span,
+                        TypeError::WrongArity { got: params.len(), expected: 1 },
+                        "Coroutines are resumed with one value"
+                    ),
+                    _ => err_type_error!(
+                        self,
+                        span,
+                        TypeError::Violating(self.bake_type(function)),
+                        "Only functions can be made into coroutines"
+                    ),
+                }
+            }
+
+            ExpressionKind::ToStr(value) => {
+                self.expression(value, ctx)?;
+                Ok(self.push_type(Type::Str))
+            }
+
+            ExpressionKind::Range { start, end, .. } => {
+                let start = self.expression(start, ctx)?;
+                let end = self.expression(end, ctx)?;
+                let int = self.push_type(Type::Int);
+                self.unify(span, ctx, start, int)?;
+                self.unify(span, ctx, end, int)?;
+                Ok(self.push_type(Type::Range))
+            }
+
             ExpressionKind::Parenthesis(expr) => self.expression(expr, ctx),
 
             ExpressionKind::IfExpression { condition, pass, fail } => {
@@ -1061,24 +1255,44 @@ impl TypeChecker {
                     let ty = self.inner_resolve_type(span, ctx, ty, &mut seen)?;
                     args.push(ty);
 
+                    // Parameters don't have to be used, the function might
+                    // have to fit a type.
                     let var = Variable {
                         ident: ident.clone(),
                         ty,
                         kind: VarKind::Const,
                         span,
+                        used: true,
                     };
                     self.stack.push(var);
                 }
 
                 let ret = self.inner_resolve_type(span, ctx, ret, &mut seen)?;
-                if let Some(actual_ret) = self.statement(body, ctx)? {
+                self.enclosing.push((args.clone(), ret));
+                let body_ret = self.statement(body, ctx);
+                self.enclosing.pop();
+                if let Some(actual_ret) = body_ret? {
                     self.unify(span, ctx, ret, actual_ret)?;
-                } else {
-                    let void = self.push_type(Type::Void);
-                    self.unify(span, ctx, ret, void)?;
+                }
+                // Functions that reach the end return nil.
+                if reaches_end(body) {
+                    match self.find_type(ret) {
+                        Type::Unknown | Type::Void => {
+                            let void = self.push_type(Type::Void);
+                            self.unify(span, ctx, ret, void)?;
+                        }
+                        _ => {
+                            return err_type_error!(
+                                self,
+                                span,
+                                TypeError::MissingReturn(self.bake_type(ret)),
+                                "Add a 'ret' at the end, or make every branch return"
+                            );
+                        }
+                    }
                 }
 
-                self.stack.truncate(ss);
+                self.pop_scope(ss);
 
                 Ok(self.push_type(Type::Function(args, ret)))
             }
@@ -1144,11 +1358,12 @@ impl TypeChecker {
                             kind: VarKind::Const,
                             ty: given_blob,
                             span,
+                            used: true,
                         });
                     }
                     let expr_ty = self.expression(expr, ctx)?;
                     self.unify(expr.span, ctx, expr_ty, fields_and_types[key])?;
-                    self.stack.truncate(ss);
+                    self.pop_scope(ss);
                 }
 
                 self.unify(span, ctx, given_blob, blob_ty)
@@ -1205,6 +1420,88 @@ impl TypeChecker {
         }
     }
 
+    /// Checks that the pattern can match values of type `ty`,
+    /// and puts the variables it binds on the stack.
+    fn pattern(&mut self, pattern: &Pattern, ty: usize, ctx: TypeCtx) -> TypeResult<()> {
+        let span = pattern.span;
+        match &pattern.kind {
+            PatternKind::Wildcard => {}
+
+            PatternKind::Bind(ident) => {
+                self.stack.push(Variable {
+                    ident: ident.clone(),
+                    ty,
+                    kind: VarKind::Const,
+                    span,
+                    used: false,
+                });
+            }
+
+            PatternKind::Int(_) | PatternKind::Str(_) | PatternKind::Bool(_) => {
+                let literal = self.push_type(match pattern.kind {
+                    PatternKind::Int(_) => Type::Int,
+                    PatternKind::Str(_) => Type::Str,
+                    _ => Type::Bool,
+                });
+                self.unify(span, ctx, ty, literal)?;
+            }
+
+            PatternKind::Tuple(patterns) => {
+                let elements: Vec<_> = patterns
+                    .iter()
+                    .map(|_| self.push_type(Type::Unknown))
+                    .collect();
+                let tuple = self.push_type(Type::Tuple(elements.clone()));
+                match self.find_type(ty) {
+                    Type::Tuple(tys) if tys.len() != elements.len() => {
+                        return err_type_error!(
+                            self,
+                            span,
+                            TypeError::Mismatch {
+                                got: self.bake_type(ty),
+                                expected: self.bake_type(tuple),
+                            },
+                            "The pattern has {} elements but the tuple has {}",
+                            elements.len(),
+                            tys.len()
+                        );
+                    }
+                    _ => {}
+                }
+                self.unify(span, ctx, ty, tuple)?;
+                for (pattern, element) in patterns.iter().zip(elements) {
+                    self.pattern(pattern, element, ctx)?;
+                }
+            }
+
+            PatternKind::Variant(variant, value) => {
+                let value_ty = self.push_type(Type::Unknown);
+                self.add_constraint(ty, span, Constraint::Enum);
+                self.add_constraint(
+                    ty,
+                    span,
+                    Constraint::Variant(variant.name.clone(), value_ty),
+                );
+                self.check_constraints(span, ctx, ty)?;
+                if let Some(value) = value {
+                    self.pattern(value, value_ty, ctx)?;
+                }
+            }
+
+            PatternKind::As(pattern, ident) => {
+                self.pattern(pattern, ty, ctx)?;
+                self.stack.push(Variable {
+                    ident: ident.clone(),
+                    ty,
+                    kind: VarKind::Const,
+                    span,
+                    used: false,
+                });
+            }
+        }
+        Ok(())
+    }
+
     fn definition(&mut self, statement: &Statement, global: bool, ctx: TypeCtx) -> TypeResult<()> {
         let span = statement.span;
         match &statement.kind {
@@ -1220,7 +1517,13 @@ impl TypeChecker {
                         let ret = self.push_type(Type::Unknown);
                         let fn_ty = self.push_type(Type::Function(args, ret));
                         self.unify(span, ctx, defined_ty, fn_ty)?;
-                        let var = Variable { ident: ident.clone(), ty: fn_ty, kind: *kind, span };
+                        let var = Variable {
+                            ident: ident.clone(),
+                            ty: fn_ty,
+                            kind: *kind,
+                            span,
+                            used: false,
+                        };
                         if global {
                             self.globals
                                 .insert((ctx.namespace, ident.name.clone()), Name::Global(var));
@@ -1241,6 +1544,7 @@ impl TypeChecker {
                         ty: defined_ty,
                         kind: *kind,
                         span,
+                        used: false,
                     };
                     if global {
                         self.globals.insert(
@@ -1302,6 +1606,7 @@ impl TypeChecker {
             Type::Float => RuntimeType::Float,
             Type::Bool => RuntimeType::Bool,
             Type::Str => RuntimeType::String,
+            Type::Range => RuntimeType::Range,
             Type::Tuple(tys) => RuntimeType::Tuple(
                 tys.iter()
                     .map(|ty| self.inner_bake_type(*ty, seen))
@@ -1354,6 +1659,13 @@ impl TypeChecker {
                 Constraint::Sub(b) => self.sub(span, ctx, a, *b),
                 Constraint::Mul(b) => self.mul(span, ctx, a, *b),
                 Constraint::Div(b) => self.div(span, ctx, a, *b),
+                Constraint::Rem(b) => self.rem(span, ctx, a, *b, "%"),
+                Constraint::FloorDiv(b) => self.rem(span, ctx, a, *b, "div"),
+                Constraint::BitAnd(b) => self.bitwise(span, a, *b, "&"),
+                Constraint::BitOr(b) => self.bitwise(span, a, *b, "|"),
+                Constraint::BitXor(b) => self.bitwise(span, a, *b, "^"),
+                Constraint::ShiftLeft(b) => self.bitwise(span, a, *b, "<<"),
+                Constraint::ShiftRight(b) => self.bitwise(span, a, *b, ">>"),
                 Constraint::Equ(b) => self.equ(span, ctx, a, *b),
                 Constraint::Cmp(b) => self.cmp(span, ctx, a, *b),
                 Constraint::CmpEqu(b) => self.equ(span, ctx, a, *b).and(self.cmp(span, ctx, a, *b)),
@@ -1367,11 +1679,9 @@ impl TypeChecker {
                     ),
                 },
 
-                Constraint::IndexedBy(b) => self.is_indexed_by(span, ctx, a, *b),
-                Constraint::Indexes(b) => self.is_indexed_by(span, ctx, *b, a),
-
-                Constraint::IndexingGives(b) => self.is_given_by_indexing(span, ctx, a, *b),
-                Constraint::GivenByIndex(b) => self.is_given_by_indexing(span, ctx, *b, a),
+                Constraint::IndexedBy(index, ret) => self.index(span, ctx, a, *index, *ret),
+                Constraint::Indexes(outer, ret) => self.index(span, ctx, *outer, a, *ret),
+                Constraint::GivenByIndex(outer, index) => self.index(span, ctx, *outer, *index, a),
 
                 Constraint::ConstantIndex(index, ret) => {
                     self.constant_index(span, ctx, a, *index, *ret)
@@ -1442,6 +1752,7 @@ impl TypeChecker {
 
                 Constraint::Contains(b) => self.contains(span, ctx, a, *b),
                 Constraint::IsContainedIn(b) => self.contains(span, ctx, *b, a),
+                Constraint::Iterable(b) => self.iterable(span, ctx, a, *b),
 
                 Constraint::Enum => match self.find_type(a) {
                     Type::Unknown | Type::Enum(..) => Ok(()),
@@ -1543,6 +1854,7 @@ impl TypeChecker {
                 (Type::Float, Type::Float) => {}
                 (Type::Bool, Type::Bool) => {}
                 (Type::Str, Type::Str) => {}
+                (Type::Range, Type::Range) => {}
 
                 (Type::List(a), Type::List(b)) => {
                     self.sub_unify(span, ctx, a, b, seen)
@@ -1702,14 +2014,26 @@ impl TypeChecker {
                         C::Sub(x) => C::Sub(self.inner_copy(*x, seen)),
                         C::Mul(x) => C::Mul(self.inner_copy(*x, seen)),
                         C::Div(x) => C::Div(self.inner_copy(*x, seen)),
+                        C::Rem(x) => C::Rem(self.inner_copy(*x, seen)),
+                        C::FloorDiv(x) => C::FloorDiv(self.inner_copy(*x, seen)),
+                        C::BitAnd(x) => C::BitAnd(self.inner_copy(*x, seen)),
+                        C::BitOr(x) => C::BitOr(self.inner_copy(*x, seen)),
+                        C::BitXor(x) => C::BitXor(self.inner_copy(*x, seen)),
+                        C::ShiftLeft(x) => C::ShiftLeft(self.inner_copy(*x, seen)),
+                        C::ShiftRight(x) => C::ShiftRight(self.inner_copy(*x, seen)),
                         C::Equ(x) => C::Equ(self.inner_copy(*x, seen)),
                         C::Cmp(x) => C::Cmp(self.inner_copy(*x, seen)),
                         C::CmpEqu(x) => C::CmpEqu(self.inner_copy(*x, seen)),
                         C::Neg => C::Neg,
-                        C::Indexes(x) => C::Indexes(self.inner_copy(*x, seen)),
-                        C::IndexedBy(x) => C::IndexedBy(self.inner_copy(*x, seen)),
-                        C::IndexingGives(x) => C::IndexingGives(self.inner_copy(*x, seen)),
-                        C::GivenByIndex(x) => C::GivenByIndex(self.inner_copy(*x, seen)),
+                        C::Indexes(x, y) => {
+                            C::Indexes(self.inner_copy(*x, seen), self.inner_copy(*y, seen))
+                        }
+                        C::IndexedBy(x, y) => {
+                            C::IndexedBy(self.inner_copy(*x, seen), self.inner_copy(*y, seen))
+                        }
+                        C::GivenByIndex(x, y) => {
+                            C::GivenByIndex(self.inner_copy(*x, seen), self.inner_copy(*y, seen))
+                        }
                         C::ConstantIndex(i, x) => C::ConstantIndex(*i, self.inner_copy(*x, seen)),
                         C::Field(f, x) => C::Field(f.clone(), self.inner_copy(*x, seen)),
                         C::Num => C::Num,
@@ -1717,6 +2041,7 @@ impl TypeChecker {
                         C::SameContainer(x) => C::SameContainer(self.inner_copy(*x, seen)),
                         C::Contains(x) => C::Contains(self.inner_copy(*x, seen)),
                         C::IsContainedIn(x) => C::IsContainedIn(self.inner_copy(*x, seen)),
+                        C::Iterable(x) => C::Iterable(self.inner_copy(*x, seen)),
                         C::Enum => C::Enum,
                         C::Variant(v, x) => C::Variant(v.clone(), *x),
                     },
@@ -1734,7 +2059,8 @@ impl TypeChecker {
             | Type::Int
             | Type::Float
             | Type::Bool
-            | Type::Str => ty,
+            | Type::Str
+            | Type::Range => ty,
 
             Type::Tuple(tys) => {
                 Type::Tuple(tys.iter().map(|ty| self.inner_copy(*ty, seen)).collect())
@@ -1966,6 +2292,49 @@ impl TypeChecker {
         }
     }
 
+    /// Checks `%` and `div`, which work like `/` but round towards negative infinity.
+    fn rem(&mut self, span: Span, ctx: TypeCtx, a: usize, b: usize, op: &str) -> TypeResult<()> {
+        match (self.find_type(a), self.find_type(b)) {
+            (Type::Unknown, _) | (_, Type::Unknown) => Ok(()),
+
+            (Type::Float, Type::Float) | (Type::Int, Type::Int) => Ok(()),
+
+            (Type::Tuple(a), Type::Tuple(b)) if a.len() == b.len() => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    self.rem(span, ctx, *a, *b, op)?;
+                }
+                Ok(())
+            }
+
+            _ => err_type_error!(
+                self,
+                span,
+                TypeError::BinOp {
+                    lhs: self.bake_type(a),
+                    rhs: self.bake_type(b),
+                    op: op.to_string(),
+                }
+            ),
+        }
+    }
+
+    /// Checks the bitwise operators, which only work on ints.
+    fn bitwise(&mut self, span: Span, a: usize, b: usize, op: &str) -> TypeResult<()> {
+        match (self.find_type(a), self.find_type(b)) {
+            (Type::Unknown | Type::Int, Type::Unknown | Type::Int) => Ok(()),
+
+            _ => err_type_error!(
+                self,
+                span,
+                TypeError::BinOp {
+                    lhs: self.bake_type(a),
+                    rhs: self.bake_type(b),
+                    op: op.to_string(),
+                }
+            ),
+        }
+    }
+
     fn equ(&mut self, span: Span, ctx: TypeCtx, a: usize, b: usize) -> TypeResult<()> {
         // Equal types all support equality!
         self.unify(span, ctx, a, b).map(|_| ())
@@ -2000,12 +2369,26 @@ impl TypeChecker {
         }
     }
 
+    /// Checks that `outer` can be indexed by `index`, and that it gives `ret`.
+    fn index(
+        &mut self,
+        span: Span,
+        ctx: TypeCtx,
+        outer: usize,
+        index: usize,
+        ret: usize,
+    ) -> TypeResult<()> {
+        self.is_indexed_by(span, ctx, outer, index)?;
+        self.is_given_by_indexing(span, ctx, outer, index, ret)
+    }
+
     fn is_indexed_by(&mut self, span: Span, ctx: TypeCtx, a: usize, b: usize) -> TypeResult<()> {
         match (self.find_type(a), self.find_type(b)) {
             (Type::Unknown, _) => Ok(()),
             (_, Type::Unknown) => Ok(()),
 
             (Type::List(_), Type::Int) => Ok(()),
+            (Type::List(_), Type::Range) => Ok(()),
             (Type::Tuple(_), Type::Int) => Ok(()),
             // TODO(ed): Sets!
             (Type::Dict(k, _), _) => {
@@ -2030,6 +2413,7 @@ impl TypeChecker {
         span: Span,
         ctx: TypeCtx,
         a: usize,
+        index: usize,
         b: usize,
     ) -> TypeResult<()> {
         match self.find_type(a) {
@@ -2045,6 +2429,12 @@ impl TypeChecker {
                     "Tuples can only be indexed by positive integer constants"
                 );
             }
+            // Slicing a list gives a list, so the index has to be known.
+            Type::List(_) if matches!(self.find_type(index), Type::Unknown) => Ok(()),
+            Type::List(_) if matches!(self.find_type(index), Type::Range) => {
+                self.unify(span, ctx, a, b)?;
+                Ok(())
+            }
             Type::List(given) => {
                 self.unify(span, ctx, given, b)?;
                 Ok(())
@@ -2108,6 +2498,12 @@ impl TypeChecker {
 
             (Type::Set(x), _) | (Type::List(x), _) => self.unify(span, ctx, x, b).map(|_| ()),
 
+            (Type::Range, Type::Int) => Ok(()),
+            (Type::Range, _) => {
+                let int = self.push_type(Type::Int);
+                self.unify(span, ctx, int, b).map(|_| ())
+            }
+
             (Type::Dict(kx, vx), Type::Tuple(ys)) => {
                 if ys.len() == 2 {
                     self.unify(span, ctx, kx, ys[0])?;
@@ -2135,6 +2531,45 @@ impl TypeChecker {
         }
     }
 
+    fn iterable(&mut self, span: Span, ctx: TypeCtx, a: usize, b: usize) -> TypeResult<()> {
+        // New types are only made when needed, unifying with them checks
+        // this constraint again.
+        match (self.find_type(a), self.find_type(b)) {
+            (Type::Unknown, _) => Ok(()),
+
+            (Type::Set(x) | Type::List(x), _) => self.unify(span, ctx, x, b).map(|_| ()),
+
+            (Type::Range, Type::Int) => Ok(()),
+            (Type::Range, _) => {
+                let int = self.push_type(Type::Int);
+                self.unify(span, ctx, int, b).map(|_| ())
+            }
+
+            (Type::Dict(kx, vx), Type::Tuple(ys)) if ys.len() == 2 => {
+                self.unify(span, ctx, kx, ys[0])?;
+                self.unify(span, ctx, vx, ys[1]).map(|_| ())
+            }
+            (Type::Dict(kx, vx), _) => {
+                let entry = self.push_type(Type::Tuple(vec![kx, vx]));
+                self.unify(span, ctx, entry, b).map(|_| ())
+            }
+
+            (Type::Tuple(xs), _) => {
+                for x in xs {
+                    self.unify(span, ctx, x, b)?;
+                }
+                Ok(())
+            }
+
+            (_, _) => err_type_error!(
+                self,
+                span,
+                TypeError::Violating(self.bake_type(a)),
+                "Only lists, sets, dicts, tuples and ranges can be looped over"
+            ),
+        }
+    }
+
     fn solve(&mut self, statements: &Vec<(&Statement, usize)>) -> TypeResult<()> {
         // Initialize the namespaces first.
         for (statement, namespace) in statements.iter() {
@@ -2150,6 +2585,11 @@ impl TypeChecker {
             }
         }
 
+        // All types are known now.
+        for case in std::mem::take(&mut self.cases) {
+            self.check_case(case)?;
+        }
+
         let ctx = TypeCtx { namespace: 0 };
         match self.globals.get(&(0, "start".to_string())).cloned() {
             Some(Name::Global(var)) => {
@@ -2177,6 +2617,7 @@ impl TypeChecker {
                     "Expected a start function in the main module - but it was something else"
                 )
             }
+            None if !self.needs_start => Ok(()),
             None => {
                 err_type_error!(
                     self,
@@ -2188,15 +2629,229 @@ impl TypeChecker {
         }
     }
 
+    /// Makes sure the case covers every value, and warns about branches
+    /// that can never be reached.
+    fn check_case(&mut self, case: CaseCheck) -> TypeResult<()> {
+        let mut rows = Vec::new();
+        for (pattern, span, guarded) in case.branches {
+            let row = vec![pattern];
+            if exhaustiveness::useful(self, &rows, &row, &[case.to_match]).is_empty() {
+                self.warning(
+                    span,
+                    Warning::UnreachablePattern,
+                    "The branches before it match everything it does",
+                );
+            }
+            if !guarded {
+                rows.push(row);
+            }
+        }
+
+        let missing = exhaustiveness::useful(self, &rows, &[Pat::Wild], &[case.to_match]);
+        match case.fall_through {
+            Some(span) if missing.is_empty() && !case.placeholder => {
+                self.warning(
+                    span,
+                    Warning::UnreachablePattern,
+                    "The branches cover every value",
+                );
+            }
+            None if !missing.is_empty() => {
+                let missing = missing
+                    .iter()
+                    .map(|witness| witness[0].to_string())
+                    .collect();
+                let err = if case.is_loop {
+                    err_type_error!(
+                        self,
+                        case.span,
+                        TypeError::NonExhaustive(missing),
+                        "The pattern of a loop has to match every element"
+                    )
+                } else {
+                    err_type_error!(
+                        self,
+                        case.span,
+                        TypeError::NonExhaustive(missing),
+                        "Add the missing branches, or an else"
+                    )
+                };
+                return if matches!(self.find_type(case.to_match), Type::Unknown) {
+                    err.help_no_span(
+                        "The type of the value isn't known here - so the variants can't be listed"
+                            .to_string(),
+                    )
+                } else {
+                    err
+                };
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Removes the variables defined since the stack had `size` variables,
+    /// and warns about the ones that weren't used.
+    fn pop_scope(&mut self, size: usize) {
+        for var in self.stack.split_off(size) {
+            if !var.used && !var.ident.name.starts_with('_') {
+                self.warnings.push(Error::Warning {
+                    kind: Warning::UnusedVariable(var.ident.name.clone()),
+                    file: self.span_file(&var.ident.span),
+                    span: var.ident.span,
+                    message: Some(format!(
+                        "Remove it, or call it '_{}' if it's unused on purpose",
+                        var.ident.name
+                    )),
+                });
+            }
+        }
+    }
+
+    fn warning(&mut self, span: Span, kind: Warning, message: &str) {
+        self.warnings.push(Error::Warning {
+            kind,
+            file: self.span_file(&span),
+            span,
+            message: Some(message.to_string()),
+        });
+    }
+
     fn span_file(&self, span: &Span) -> PathBuf {
         self.namespace_to_file[&span.file_id].clone()
     }
 }
 
+impl Signature for TypeChecker {
+    fn constructors(&mut self, ty: usize) -> Option<Vec<Constructor>> {
+        match self.find_type(ty) {
+            Type::Enum(_, variants) => {
+                Some(variants.keys().cloned().map(Constructor::Variant).collect())
+            }
+            Type::Bool => Some(vec![Constructor::Bool(true), Constructor::Bool(false)]),
+            Type::Tuple(tys) => Some(vec![Constructor::Tuple(tys.len())]),
+            _ => None,
+        }
+    }
+
+    fn fields(&mut self, ty: usize, constructor: &Constructor) -> Vec<usize> {
+        match (self.find_type(ty), constructor) {
+            (Type::Enum(_, variants), Constructor::Variant(variant))
+                if variants.contains_key(variant) =>
+            {
+                vec![variants[variant]]
+            }
+            (Type::Tuple(tys), Constructor::Tuple(_)) => tys,
+            (_, Constructor::Variant(_)) => vec![self.push_type(Type::Unknown)],
+            (_, Constructor::Tuple(size)) => {
+                (0..*size).map(|_| self.push_type(Type::Unknown)).collect()
+            }
+            (_, Constructor::Bool(_) | Constructor::Int(_) | Constructor::Str(_)) => Vec::new(),
+        }
+    }
+}
+
+/// Checks if running the statement can reach the end of it. It can't if
+/// every path through it ends with a `ret`, `break`, `continue` or `<!>`.
+fn reaches_end(statement: &Statement) -> bool {
+    match &statement.kind {
+        StatementKind::Ret { .. }
+        | StatementKind::Break
+        | StatementKind::Continue
+        | StatementKind::Unreachable => false,
+
+        StatementKind::If { pass, fail, .. } => reaches_end(pass) || reaches_end(fail),
+
+        StatementKind::Case { branches, fall_through, .. } => {
+            branches.iter().any(|branch| reaches_end(&branch.body))
+                || match fall_through {
+                    Some(fall_through) => reaches_end(fall_through),
+                    // The branches cover every value.
+                    None => false,
+                }
+        }
+
+        StatementKind::Block { statements } => statements.iter().all(reaches_end),
+
+        // Only a `break` can end a loop without a condition.
+        StatementKind::Loop { condition, body } => {
+            !matches!(condition.kind, ExpressionKind::Bool(true)) || breaks(body)
+        }
+
+        // There might not be any elements.
+        StatementKind::LoopIn { .. } => true,
+
+        StatementKind::Assignment { .. }
+        | StatementKind::Blob { .. }
+        | StatementKind::Enum { .. }
+        | StatementKind::Definition { .. }
+        | StatementKind::EmptyStatement
+        | StatementKind::ExternalDefinition { .. }
+        | StatementKind::IsCheck { .. }
+        | StatementKind::StatementExpression { .. }
+        | StatementKind::FromUse { .. }
+        | StatementKind::Use { .. } => true,
+    }
+}
+
+/// Checks if the statement does nothing, or only `<!>`. An `else` like that
+/// is kept in case variants are added.
+fn is_placeholder(statement: &Statement) -> bool {
+    match &statement.kind {
+        StatementKind::Block { statements } => statements.iter().all(is_placeholder),
+        StatementKind::Unreachable | StatementKind::EmptyStatement => true,
+        _ => false,
+    }
+}
+
+/// Checks if the statement contains a `break` for the loop it's in.
+fn breaks(statement: &Statement) -> bool {
+    match &statement.kind {
+        StatementKind::Break => true,
+
+        StatementKind::If { pass, fail, .. } => breaks(pass) || breaks(fail),
+        StatementKind::Case { branches, fall_through, .. } => {
+            branches.iter().any(|branch| breaks(&branch.body))
+                || match fall_through {
+                    Some(fall_through) => breaks(fall_through),
+                    None => false,
+                }
+        }
+        StatementKind::Block { statements } => statements.iter().any(breaks),
+
+        // A `break` in an inner loop ends the inner loop.
+        StatementKind::Loop { .. } | StatementKind::LoopIn { .. } => false,
+
+        _ => false,
+    }
+}
+
+/// The types of the globals and the types that are defined,
+/// keyed by namespace and name.
+pub(crate) type GlobalTypes = HashMap<(usize, String), RuntimeType>;
+
+/// Checks the types of the program, and gives back the warnings
+/// together with the types of everything that is defined globally.
 pub(crate) fn solve(
     statements: &Vec<(&Statement, usize)>,
     namespace_to_file: &HashMap<usize, PathBuf>,
     functions: &HashMap<String, (usize, RustFunction, ParserType)>,
-) -> TypeResult<()> {
-    TypeChecker::new(namespace_to_file, functions).solve(statements)
+    externals: &HashMap<String, ParserType>,
+    needs_start: bool,
+) -> TypeResult<(Vec<Error>, GlobalTypes)> {
+    let mut typechecker = TypeChecker::new(namespace_to_file, functions);
+    typechecker.externals = externals.clone();
+    typechecker.needs_start = needs_start;
+    typechecker.solve(statements)?;
+    let types = typechecker
+        .globals
+        .clone()
+        .into_iter()
+        .filter_map(|(key, name)| match name {
+            Name::Global(var) => Some((key, typechecker.bake_type(var.ty))),
+            Name::Type(ty) => Some((key, typechecker.bake_type(ty))),
+            Name::Namespace(_) => None,
+        })
+        .collect();
+    Ok((typechecker.warnings, types))
 }
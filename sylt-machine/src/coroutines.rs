@@ -0,0 +1,162 @@
+//! Suspending and resuming coroutines.
+//!
+//! A coroutine runs on the stack of the VM, right above the slot it was
+//! called from. When it yields, its frames and its part of the stack are
+//! moved into the [Coroutine], and the value it yields is left where the
+//! call returns. Resuming moves everything back, on top of the new caller.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use sylt_common::error::{Error, RuntimeError};
+use sylt_common::{Coroutine, CoroutineState, Frame, Machine, Op, OpResult, Value};
+
+use crate::VM;
+
+/// A coroutine that is running, or is suspended in a nested resume.
+pub(crate) struct Running {
+    coroutine: Rc<RefCell<Coroutine>>,
+    /// Where the function of the coroutine is on the stack.
+    base: usize,
+    /// The number of frames below the coroutine.
+    depth: usize,
+    /// Yielding through an external function isn't possible,
+    /// since it isn't on the stack of the VM.
+    nested_calls: usize,
+    /// The coroutine was resumed by a tail call, so the caller
+    /// returns whatever the coroutine gives back.
+    returns: bool,
+}
+
+impl VM {
+    /// Resumes the coroutine called at `new_base`, the arguments are
+    /// above it on the stack.
+    pub(crate) fn resume(
+        &mut self,
+        coroutine: Rc<RefCell<Coroutine>>,
+        new_base: usize,
+        returns: bool,
+    ) -> Result<OpResult, Error> {
+        match coroutine.borrow().state {
+            CoroutineState::Suspended => {}
+            CoroutineState::Running => {
+                return Err(self.error(RuntimeError::ResumeRunning, None));
+            }
+            CoroutineState::Done => {
+                return Err(self.error(RuntimeError::ResumeFinished, None));
+            }
+        }
+        self.check_call_depth()?;
+
+        let args = self.stack.split_off(new_base + 1);
+        let base = new_base + 1;
+        let depth = self.frames.len();
+
+        let mut co = coroutine.borrow_mut();
+        co.state = CoroutineState::Running;
+        self.stack.append(&mut co.stack);
+        if co.is_started() {
+            for mut frame in co.frames.drain(..) {
+                frame.stack_offset += base;
+                self.frames.push(frame);
+            }
+            for (slot, upvalue) in co.upvalues.drain(..) {
+                let slot = base + slot;
+                self.stack[slot] = upvalue.borrow_mut().open(slot);
+                self.upvalues.insert(slot, upvalue);
+            }
+            // The yield evaluates to what the coroutine is resumed with.
+            self.push(args.into_iter().next().unwrap_or(Value::Nil));
+        } else {
+            let block = match &self.stack[base] {
+                Value::Function(_, block) => *block,
+                _ => unreachable!("Coroutines are made from functions"),
+            };
+            if let Some(profiler) = &mut self.profiler {
+                profiler.call(block);
+            }
+            self.stack.extend(args);
+            self.frames.push(Frame {
+                stack_offset: base,
+                block: Rc::clone(&self.blocks[block]),
+                ip: 0,
+                contains_upvalues: true,
+            });
+        }
+        drop(co);
+
+        self.coroutines.push(Running {
+            coroutine,
+            base,
+            depth,
+            nested_calls: self.nested_calls,
+            returns,
+        });
+        Ok(OpResult::Continue)
+    }
+
+    /// Suspends the running coroutine, and gives `value` to its caller.
+    pub(crate) fn suspend(&mut self, value: Value) -> Result<OpResult, Error> {
+        match self.coroutines.last() {
+            Some(running) if running.nested_calls == self.nested_calls => {}
+            _ => return Err(self.error(RuntimeError::YieldOutsideCoroutine, None)),
+        }
+        let running = self.coroutines.pop().unwrap();
+        let base = running.base;
+
+        // Continues after the yield when it's resumed.
+        self.frame_mut().ip += 1;
+
+        let mut co = running.coroutine.borrow_mut();
+        co.state = CoroutineState::Suspended;
+        let mut captured: Vec<_> = self
+            .upvalues
+            .keys()
+            .copied()
+            .filter(|slot| *slot >= base)
+            .collect();
+        captured.sort_unstable();
+        for slot in captured {
+            let upvalue = self.upvalues.remove(&slot).unwrap();
+            upvalue.borrow_mut().close(self.stack[slot].clone());
+            co.upvalues.push((slot - base, upvalue));
+        }
+        co.frames = self
+            .frames
+            .split_off(running.depth)
+            .into_iter()
+            .map(|mut frame| {
+                frame.stack_offset -= base;
+                frame
+            })
+            .collect();
+        co.stack = self.stack.split_off(base);
+        drop(co);
+
+        self.give_back(running, value)
+    }
+
+    /// Whether the last frame of the running coroutine has returned.
+    pub(crate) fn coroutine_returned(&self) -> bool {
+        matches!(self.coroutines.last(), Some(running) if running.depth == self.frames.len())
+    }
+
+    /// Finishes the running coroutine, its return value is on the stack.
+    pub(crate) fn finish_coroutine(&mut self) -> Result<OpResult, Error> {
+        let running = self.coroutines.pop().unwrap();
+        let value = self.pop();
+        running.coroutine.borrow_mut().state = CoroutineState::Done;
+        self.give_back(running, value)
+    }
+
+    /// Replaces the coroutine on the stack with the value it gave back.
+    fn give_back(&mut self, running: Running, value: Value) -> Result<OpResult, Error> {
+        self.stack.truncate(running.base);
+        self.stack[running.base - 1] = value;
+        if running.returns {
+            self.eval_op(Op::Return)
+        } else {
+            self.frame_mut().ip += 1;
+            Ok(OpResult::Continue)
+        }
+    }
+}
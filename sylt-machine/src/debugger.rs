@@ -0,0 +1,311 @@
+//! A source level debugger for the VM.
+//!
+//! The debugger reads one command per line, and writes a prompt when it
+//! waits for the next one. This makes it usable both by hand and by editors
+//! that talk to it over stdin and stdout.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use sylt_common::error::Error;
+use sylt_common::{Machine, OpResult, Value};
+
+use crate::VM;
+
+const PROMPT: &str = "(debug) ";
+
+const HELP: &str = "\
+break [file:]line   stop when the line is reached, short 'b'
+clear [file:]line   remove a breakpoint
+breakpoints         list all breakpoints
+continue            run until a breakpoint, short 'c'
+step                run to the next line, entering calls, short 's'
+next                run to the next line, skipping calls, short 'n'
+finish              run until the function returns, short 'f'
+locals              show the variables in the frame
+upvalues            show the variables the frame has captured
+globals             show the global variables
+print name          show a variable, short 'p'
+backtrace           show the stackframes, short 'bt'
+frame n             select the frame to look at
+quit                stop the program, short 'q'";
+
+/// How far to run before stopping again.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    Continue,
+    Step,
+    Next,
+    Finish,
+    Quit,
+}
+
+/// Where the VM is. The debugger stops when this changes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Location {
+    depth: usize,
+    block: *const (),
+    line: usize,
+}
+
+impl Location {
+    fn of(vm: &VM) -> Self {
+        let frame = vm.frame();
+        Self {
+            depth: vm.frames.len(),
+            block: Rc::as_ptr(&frame.block) as *const (),
+            line: frame.block.borrow().line(frame.ip),
+        }
+    }
+}
+
+struct Debugger<'a> {
+    input: &'a mut dyn BufRead,
+    output: &'a mut dyn Write,
+
+    breakpoints: Vec<(PathBuf, usize)>,
+    /// The frame that is inspected, an index into `VM::frames`.
+    selected: usize,
+    sources: HashMap<PathBuf, Vec<String>>,
+}
+
+impl VM {
+    /// Runs the program like [VM::run], but stops before the first line and
+    /// waits for commands from `input`. Everything is written to `output`.
+    pub fn debug(
+        &mut self,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> Result<OpResult, Error> {
+        let mut debugger = Debugger {
+            input,
+            output,
+            breakpoints: Vec::new(),
+            selected: 0,
+            sources: HashMap::new(),
+        };
+        debugger
+            .run(self)
+            .unwrap_or_else(|e| Err(Error::IOError(Rc::new(e))))
+    }
+}
+
+impl<'a> Debugger<'a> {
+    fn run(&mut self, vm: &mut VM) -> io::Result<Result<OpResult, Error>> {
+        writeln!(self.output, "Type 'help' to list the commands")?;
+        let mut mode = self.stopped(vm, "started")?;
+        let mut from = Location::of(vm);
+        let mut previous = from;
+        loop {
+            if mode == Mode::Quit {
+                return Ok(Ok(OpResult::Done));
+            }
+
+            let here = Location::of(vm);
+            if here != previous && self.should_stop(vm, mode, &from, &here) {
+                mode = self.stopped(vm, "stopped")?;
+                from = here;
+            }
+            previous = here;
+
+            match vm.check_limits().and_then(|_| vm.eval_op(vm.op())) {
+                Ok(OpResult::Done) => {
+                    writeln!(self.output, "exited")?;
+                    return Ok(Ok(OpResult::Done));
+                }
+                Ok(OpResult::Continue) => {}
+                Err(err) => {
+                    // The state is kept, so the crash can be inspected.
+                    write!(self.output, "{}", err)?;
+                    self.stopped(vm, "crashed")?;
+                    return Ok(Err(err));
+                }
+            }
+        }
+    }
+
+    fn should_stop(&self, vm: &VM, mode: Mode, from: &Location, here: &Location) -> bool {
+        let block = vm.frame().block.borrow();
+        let breakpoint = self
+            .breakpoints
+            .iter()
+            .any(|(file, line)| *line == here.line && block.file.ends_with(file));
+        if breakpoint {
+            return true;
+        }
+        // Line 0 is code the compiler added.
+        if here.line == 0 {
+            return false;
+        }
+        match mode {
+            Mode::Continue | Mode::Quit => false,
+            Mode::Step => true,
+            Mode::Next => here.depth <= from.depth,
+            Mode::Finish => here.depth < from.depth,
+        }
+    }
+
+    /// Shows where the VM is, and handles commands until the
+    /// user wants to continue.
+    fn stopped(&mut self, vm: &VM, reason: &str) -> io::Result<Mode> {
+        self.selected = vm.frames.len() - 1;
+        let (file, line) = {
+            let frame = vm.frame();
+            let block = frame.block.borrow();
+            (block.file.clone(), block.line(frame.ip))
+        };
+        writeln!(self.output, "{} at {}:{}", reason, file.display(), line)?;
+        if let Some(source) = self.source_line(&file, line) {
+            writeln!(self.output, "{:>4} | {}", line, source)?;
+        }
+
+        loop {
+            write!(self.output, "{}", PROMPT)?;
+            self.output.flush()?;
+
+            let mut command = String::new();
+            if self.input.read_line(&mut command)? == 0 {
+                return Ok(Mode::Quit);
+            }
+            let words: Vec<_> = command.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["continue" | "c"] => return Ok(Mode::Continue),
+                ["step" | "s"] => return Ok(Mode::Step),
+                ["next" | "n"] => return Ok(Mode::Next),
+                ["finish" | "f"] => return Ok(Mode::Finish),
+                ["quit" | "q"] => return Ok(Mode::Quit),
+                ["break" | "b", at] => match self.parse_location(vm, at) {
+                    Some(breakpoint) => {
+                        writeln!(
+                            self.output,
+                            "breakpoint at {}:{}",
+                            breakpoint.0.display(),
+                            breakpoint.1
+                        )?;
+                        self.breakpoints.push(breakpoint);
+                    }
+                    None => writeln!(self.output, "error: expected [file:]line")?,
+                },
+                ["clear", at] => match self.parse_location(vm, at) {
+                    Some(breakpoint) => self.breakpoints.retain(|bp| *bp != breakpoint),
+                    None => writeln!(self.output, "error: expected [file:]line")?,
+                },
+                ["breakpoints"] => {
+                    for (file, line) in self.breakpoints.iter() {
+                        writeln!(self.output, "{}:{}", file.display(), line)?;
+                    }
+                }
+                ["locals"] => self.write_variables(&locals(vm, self.selected))?,
+                ["upvalues"] => self.write_variables(&upvalues(vm, self.selected))?,
+                ["globals"] => self.write_variables(&globals(vm))?,
+                ["print" | "p", name] => {
+                    let variable = locals(vm, self.selected)
+                        .into_iter()
+                        .rev()
+                        .chain(upvalues(vm, self.selected))
+                        .chain(globals(vm))
+                        .find(|(var, _)| var == name);
+                    match variable {
+                        Some(variable) => self.write_variables(&[variable])?,
+                        None => writeln!(self.output, "error: no variable called '{}'", name)?,
+                    }
+                }
+                ["backtrace" | "bt"] => self.write_backtrace(vm)?,
+                ["frame", n] => match n.parse::<usize>() {
+                    Ok(n) if n < vm.frames.len() => self.selected = n,
+                    _ => writeln!(self.output, "error: there is no frame {}", n)?,
+                },
+                ["help" | "h"] => writeln!(self.output, "{}", HELP)?,
+                _ => writeln!(
+                    self.output,
+                    "error: unknown command '{}', try 'help'",
+                    command.trim()
+                )?,
+            }
+        }
+    }
+
+    /// Parses `file:line` or `line`, where the file defaults
+    /// to the file of the selected frame.
+    fn parse_location(&self, vm: &VM, at: &str) -> Option<(PathBuf, usize)> {
+        let (file, line) = match at.rsplit_once(':') {
+            Some((file, line)) => (PathBuf::from(file), line),
+            None => (vm.frames[self.selected].block.borrow().file.clone(), at),
+        };
+        Some((file, line.parse().ok()?))
+    }
+
+    fn source_line(&mut self, file: &Path, line: usize) -> Option<String> {
+        if !self.sources.contains_key(file) {
+            let source = std::fs::read_to_string(file).ok()?;
+            self.sources.insert(
+                file.to_path_buf(),
+                source.lines().map(String::from).collect(),
+            );
+        }
+        self.sources[file].get(line.checked_sub(1)?).cloned()
+    }
+
+    fn write_variables(&mut self, variables: &[(String, Value)]) -> io::Result<()> {
+        for (name, value) in variables.iter() {
+            writeln!(self.output, "{} = {:?}", name, value)?;
+        }
+        Ok(())
+    }
+
+    fn write_backtrace(&mut self, vm: &VM) -> io::Result<()> {
+        for (i, frame) in vm.frames.iter().enumerate().rev() {
+            let block = frame.block.borrow();
+            writeln!(
+                self.output,
+                "{}{} {} at {}:{}",
+                if i == self.selected { "*" } else { " " },
+                i,
+                block.name,
+                block.file.display(),
+                block.line(frame.ip)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The local variables of a frame that are in scope.
+fn locals(vm: &VM, frame: usize) -> Vec<(String, Value)> {
+    let frame = &vm.frames[frame];
+    let block = frame.block.borrow();
+    block
+        .locals_at(frame.ip)
+        .filter_map(|local| {
+            let value = vm.stack.get(frame.stack_offset + local.slot)?;
+            Some((local.name.clone(), value.clone()))
+        })
+        .collect()
+}
+
+/// The variables the function of a frame has captured.
+fn upvalues(vm: &VM, frame: usize) -> Vec<(String, Value)> {
+    let frame = &vm.frames[frame];
+    let block = frame.block.borrow();
+    match &vm.stack[frame.stack_offset] {
+        Value::Function(ups, _) => block
+            .upvalue_names
+            .iter()
+            .zip(ups.iter())
+            .map(|(name, up)| (name.clone(), up.borrow().get(&vm.stack)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The globals live at the bottom of the stack, and are
+/// described by the block of the preamble.
+fn globals(vm: &VM) -> Vec<(String, Value)> {
+    let block = vm.blocks[0].borrow();
+    block
+        .locals_at(0)
+        .filter_map(|local| Some((local.name.clone(), vm.stack.get(local.slot)?.clone())))
+        .collect()
+}
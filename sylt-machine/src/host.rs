@@ -0,0 +1,134 @@
+//! Calling into a program from the program that embeds the VM.
+//!
+//! [VM::define_globals] runs the program up to where `start` would be
+//! called. The preamble is then left waiting, and functions can be called
+//! on top of it as many times as needed.
+//!
+//! Values that are given to the host are held by the VM, so the collector
+//! doesn't break them while the host still uses them. A held value is let
+//! go of when the host drops it - unless it's part of a cycle, then it's
+//! kept until the VM is initialized again.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use sylt_common::error::Error;
+use sylt_common::{Machine, Op, RustClosure, Value};
+
+use crate::VM;
+
+impl VM {
+    /// Gives the value of a global in the main file of the program.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        let main = &self.blocks.first()?.borrow().file;
+        self.globals
+            .iter()
+            .find(|global| global.name == name && &global.file == main)
+            .and_then(|global| self.stack.get(global.slot).cloned())
+            .map(|value| {
+                self.hold(&value);
+                value
+            })
+    }
+
+    /// Runs the definitions of the globals, and stops before `start` is
+    /// called. Calling [VM::run] after this calls `start`.
+    pub fn define_globals(&mut self) -> Result<(), Error> {
+        let start = {
+            let main = &self.blocks[0].borrow().file;
+            self.globals
+                .iter()
+                .find(|global| global.name == "start" && &global.file == main)
+                .map(|global| global.slot)
+        };
+        loop {
+            if self.frames.len() == 1 {
+                let frame = self.frame();
+                let block = frame.block.borrow();
+                let ops = &block.ops[frame.ip..];
+                if matches!(ops, [Op::ReadGlobal(slot), Op::Call(0), ..] if Some(*slot) == start) {
+                    return Ok(());
+                }
+            }
+            self.check_limits()?;
+            self.eval_op(self.op())?;
+        }
+    }
+
+    /// Calls a function with the arguments, and runs until it returns.
+    ///
+    /// The VM has to be waiting, after [VM::define_globals] or between
+    /// calls. If the call fails, the VM is put back the way it was before
+    /// the call.
+    pub fn call(&mut self, callable: &Value, args: &[Value]) -> Result<Value, Error> {
+        if let Some(error) = &self.stopped {
+            return Err(error.clone());
+        }
+        let depth = self.frames.len();
+        let base = self.stack.len();
+        let ip = self.frame().ip;
+
+        self.hold(callable);
+        args.iter().for_each(|arg| self.hold(arg));
+        self.push(callable.clone());
+        self.stack.extend(args.iter().cloned());
+        if let Err(err) = self.run_call(args.len()) {
+            let mut open: Vec<_> = self
+                .upvalues
+                .keys()
+                .copied()
+                .filter(|slot| *slot >= base)
+                .collect();
+            open.sort_unstable();
+            for slot in open {
+                let value = self.stack[slot].clone();
+                self.drop_upvalue(slot, value);
+            }
+            self.frames.truncate(depth);
+            self.stack.truncate(base);
+            self.coroutines.clear();
+            // The external functions the call was in are gone too.
+            self.stopped = None;
+            self.frame_mut().ip = ip;
+            return Err(err);
+        }
+
+        self.frame_mut().ip = ip;
+        let value = self.pop();
+        self.hold(&value);
+        Ok(value)
+    }
+
+    /// Replaces the extern function in `slot`, with a function that can
+    /// hold state.
+    pub fn link_closure(&mut self, slot: usize, function: RustClosure) {
+        self.extern_functions[slot] = function;
+    }
+}
+
+/// Adds the objects in the value that the collector could break to `held`.
+pub(crate) fn objects(value: &Value, held: &mut HashMap<usize, Value>) {
+    match value {
+        Value::Blob(_) | Value::List(_) | Value::Set(_) | Value::Dict(_) | Value::Coroutine(_) => {
+            held.insert(value.unique_id(), value.clone());
+        }
+        Value::Function(upvalues, _) if !upvalues.is_empty() => {
+            held.insert(value.unique_id(), value.clone());
+        }
+        Value::Tuple(values) => values.iter().for_each(|value| objects(value, held)),
+        Value::Variant(_, value) => objects(value, held),
+        _ => {}
+    }
+}
+
+/// Whether anything but the VM's copy references the held object.
+pub(crate) fn is_shared(value: &Value) -> bool {
+    match value {
+        Value::Blob(b) => Rc::strong_count(b) > 1,
+        Value::List(l) => Rc::strong_count(l) > 1,
+        Value::Set(s) => Rc::strong_count(s) > 1,
+        Value::Dict(d) => Rc::strong_count(d) > 1,
+        Value::Coroutine(c) => Rc::strong_count(c) > 1,
+        Value::Function(upvalues, _) => Rc::strong_count(upvalues) > 1,
+        _ => false,
+    }
+}
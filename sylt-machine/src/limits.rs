@@ -0,0 +1,106 @@
+//! Limits on what a program may use, for running code that can't be trusted.
+//!
+//! When a limit is reached the VM stops with an error before the next op, and
+//! keeps its state. Raising the limit and calling [VM::run] again continues
+//! the program where it stopped, which can be used to run several programs a
+//! few ops at a time.
+//!
+//! A limit that is reached in a function called by an external function, like
+//! the function given to `for_each`, can't be continued from. The external
+//! function has already given up, so the VM keeps returning the error instead.
+
+use sylt_common::error::{Error, RuntimeError};
+
+use crate::VM;
+
+/// How many ops are run between measuring the heap.
+const HEAP_CHECK_INTERVAL: usize = 1024;
+
+/// The resources a program may use, `None` means there is no limit.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    /// The number of ops the program may run from when the limits are set.
+    pub ops: Option<usize>,
+    /// The number of stackframes.
+    pub call_depth: Option<usize>,
+    /// The number of values on the stack.
+    pub stack_size: Option<usize>,
+    /// The number of values stored in lists, sets, dicts, blobs and
+    /// captured variables. The heap is measured every few ops.
+    pub heap_size: Option<usize>,
+}
+
+impl VM {
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+        self.last_op = limits.ops.map(|ops| self.ops_run + ops);
+        self.next_heap_check = self.ops_run;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Lets the program run `ops` more ops. If the program ran out of ops,
+    /// it continues where it stopped on the next call to [VM::run] - unless
+    /// it ran out in a call from an external function.
+    pub fn grant_ops(&mut self, ops: usize) {
+        let last_op = self
+            .last_op
+            .map_or(self.ops_run, |last| last.max(self.ops_run));
+        self.last_op = Some(last_op + ops);
+    }
+
+    /// Checks the limits before the next op is run.
+    pub(crate) fn check_limits(&mut self) -> Result<(), Error> {
+        if let Some(error) = &self.stopped {
+            return Err(error.clone());
+        }
+        if let Some(last_op) = self.last_op {
+            if self.ops_run >= last_op {
+                let limit = self.limits.ops.unwrap_or(last_op);
+                return Err(self.limit_error(RuntimeError::OpLimit(limit)));
+            }
+        }
+        if let Some(limit) = self.limits.stack_size {
+            if self.stack.len() > limit {
+                return Err(self.limit_error(RuntimeError::StackLimit(limit)));
+            }
+        }
+        if let Some(limit) = self.limits.heap_size {
+            if self.ops_run >= self.next_heap_check {
+                self.next_heap_check = self.ops_run + HEAP_CHECK_INTERVAL;
+                if self.gc.heap_size() > limit {
+                    return Err(self.limit_error(RuntimeError::HeapLimit(limit)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the call depth before a new stackframe is added.
+    pub(crate) fn check_call_depth(&self) -> Result<(), Error> {
+        match self.limits.call_depth {
+            Some(limit) if self.frames.len() >= limit => {
+                Err(self.limit_error(RuntimeError::CallDepthLimit(limit)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The error comes with the stack, since the op where a limit is
+    /// reached is rarely where the problem is.
+    fn limit_error(&self, kind: RuntimeError) -> Error {
+        let stacktrace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let block = frame.block.borrow();
+                format!("in {} at line {}", block.name, block.line(frame.ip))
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.error(kind, Some(stacktrace))
+    }
+}
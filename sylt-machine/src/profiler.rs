@@ -0,0 +1,258 @@
+//! Records where a program spends its time.
+//!
+//! The profiler looks at the VM before every op, and gives the time since the
+//! last op to the place the VM was at then. This is done for every function,
+//! every line and every stack of calls - the stacks can be written in the
+//! folded format that flamegraph tools read.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use sylt_common::{Block, Frame};
+
+use crate::VM;
+
+/// How many lines are shown in the report.
+const REPORT_LINES: usize = 20;
+
+/// What was recorded for a function.
+#[derive(Debug, Clone)]
+pub struct BlockProfile {
+    pub name: String,
+    pub file: PathBuf,
+    /// How many times the function was called.
+    pub calls: usize,
+    /// How many ops were run in the function.
+    pub ops: usize,
+    /// The time spent in the function, not counting the functions it calls.
+    pub time: Duration,
+}
+
+/// What was recorded for a line.
+#[derive(Debug, Default, Clone, Copy)]
+struct LineProfile {
+    ops: usize,
+    time: Duration,
+}
+
+/// A unique stack of calls, the stacks form a tree.
+struct Node {
+    parent: Option<usize>,
+    block: usize,
+    time: Duration,
+}
+
+/// Where the last op was run.
+#[derive(Copy, Clone)]
+struct Location {
+    block: usize,
+    line: usize,
+    node: usize,
+}
+
+pub struct Profiler {
+    /// Finds the index of a block from the block a frame points to.
+    block_ids: HashMap<*const RefCell<Block>, usize>,
+    blocks: Vec<BlockProfile>,
+    /// Keyed by block and line.
+    lines: HashMap<(usize, usize), LineProfile>,
+
+    nodes: Vec<Node>,
+    children: HashMap<(Option<usize>, usize), usize>,
+    /// The node of every frame on the stack.
+    path: Vec<usize>,
+
+    started: Instant,
+    last: Instant,
+    at: Option<Location>,
+}
+
+impl Profiler {
+    fn new(blocks: &[Rc<RefCell<Block>>]) -> Self {
+        let now = Instant::now();
+        let mut profiler = Self {
+            block_ids: HashMap::new(),
+            blocks: Vec::new(),
+            lines: HashMap::new(),
+
+            nodes: Vec::new(),
+            children: HashMap::new(),
+            path: Vec::new(),
+
+            started: now,
+            last: now,
+            at: None,
+        };
+        profiler.add_blocks(blocks);
+        profiler
+    }
+
+    /// Called when the program is reloaded, with the blocks of the new program.
+    pub(crate) fn add_blocks(&mut self, blocks: &[Rc<RefCell<Block>>]) {
+        for block in blocks.iter() {
+            self.block_ids.insert(Rc::as_ptr(block), self.blocks.len());
+            let block = block.borrow();
+            self.blocks.push(BlockProfile {
+                name: block.name.clone(),
+                file: block.file.clone(),
+                calls: 0,
+                ops: 0,
+                time: Duration::ZERO,
+            });
+        }
+    }
+
+    /// Called before every op the VM runs.
+    pub(crate) fn sample(&mut self, frames: &[Frame]) {
+        let now = Instant::now();
+        self.spend(now);
+
+        // Calls and returns only change the top of the stack.
+        let depth = frames.len();
+        self.path.truncate(depth);
+        while self.path.len() < depth {
+            let block = self.block_id(&frames[self.path.len()]);
+            let node = self.node(self.path.last().copied(), block);
+            self.path.push(node);
+        }
+        let frame = &frames[depth - 1];
+        let block = self.block_id(frame);
+        let mut node = self.path[depth - 1];
+        if self.nodes[node].block != block {
+            // A tail call replaced the function in the frame.
+            node = self.node(self.nodes[node].parent, block);
+            self.path[depth - 1] = node;
+        }
+
+        let line = frame.block.borrow().line(frame.ip);
+        self.blocks[block].ops += 1;
+        self.lines.entry((block, line)).or_default().ops += 1;
+        self.at = Some(Location { block, line, node });
+    }
+
+    /// Called when a function is called.
+    pub(crate) fn call(&mut self, block: usize) {
+        self.blocks[block].calls += 1;
+    }
+
+    /// Gives the time since the last op to where it was run.
+    fn spend(&mut self, now: Instant) {
+        let time = now - self.last;
+        self.last = now;
+        if let Some(at) = self.at {
+            self.blocks[at.block].time += time;
+            self.lines.entry((at.block, at.line)).or_default().time += time;
+            self.nodes[at.node].time += time;
+        }
+    }
+
+    fn block_id(&self, frame: &Frame) -> usize {
+        self.block_ids[&Rc::as_ptr(&frame.block)]
+    }
+
+    fn node(&mut self, parent: Option<usize>, block: usize) -> usize {
+        let nodes = &mut self.nodes;
+        *self.children.entry((parent, block)).or_insert_with(|| {
+            nodes.push(Node { parent, block, time: Duration::ZERO });
+            nodes.len() - 1
+        })
+    }
+
+    /// What was recorded for every function, in the order of the blocks.
+    pub fn blocks(&self) -> &[BlockProfile] {
+        &self.blocks
+    }
+
+    /// The time from when the profiler started until the last op.
+    pub fn total_time(&self) -> Duration {
+        self.last - self.started
+    }
+
+    /// Writes a summary, the functions and lines that took the most time
+    /// come first.
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        let total_ops: usize = self.blocks.iter().map(|block| block.ops).sum();
+        writeln!(
+            out,
+            "total {} in {} ops\n",
+            format_time(self.total_time()),
+            total_ops
+        )?;
+
+        let mut blocks: Vec<_> = self.blocks.iter().filter(|block| block.ops > 0).collect();
+        blocks.sort_by(|a, b| b.time.cmp(&a.time));
+        writeln!(
+            out,
+            "{:>10} {:>10} {:>12}  function",
+            "calls", "ops", "time"
+        )?;
+        for block in blocks {
+            writeln!(
+                out,
+                "{:>10} {:>10} {:>12}  {}",
+                block.calls,
+                block.ops,
+                format_time(block.time),
+                block.name
+            )?;
+        }
+
+        let mut lines: Vec<_> = self
+            .lines
+            .iter()
+            .filter(|(&(_, line), _)| line != 0)
+            .collect();
+        lines.sort_by(|(_, a), (_, b)| b.time.cmp(&a.time));
+        writeln!(out, "\n{:>10} {:>12}  line", "ops", "time")?;
+        for (&(block, line), profile) in lines.into_iter().take(REPORT_LINES) {
+            writeln!(
+                out,
+                "{:>10} {:>12}  {}:{}",
+                profile.ops,
+                format_time(profile.time),
+                self.blocks[block].file.display(),
+                line
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes every stack of calls on a line, followed by the
+    /// nanoseconds spent there.
+    pub fn write_folded(&self, out: &mut dyn Write) -> io::Result<()> {
+        for node in self.nodes.iter().filter(|node| node.time > Duration::ZERO) {
+            let mut names = vec![self.blocks[node.block].name.as_str()];
+            let mut parent = node.parent;
+            while let Some(p) = parent {
+                names.push(self.blocks[self.nodes[p].block].name.as_str());
+                parent = self.nodes[p].parent;
+            }
+            names.reverse();
+            writeln!(out, "{} {}", names.join(";"), node.time.as_nanos())?;
+        }
+        Ok(())
+    }
+}
+
+fn format_time(time: Duration) -> String {
+    format!("{:.3}ms", time.as_secs_f64() * 1000.0)
+}
+
+impl VM {
+    /// Starts recording where the program spends its time, call this
+    /// after [VM::init]. Nothing is recorded unless the profiler is started.
+    pub fn start_profiling(&mut self) {
+        self.profiler = Some(Profiler::new(&self.blocks));
+    }
+
+    /// Stops the profiler and returns what it recorded.
+    pub fn stop_profiling(&mut self) -> Option<Profiler> {
+        let mut profiler = self.profiler.take()?;
+        profiler.spend(Instant::now());
+        profiler.at = None;
+        Some(profiler)
+    }
+}
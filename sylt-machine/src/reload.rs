@@ -0,0 +1,327 @@
+//! Swapping a new version of a program into the VM while it runs.
+//!
+//! The new blocks and constants are added next to the old ones, so the
+//! frames that are running keep the code they were called with, and every
+//! call after the reload runs the new code. Globals that the program
+//! changes keep their values, the rest are taken from the new program.
+//! The old code that can't run anymore is freed, and its slots are given
+//! to the code of the next reload.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use sylt_common::error::Error;
+use sylt_common::gc;
+use sylt_common::prog::Symbol;
+use sylt_common::{Block, BytecodeProg, Op, RustClosure, Type, Value};
+
+use crate::VM;
+
+/// What a global in the new program reads and writes.
+#[derive(Debug, Copy, Clone)]
+enum Target {
+    /// A global that was there before.
+    Slot(usize),
+    /// A new global that is never changed, reads of it become the constant.
+    Constant(usize),
+}
+
+/// The slots that are assigned outside of the preamble.
+pub(crate) fn assigned_globals(blocks: &[Rc<RefCell<Block>>]) -> HashSet<usize> {
+    blocks
+        .iter()
+        .skip(1)
+        .flat_map(|block| {
+            block
+                .borrow()
+                .ops
+                .iter()
+                .filter_map(|op| match op {
+                    Op::AssignGlobal(slot) => Some(*slot),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn key(symbol: &Symbol) -> (PathBuf, String) {
+    (symbol.file.clone(), symbol.name.clone())
+}
+
+/// Types that aren't known are assumed to be the same.
+fn same_type(a: &Type, b: &Type) -> bool {
+    matches!(a, Type::Unknown) || matches!(b, Type::Unknown) || a == b
+}
+
+/// Put in the slots of blocks that are freed, running it is an error.
+fn freed_block() -> Rc<RefCell<Block>> {
+    let mut block = Block::new("freed", 0, Path::new(""));
+    block.add(Op::Illegal, 0);
+    Rc::new(RefCell::new(block))
+}
+
+/// Gives out the slots in `free` before adding new ones to the end of `items`.
+fn allocate<T: Clone>(
+    items: &mut Vec<T>,
+    free: &mut Vec<usize>,
+    count: usize,
+    empty: T,
+) -> Vec<usize> {
+    (0..count)
+        .map(|_| {
+            free.pop().unwrap_or_else(|| {
+                items.push(empty.clone());
+                items.len() - 1
+            })
+        })
+        .collect()
+}
+
+impl VM {
+    /// Replaces the code of the running program with the code of `prog`,
+    /// which is a newer version of the program given to [VM::init].
+    ///
+    /// Functions and globals that are never changed after the program
+    /// started are replaced, the other globals keep their values. Nothing
+    /// is replaced if the new program can't run on the old state, the
+    /// errors explain why.
+    pub fn reload(&mut self, prog: &BytecodeProg) -> Result<(), Vec<Error>> {
+        let mut errors = Vec::new();
+        let error =
+            |file: &PathBuf, message: String| Error::ReloadError { file: file.clone(), message };
+
+        let old_blobs: HashMap<_, _> = self.blobs.iter().map(|b| (key(b), b)).collect();
+        for blob in prog.blobs.iter() {
+            match old_blobs.get(&key(blob)) {
+                Some(old) if !same_type(&old.ty, &blob.ty) => errors.push(error(
+                    &blob.file,
+                    format!(
+                        "The fields of the blob '{}' changed, restart the program to use them",
+                        blob.name
+                    ),
+                )),
+                _ => {}
+            }
+        }
+
+        let assigned = assigned_globals(&prog.blocks);
+
+        let old_globals: HashMap<_, _> = self.globals.iter().map(|g| (key(g), g)).collect();
+        let mut targets = HashMap::new();
+        let mut replaced = Vec::new();
+        for global in prog.globals.iter() {
+            let old = old_globals.get(&key(global));
+            // Globals that only the preamble sets are part of the code. The
+            // old code can still be running, so it mustn't assign them either.
+            let assigned = assigned.contains(&global.slot)
+                || old.map_or(false, |old| self.assigned_globals.contains(&old.slot));
+            let constant = global.constant.filter(|_| !assigned);
+            match (old, constant) {
+                (Some(old), Some(constant)) => {
+                    targets.insert(global.slot, Target::Slot(old.slot));
+                    replaced.push((old.slot, constant));
+                }
+                (Some(old), None) => {
+                    // Types that look the same differ in the fields of a
+                    // blob, which is reported for the blob.
+                    let looks_same = old.ty.to_string() == global.ty.to_string();
+                    if !same_type(&old.ty, &global.ty) && !looks_same {
+                        errors.push(error(
+                            &global.file,
+                            format!(
+                                "The type of '{}' changed from {} to {}, restart the program to use it",
+                                global.name, old.ty, global.ty
+                            ),
+                        ));
+                    }
+                    targets.insert(global.slot, Target::Slot(old.slot));
+                }
+                (None, Some(constant)) => {
+                    targets.insert(global.slot, Target::Constant(constant));
+                }
+                (None, None) => errors.push(error(
+                    &global.file,
+                    format!(
+                        "'{}' is a new global that changes, restart the program to add it",
+                        global.name
+                    ),
+                )),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        // Extern functions and strings that are already there are shared.
+        let function_slots: Vec<_> = prog
+            .function_names
+            .iter()
+            .zip(prog.functions.iter())
+            .map(|(name, function)| {
+                match self.function_names.iter().position(|other| other == name) {
+                    Some(slot) => slot,
+                    None => {
+                        self.extern_functions
+                            .push(Rc::new(*function) as RustClosure);
+                        self.function_names.push(name.clone());
+                        self.extern_functions.len() - 1
+                    }
+                }
+            })
+            .collect();
+        let string_slots: Vec<_> = prog
+            .strings
+            .iter()
+            .map(
+                |string| match self.strings.iter().position(|other| other == string) {
+                    Some(slot) => slot,
+                    None => {
+                        self.strings.push(string.clone());
+                        self.strings.len() - 1
+                    }
+                },
+            )
+            .collect();
+        let constant_slots = allocate(
+            &mut self.constants,
+            &mut self.free_constants,
+            prog.constants.len(),
+            Value::Nil,
+        );
+        let block_slots = allocate(
+            &mut self.blocks,
+            &mut self.free_blocks,
+            prog.blocks.len(),
+            freed_block(),
+        );
+
+        for (constant, slot) in prog.constants.iter().zip(constant_slots.iter()) {
+            self.constants[*slot] = match constant {
+                Value::Function(ups, block) => Value::Function(Rc::clone(ups), block_slots[*block]),
+                Value::ExternFunction(slot) => Value::ExternFunction(function_slots[*slot]),
+                constant => constant.clone(),
+            };
+        }
+
+        let blocks: Vec<_> = prog
+            .blocks
+            .iter()
+            .map(|block| {
+                let mut block = block.borrow().clone();
+                for op in block.ops.iter_mut() {
+                    *op = match *op {
+                        Op::Constant(c) => Op::Constant(constant_slots[c]),
+                        Op::Link(c) => Op::Link(constant_slots[c]),
+                        Op::GetField(s) => Op::GetField(string_slots[s]),
+                        Op::AssignField(s) => Op::AssignField(string_slots[s]),
+                        Op::ReadGlobal(slot) => match targets.get(&slot) {
+                            Some(Target::Slot(slot)) => Op::ReadGlobal(*slot),
+                            Some(Target::Constant(c)) => Op::Constant(constant_slots[*c]),
+                            None => Op::ReadGlobal(slot),
+                        },
+                        Op::AssignGlobal(slot) => match targets.get(&slot) {
+                            Some(Target::Slot(slot)) => Op::AssignGlobal(*slot),
+                            _ => Op::AssignGlobal(slot),
+                        },
+                        op => op,
+                    };
+                }
+                Rc::new(RefCell::new(block))
+            })
+            .collect();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.add_blocks(&blocks);
+        }
+        for (block, slot) in blocks.into_iter().zip(block_slots.iter()) {
+            self.blocks[*slot] = block;
+        }
+
+        for (slot, constant) in replaced {
+            self.stack[slot] = self.constants[constant_slots[constant]].clone();
+        }
+        for slot in assigned {
+            if let Some(Target::Slot(slot)) = targets.get(&slot) {
+                self.assigned_globals.insert(*slot);
+            }
+        }
+
+        // Globals that are removed are remembered, since they
+        // keep their slot if they are added again.
+        for global in prog.globals.iter() {
+            if let Some(Target::Slot(slot)) = targets.get(&global.slot) {
+                let old = self
+                    .globals
+                    .iter_mut()
+                    .find(|old| old.slot == *slot)
+                    .unwrap();
+                old.ty = global.ty.clone();
+            }
+        }
+        for blob in prog.blobs.iter() {
+            match self.blobs.iter_mut().find(|old| key(old) == key(blob)) {
+                Some(old) => old.ty = blob.ty.clone(),
+                None => self.blobs.push(blob.clone()),
+            }
+        }
+
+        self.free_unreachable_code();
+        Ok(())
+    }
+
+    /// Frees the blocks and constants that no frame and no value can reach.
+    /// The first block is kept, since it tells which file is the main file.
+    fn free_unreachable_code(&mut self) {
+        let values = gc::reachable_code(self.stack.iter().chain(self.held.get_mut().values()));
+        let mut todo: Vec<_> = self
+            .frames
+            .iter()
+            .map(|frame| Rc::clone(&frame.block))
+            .chain(values.frames)
+            .collect();
+        let mut functions: Vec<_> = values.functions.into_iter().chain(Some(0)).collect();
+        let mut blocks = HashSet::new();
+        let mut constants = HashSet::new();
+        loop {
+            for function in functions.drain(..) {
+                if blocks.insert(function) {
+                    todo.push(Rc::clone(&self.blocks[function]));
+                }
+            }
+            let block = match todo.pop() {
+                Some(block) => block,
+                None => break,
+            };
+            for op in block.borrow().ops.iter() {
+                if let Op::Constant(c) | Op::Link(c) = op {
+                    if constants.insert(*c) {
+                        let code = gc::reachable_code(std::iter::once(&self.constants[*c]));
+                        functions.extend(code.functions);
+                        todo.extend(code.frames);
+                    }
+                }
+            }
+        }
+
+        let freed = freed_block();
+        self.free_blocks = (0..self.blocks.len())
+            .filter(|block| !blocks.contains(block))
+            .collect();
+        for block in self.free_blocks.iter() {
+            self.blocks[*block] = Rc::clone(&freed);
+        }
+        self.free_constants = (0..self.constants.len())
+            .filter(|constant| !constants.contains(constant))
+            .collect();
+        for constant in self.free_constants.iter() {
+            self.constants[*constant] = Value::Nil;
+        }
+    }
+
+    /// How many blocks and constants the VM has room for. Slots that a
+    /// reload frees are reused, so reloading doesn't make this grow.
+    pub fn code_size(&self) -> (usize, usize) {
+        (self.blocks.len(), self.constants.len())
+    }
+}
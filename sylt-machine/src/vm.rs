@@ -4,11 +4,23 @@ use std::cell::RefCell;
 use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::rc::Rc;
 use sylt_common::error::{Error, RuntimeError};
+use sylt_common::gc::Collector;
+use sylt_common::prog::Symbol;
 use sylt_common::{
-    Block, BlockLinkState, BytecodeProg, Frame, Machine, Op, OpResult, RuntimeContext,
-    RustFunction, Type, UpValue, Value,
+    op, Block, BlockLinkState, BytecodeProg, Coroutine, Frame, GcStats, Machine, Op, OpResult,
+    RuntimeContext, RustClosure, Type, UpValue, Value,
 };
 
+mod coroutines;
+mod debugger;
+mod host;
+mod limits;
+mod profiler;
+mod reload;
+
+pub use limits::Limits;
+pub use profiler::{BlockProfile, Profiler};
+
 macro_rules! error {
     ( $thing:expr, $kind:expr) => {
         return Err($thing.error($kind, None));
@@ -52,14 +64,52 @@ pub struct VM {
     frames: Vec<Frame>,
     blocks: Vec<Rc<RefCell<Block>>>,
     args: Vec<String>,
+    /// The coroutines that are running, the innermost last.
+    coroutines: Vec<coroutines::Running>,
 
     constants: Vec<Value>,
     strings: Vec<String>,
+    /// What the program defines, used when it is reloaded.
+    globals: Vec<Symbol>,
+    blobs: Vec<Symbol>,
+    /// The globals that the code that has been loaded assigns, outside of
+    /// the preambles. They're never replaced when the program is reloaded.
+    assigned_globals: HashSet<usize>,
+    /// The slots in `blocks` and `constants` that nothing can reach after
+    /// a reload, the next reload puts its code there.
+    free_blocks: Vec<usize>,
+    free_constants: Vec<usize>,
 
     pub print_bytecode: bool,
     pub print_exec: bool,
 
-    extern_functions: Vec<RustFunction>,
+    /// How many ops have been run, used to measure optimizations.
+    pub ops_run: usize,
+
+    extern_functions: Vec<RustClosure>,
+    /// The names of the extern functions, in the same order as `extern_functions`.
+    function_names: Vec<String>,
+
+    gc: Collector,
+    /// How many calls from external functions are running. Values can
+    /// live outside of the VM during them, so nothing is collected.
+    nested_calls: usize,
+    /// The error from a call by an external function, reported
+    /// instead of the error from the external function.
+    callback_error: Option<Error>,
+    /// The first error from a call by an external function. The external
+    /// function has given up, so the program can't be continued.
+    stopped: Option<Error>,
+    /// Values the host holds, by id. They're roots for the collector until
+    /// the host drops them.
+    held: RefCell<HashMap<usize, Value>>,
+
+    profiler: Option<Profiler>,
+
+    limits: Limits,
+    /// When the program runs out of ops.
+    last_op: Option<usize>,
+    next_heap_check: usize,
 }
 
 impl VM {
@@ -71,14 +121,35 @@ impl VM {
             frames: Vec::new(),
             blocks: Vec::new(),
             args: Vec::new(),
+            coroutines: Vec::new(),
 
             constants: Vec::new(),
             strings: Vec::new(),
+            globals: Vec::new(),
+            blobs: Vec::new(),
+            assigned_globals: HashSet::new(),
+            free_blocks: Vec::new(),
+            free_constants: Vec::new(),
 
             print_bytecode: false,
             print_exec: false,
 
+            ops_run: 0,
+
             extern_functions: Vec::new(),
+            function_names: Vec::new(),
+
+            gc: Collector::new(),
+            nested_calls: 0,
+            callback_error: None,
+            stopped: None,
+            held: RefCell::new(HashMap::new()),
+
+            profiler: None,
+
+            limits: Limits::default(),
+            last_op: None,
+            next_heap_check: 0,
         }
     }
 
@@ -90,15 +161,24 @@ impl VM {
     }
 
     fn find_upvalue(&mut self, slot: usize) -> &mut Rc<RefCell<UpValue>> {
-        self.upvalues
-            .entry(slot)
-            .or_insert_with(|| Rc::new(RefCell::new(UpValue::new(slot))))
+        let gc = &mut self.gc;
+        self.upvalues.entry(slot).or_insert_with(|| {
+            let upvalue = Rc::new(RefCell::new(UpValue::new(slot)));
+            gc.track_upvalue(&upvalue);
+            upvalue
+        })
     }
 
     fn push(&mut self, value: Value) {
         self.stack.push(value);
     }
 
+    /// Pushes a value that was just allocated, so the collector knows about it.
+    fn push_new(&mut self, value: Value) {
+        self.gc.track(&value);
+        self.stack.push(value);
+    }
+
     fn pop(&mut self) -> Value {
         match self.stack.pop() {
             Some(x) => x,
@@ -211,12 +291,27 @@ impl VM {
         let block = Rc::clone(&prog.blocks[0]);
         self.constants = prog.constants.clone();
         self.strings = prog.strings.clone();
+        self.globals = prog.globals.clone();
+        self.blobs = prog.blobs.clone();
+        self.assigned_globals = reload::assigned_globals(&prog.blocks);
+        self.free_blocks.clear();
+        self.free_constants.clear();
         self.blocks = prog.blocks.clone();
         self.args = Vec::from(args);
 
-        self.extern_functions = prog.functions.clone();
+        self.extern_functions = prog
+            .functions
+            .iter()
+            .map(|f| Rc::new(*f) as RustClosure)
+            .collect();
+        self.function_names = prog.function_names.clone();
         self.stack.clear();
         self.frames.clear();
+        self.coroutines.clear();
+        self.upvalues.clear();
+        self.stopped = None;
+        self.held.get_mut().clear();
+        self.gc = Collector::new();
 
         self.push(Value::Function(Rc::new(Vec::new()), 0));
 
@@ -228,7 +323,8 @@ impl VM {
         });
     }
 
-    /// Simulates the program.
+    /// Simulates the program. If a limit was reached, this continues
+    /// where the program stopped.
     pub fn run(&mut self) -> Result<OpResult, Error> {
         if self.print_bytecode {
             println!("\n    [[{}]]\n", "RUNNING".red());
@@ -244,11 +340,48 @@ impl VM {
                 self.print_stack()
             }
 
+            self.check_limits()?;
+            let op = self.eval_op(self.op())?;
+            if matches!(op, OpResult::Done) {
+                return Ok(op);
+            }
+        }
+    }
+
+    /// Runs at most `ops` ops, [OpResult::Continue] means the program
+    /// hasn't finished. Used to do other work while a program runs.
+    pub fn run_for(&mut self, ops: usize) -> Result<OpResult, Error> {
+        for _ in 0..ops {
+            #[cfg(debug_assertions)]
+            if self.print_exec {
+                self.print_stack()
+            }
+
+            self.check_limits()?;
             let op = self.eval_op(self.op())?;
             if matches!(op, OpResult::Done) {
                 return Ok(op);
             }
         }
+        Ok(OpResult::Continue)
+    }
+
+    /// Calls the callable on top of the arguments, and runs until it returns.
+    fn run_call(&mut self, num_args: usize) -> Result<(), Error> {
+        // A resumed coroutine can add more than one frame.
+        let depth = self.frames.len();
+        self.eval_op(Op::Call(num_args))?;
+
+        while self.frames.len() > depth {
+            #[cfg(debug_assertions)]
+            if self.print_exec {
+                self.print_stack()
+            }
+
+            self.check_limits()?;
+            self.eval_op(self.op())?;
+        }
+        Ok(())
     }
 }
 
@@ -274,27 +407,55 @@ impl Machine for VM {
         // Since the Op::Call below isn't a compiled instruction, we need to store the current
         // instruction pointer and restore it when we return to this frame.
         let ip = self.frame().ip;
-        self.eval_op(Op::Call(num_args))?;
-
-        if !matches!(callable, Value::ExternFunction(..)) {
-            let cur_frame = self.frames.len();
-            while self.frames.len() >= cur_frame {
-                #[cfg(debug_assertions)]
-                if self.print_exec {
-                    self.print_stack()
-                }
-
-                self.eval_op(self.op())?;
-            }
+        self.nested_calls += 1;
+        let result = self.run_call(num_args);
+        self.nested_calls -= 1;
+        if let Err(err) = result {
+            self.callback_error.get_or_insert_with(|| err.clone());
+            self.stopped.get_or_insert_with(|| err.clone());
+            return Err(err);
         }
+
         // Restore the instruction pointer.
         self.frame_mut().ip = ip;
         // Take the return value from the stack.
         Ok(self.pop())
     }
 
+    fn gc_stats(&self) -> GcStats {
+        self.gc.stats()
+    }
+
+    /// Collects the garbage cycles, unless an external function is calling
+    /// back into the VM.
+    fn collect_garbage(&mut self) {
+        if self.nested_calls == 0 {
+            let held = self.held.get_mut();
+            held.retain(|_, value| crate::host::is_shared(value));
+            self.gc.collect(
+                self.stack
+                    .iter()
+                    .chain(self.constants.iter())
+                    .chain(held.values()),
+                self.upvalues.values(),
+            );
+        }
+    }
+
+    fn hold(&self, value: &Value) {
+        crate::host::objects(value, &mut self.held.borrow_mut());
+    }
+
     /// Runs a single operation on the VM
     fn eval_op(&mut self, op: Op) -> Result<OpResult, Error> {
+        self.ops_run += 1;
+        // Between ops, everything that's alive can be reached from the VM.
+        if self.gc.should_collect() {
+            self.collect_garbage();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.sample(&self.frames);
+        }
         match op {
             Op::Illegal => {
                 error!(self, RuntimeError::InvalidProgram);
@@ -315,7 +476,7 @@ impl Machine for VM {
 
             Op::List(size) => {
                 let values = self.stack.split_off(self.stack.len() - size);
-                self.stack.push(Value::List(Rc::new(RefCell::new(values))));
+                self.push_new(Value::List(Rc::new(RefCell::new(values))));
             }
 
             Op::Set(size) => {
@@ -324,7 +485,7 @@ impl Machine for VM {
                     .split_off(self.stack.len() - size)
                     .into_iter()
                     .collect();
-                self.stack.push(Value::Set(Rc::new(RefCell::new(values))));
+                self.push_new(Value::Set(Rc::new(RefCell::new(values))));
             }
 
             Op::Dict(size) => {
@@ -334,9 +495,19 @@ impl Machine for VM {
                     .chunks_exact(2)
                     .map(|a| (a[0].clone(), a[1].clone()))
                     .collect();
-                self.stack.push(Value::Dict(Rc::new(RefCell::new(values))));
+                self.push_new(Value::Dict(Rc::new(RefCell::new(values))));
             }
 
+            Op::Range => match self.poppop() {
+                (Value::Int(start), Value::Int(end)) => {
+                    self.push(Value::Range(start, end));
+                }
+                (start, end) => {
+                    self.push(Value::Nil);
+                    error!(self, RuntimeError::ValueError(op, vec![start, end]));
+                }
+            },
+
             Op::Tag => {
                 if let (value, Value::String(tag)) = self.poppop() {
                     self.stack.push(Value::Variant(tag, Box::new(value)));
@@ -465,6 +636,24 @@ impl Machine for VM {
                         }
                         self.stack.push(v[slot].clone());
                     }
+                    (Value::List(rc_v), Value::Range(start, end)) => {
+                        let v = rc_v.borrow();
+                        if start < 0 || end < start || v.len() < end as usize {
+                            self.stack.push(Value::Nil);
+                            let len = v.len();
+                            drop(v);
+                            error!(
+                                self,
+                                RuntimeError::IndexOutOfBounds(
+                                    Value::List(rc_v),
+                                    len,
+                                    end as usize
+                                )
+                            );
+                        }
+                        let slice = v[start as usize..end as usize].to_vec();
+                        self.push_new(Value::List(Rc::new(RefCell::new(slice))));
+                    }
                     (Value::Dict(dict), i) => {
                         self.push(
                             dict.as_ref()
@@ -581,6 +770,9 @@ impl Machine for VM {
                     (Value::Set(rc_v), e) => {
                         self.push(Value::Bool(rc_v.as_ref().borrow_mut().contains(&e)));
                     }
+                    (Value::Range(start, end), Value::Int(e)) => {
+                        self.push(Value::Bool(start <= e && e < end));
+                    }
                     (indexable, e) => {
                         self.push(Value::Nil);
                         error!(self, RuntimeError::IndexError(indexable, e));
@@ -588,6 +780,59 @@ impl Machine for VM {
                 }
             }
 
+            Op::Iter => {
+                let iterable = match self.pop() {
+                    iterable @ (Value::List(_) | Value::Tuple(_) | Value::Range(..)) => iterable,
+                    Value::Set(set) => {
+                        Value::Tuple(Rc::new(set.borrow().iter().cloned().collect()))
+                    }
+                    Value::Dict(dict) => Value::Tuple(Rc::new(
+                        dict.borrow()
+                            .iter()
+                            .map(|(k, v)| Value::Tuple(Rc::new(vec![k.clone(), v.clone()])))
+                            .collect(),
+                    )),
+                    value => {
+                        self.push(Value::Nil);
+                        self.push(Value::Int(0));
+                        error!(self, RuntimeError::ValueError(op, vec![value]));
+                    }
+                };
+                self.push(iterable);
+                self.push(Value::Int(0));
+            }
+
+            Op::IterNext(after) => {
+                let len = self.stack.len();
+                let index = match self.stack[len - 1] {
+                    Value::Int(index) => index as usize,
+                    _ => unreachable!("The index of a loop has to be an int"),
+                };
+                let element = match &self.stack[len - 2] {
+                    Value::List(list) => list.borrow().get(index).cloned(),
+                    Value::Tuple(tuple) => tuple.get(index).cloned(),
+                    Value::Range(start, end) => {
+                        let element = start + index as i64;
+                        if element < *end {
+                            Some(Value::Int(element))
+                        } else {
+                            None
+                        }
+                    }
+                    _ => unreachable!("Only lists, tuples and ranges are looped over"),
+                };
+                match element {
+                    Some(element) => {
+                        self.stack[len - 1] = Value::Int(index as i64 + 1);
+                        self.push(element);
+                    }
+                    None => {
+                        self.frame_mut().ip = after;
+                        return Ok(OpResult::Continue);
+                    }
+                }
+            }
+
             Op::GetField(field) => {
                 let inst = self.pop();
                 match inst {
@@ -655,6 +900,34 @@ impl Machine for VM {
                 two_op!(self, Op::Div, op::div);
             }
 
+            Op::Rem => {
+                two_op!(self, Op::Rem, op::rem);
+            }
+
+            Op::FloorDiv => {
+                two_op!(self, Op::FloorDiv, op::floor_div);
+            }
+
+            Op::BitAnd => {
+                two_op!(self, Op::BitAnd, op::bit_and);
+            }
+
+            Op::BitOr => {
+                two_op!(self, Op::BitOr, op::bit_or);
+            }
+
+            Op::BitXor => {
+                two_op!(self, Op::BitXor, op::bit_xor);
+            }
+
+            Op::ShiftLeft => {
+                two_op!(self, Op::ShiftLeft, op::shift_left);
+            }
+
+            Op::ShiftRight => {
+                two_op!(self, Op::ShiftRight, op::shift_right);
+            }
+
             Op::Equal => {
                 two_op!(self, Op::Equal, op::eq);
             }
@@ -691,6 +964,13 @@ impl Machine for VM {
                 }
             }
 
+            Op::JmpTrue(line) => {
+                if matches!(self.pop(), Value::Bool(true)) {
+                    self.frame_mut().ip = line;
+                    return Ok(OpResult::Continue);
+                }
+            }
+
             Op::JmpNPop(line, to_pop) => {
                 let hi = self.stack.len();
                 let lo = hi - to_pop;
@@ -760,9 +1040,13 @@ impl Machine for VM {
                             values.entry(name.clone()).or_insert(Value::Nil);
                         }
                         values.insert("_name".to_string(), Value::String(Rc::new(name)));
-                        self.push(Value::Blob(Rc::new(RefCell::new(values))));
+                        self.push_new(Value::Blob(Rc::new(RefCell::new(values))));
                     }
                     Value::Function(_, block) => {
+                        self.check_call_depth()?;
+                        if let Some(profiler) = &mut self.profiler {
+                            profiler.call(block);
+                        }
                         let inner = self.blocks[block].borrow();
 
                         #[cfg(debug_assertions)]
@@ -778,14 +1062,22 @@ impl Machine for VM {
                         return Ok(OpResult::Continue);
                     }
                     Value::ExternFunction(slot) => {
-                        let extern_func = self.extern_functions[slot];
+                        let extern_func = Rc::clone(&self.extern_functions[slot]);
+                        self.callback_error = None;
                         let ctx = RuntimeContext { stack_base: new_base + 1, machine: self };
                         let res = match extern_func(ctx) {
                             Ok(value) => value,
-                            Err(ek) => error!(self, ek, "Failed in external function"),
+                            Err(ek) => match self.callback_error.take() {
+                                Some(err) => return Err(err),
+                                None => error!(self, ek, "Failed in external function"),
+                            },
                         };
                         self.stack.truncate(new_base);
-                        self.push(res);
+                        // The function might have allocated the value.
+                        self.push_new(res);
+                    }
+                    Value::Coroutine(coroutine) => {
+                        return self.resume(coroutine, new_base, false);
                     }
                     _ => {
                         unreachable!()
@@ -793,6 +1085,53 @@ impl Machine for VM {
                 }
             }
 
+            Op::TailCall(num_args) => {
+                let new_base = self.stack.len() - 1 - num_args;
+                let block = match &self.stack[new_base] {
+                    Value::Function(_, block) => *block,
+                    // The caller returns when the coroutine gives something back.
+                    Value::Coroutine(coroutine) => {
+                        let coroutine = Rc::clone(coroutine);
+                        return self.resume(coroutine, new_base, true);
+                    }
+                    // Only functions need a stackframe.
+                    _ => {
+                        self.eval_op(Op::Call(num_args))?;
+                        return self.eval_op(Op::Return);
+                    }
+                };
+
+                #[cfg(debug_assertions)]
+                if self.print_bytecode {
+                    self.blocks[block]
+                        .borrow()
+                        .debug_print(Some(&self.constants));
+                }
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.call(block);
+                }
+
+                // The locals of this frame are gone after the call,
+                // so upvalues pointing to them are closed.
+                let stack_offset = self.frame().stack_offset;
+                if self.frame().contains_upvalues {
+                    for slot in stack_offset..new_base {
+                        if self.upvalues.contains_key(&slot) {
+                            let value = self.stack[slot].clone();
+                            self.drop_upvalue(slot, value);
+                        }
+                    }
+                }
+                self.stack.drain(stack_offset..new_base);
+
+                let block = Rc::clone(&self.blocks[block]);
+                let frame = self.frame_mut();
+                frame.block = block;
+                frame.ip = 0;
+                frame.contains_upvalues = true;
+                return Ok(OpResult::Continue);
+            }
+
             Op::Return => {
                 let last = self.frames.pop().unwrap();
                 if self.frames.is_empty() {
@@ -808,138 +1147,29 @@ impl Machine for VM {
                         }
                     }
                     self.stack.truncate(last.stack_offset + 1);
+                    if self.coroutine_returned() {
+                        return self.finish_coroutine();
+                    }
                 }
             }
-        }
-        self.frame_mut().ip += 1;
-        Ok(OpResult::Continue)
-    }
-}
-
-///
-/// Module with all the operators that can be applied
-/// to values.
-///
-/// Broken out because they need to be recursive.
-mod op {
-    use super::Rc;
-    use super::Value;
-
-    fn tuple_bin_op(
-        a: &Rc<Vec<Value>>,
-        b: &Rc<Vec<Value>>,
-        f: fn(&Value, &Value) -> Value,
-    ) -> Value {
-        Value::Tuple(Rc::new(
-            a.iter().zip(b.iter()).map(|(a, b)| f(a, b)).collect(),
-        ))
-    }
-
-    fn tuple_un_op(a: &Rc<Vec<Value>>, f: fn(&Value) -> Value) -> Value {
-        Value::Tuple(Rc::new(a.iter().map(f).collect()))
-    }
-
-    pub fn neg(value: &Value) -> Value {
-        match value {
-            Value::Float(a) => Value::Float(-*a),
-            Value::Int(a) => Value::Int(-*a),
-            Value::Tuple(a) => tuple_un_op(a, neg),
-            _ => Value::Nil,
-        }
-    }
-
-    pub fn not(value: &Value) -> Value {
-        match value {
-            Value::Bool(a) => Value::Bool(!*a),
-            Value::Tuple(a) => tuple_un_op(a, not),
-            _ => Value::Nil,
-        }
-    }
-
-    pub fn add(a: &Value, b: &Value) -> Value {
-        match (a, b) {
-            (Value::Float(a), Value::Float(b)) => Value::Float(a + b),
-            (Value::Int(a), Value::Int(b)) => Value::Int(a + b),
-            (Value::String(a), Value::String(b)) => Value::String(Rc::from(format!("{}{}", a, b))),
-            (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, add),
-            _ => Value::Nil,
-        }
-    }
-
-    pub fn sub(a: &Value, b: &Value) -> Value {
-        add(a, &neg(b))
-    }
-
-    pub fn mul(a: &Value, b: &Value) -> Value {
-        match (a, b) {
-            (Value::Float(a), Value::Float(b)) => Value::Float(a * b),
-            (Value::Int(a), Value::Int(b)) => Value::Int(a * b),
-            (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, mul),
-            _ => Value::Nil,
-        }
-    }
-
-    pub fn div(a: &Value, b: &Value) -> Value {
-        match (a, b) {
-            (Value::Float(a), Value::Float(b)) => Value::Float(a / b),
-            (Value::Int(a), Value::Int(b)) => Value::Int(a / b),
-            (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, div),
-            _ => Value::Nil,
-        }
-    }
-
-    pub fn eq(a: &Value, b: &Value) -> Value {
-        match (a, b) {
-            (Value::Float(a), Value::Float(b)) => Value::Bool(a == b),
-            (Value::Int(a), Value::Int(b)) => Value::Bool(a == b),
-            (Value::String(a), Value::String(b)) => Value::Bool(a == b),
-            (Value::Bool(a), Value::Bool(b)) => Value::Bool(a == b),
-            (Value::Tuple(a), Value::Tuple(b)) => Value::Bool(a == b),
-            (Value::Nil, Value::Nil) => Value::Bool(true),
-            (Value::List(a), Value::List(b)) => Value::Bool(a == b),
-            (Value::Set(a), Value::Set(b)) => Value::Bool(a == b),
-            (Value::Dict(a), Value::Dict(b)) => Value::Bool(a == b),
-            _ => Value::Nil,
-        }
-    }
-
-    pub fn less(a: &Value, b: &Value) -> Value {
-        match (a, b) {
-            (Value::Float(a), Value::Float(b)) => Value::Bool(a < b),
-            (Value::Float(a), Value::Int(b)) => Value::Bool(*a < (*b as f64)),
-            (Value::Int(a), Value::Float(b)) => Value::Bool((*a as f64) < *b),
-            (Value::Int(a), Value::Int(b)) => Value::Bool(a < b),
-            (Value::String(a), Value::String(b)) => Value::Bool(a < b),
-            (Value::Bool(a), Value::Bool(b)) => Value::Bool(a < b),
-            (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => a
-                .iter()
-                .zip(b.iter())
-                .find_map(|(a, b)| match eq(a, b) {
-                    Value::Bool(false) => Some(less(a, b)),
-                    _ => None,
-                })
-                .unwrap_or(Value::Bool(false)),
-            _ => Value::Nil,
-        }
-    }
 
-    pub fn greater(a: &Value, b: &Value) -> Value {
-        less(b, a)
-    }
-
-    pub fn and(a: &Value, b: &Value) -> Value {
-        match (a, b) {
-            (Value::Bool(a), Value::Bool(b)) => Value::Bool(*a && *b),
-            (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, and),
-            _ => Value::Nil,
-        }
-    }
+            Op::Coroutine => match self.pop() {
+                function @ Value::Function(..) => {
+                    let coroutine = Coroutine::new(function);
+                    self.push_new(Value::Coroutine(Rc::new(RefCell::new(coroutine))));
+                }
+                value => {
+                    self.push(Value::Nil);
+                    error!(self, RuntimeError::ValueError(op, vec![value]));
+                }
+            },
 
-    pub fn or(a: &Value, b: &Value) -> Value {
-        match (a, b) {
-            (Value::Bool(a), Value::Bool(b)) => Value::Bool(*a || *b),
-            (Value::Tuple(a), Value::Tuple(b)) if a.len() == b.len() => tuple_bin_op(a, b, or),
-            _ => Value::Nil,
+            Op::Yield => {
+                let value = self.pop();
+                return self.suspend(value);
+            }
         }
+        self.frame_mut().ip += 1;
+        Ok(OpResult::Continue)
     }
 }
@@ -188,6 +188,8 @@ struct TestSettings {
     print: bool,
     // Used to tell lua there are runtime errors - since it doesn't care about the type.
     any_runtime_errors: bool,
+    // Expecting a warning makes the test fail on warnings.
+    deny_warnings: bool,
 }
 
 impl Default for TestSettings {
@@ -196,6 +198,7 @@ impl Default for TestSettings {
             errors: String::new(),
             print: true,
             any_runtime_errors: false,
+            deny_warnings: false,
         }
     }
 }
@@ -216,6 +219,10 @@ fn parse_test_settings(contents: String) -> TestSettings {
                     &line[1..]
                 );
             }
+            if line.starts_with("!") {
+                line = format!("Error::Warning {{ kind: Warning::{}, .. }}", &line[1..]);
+                settings.deny_warnings = true;
+            }
             if line.starts_with("@") {
                 line = format!(
                     "Error::SyntaxError {{ span: Span {{ line_start: {}, ..}}, .. }}",
@@ -230,6 +237,9 @@ fn parse_test_settings(contents: String) -> TestSettings {
                     "no_print" => {
                         settings.print = false;
                     }
+                    "deny_warnings" => {
+                        settings.deny_warnings = true;
+                    }
                     _ => {
                         panic!("Unknown test flag '{}'", flag);
                     }
@@ -270,11 +280,12 @@ fn find_test_paths(directory: &Path, macro_path: &syn::Path) -> proc_macro2::Tok
             let settings = parse_test_settings(std::fs::read_to_string(path.clone()).unwrap());
             let any_runtime_errors = settings.any_runtime_errors;
             let print = settings.print;
+            let deny_warnings = settings.deny_warnings;
             let wanted_errs: proc_macro2::TokenStream = settings.errors.parse().unwrap();
 
             // TODO(ed): Make a flag for skipping the test
             let tokens = quote! {
-                #macro_path!(#test_name, #path_string, #print, #wanted_errs, #any_runtime_errors);
+                #macro_path!(#test_name, #path_string, #print, #deny_warnings, #wanted_errs, #any_runtime_errors);
             };
 
             tests.extend(tokens);
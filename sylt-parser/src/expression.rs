@@ -32,6 +32,20 @@ pub enum ExpressionKind {
     Mul(Box<Expression>, Box<Expression>),
     /// `a / b`
     Div(Box<Expression>, Box<Expression>),
+    /// `a % b`
+    Rem(Box<Expression>, Box<Expression>),
+    /// `a div b`
+    FloorDiv(Box<Expression>, Box<Expression>),
+    /// `a & b`
+    BitAnd(Box<Expression>, Box<Expression>),
+    /// `a | b`
+    BitOr(Box<Expression>, Box<Expression>),
+    /// `a ^ b`
+    BitXor(Box<Expression>, Box<Expression>),
+    /// `a << b`
+    ShiftLeft(Box<Expression>, Box<Expression>),
+    /// `a >> b`
+    ShiftRight(Box<Expression>, Box<Expression>),
     /// `-a`
     Neg(Box<Expression>),
 
@@ -47,6 +61,23 @@ pub enum ExpressionKind {
     /// `!a`
     Not(Box<Expression>),
 
+    /// `yield a`, suspends the coroutine that is running and gives `a` to
+    /// the caller. Evaluates to the value the coroutine is resumed with.
+    Yield(Box<Expression>),
+    /// `coroutine f`, a coroutine that runs the function `f`.
+    /// Calling the coroutine resumes it.
+    Coroutine(Box<Expression>),
+    /// `"{a}"`, `a` converted to a string like `as_str` does.
+    ToStr(Box<Expression>),
+
+    /// `a..b` or `a..=b`, the integers from `a` up to `b`. The end is
+    /// only included if the range is inclusive.
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        inclusive: bool,
+    },
+
     Parenthesis(Box<Expression>),
 
     /// Inline If-statements
@@ -224,10 +255,15 @@ fn precedence(token: &T) -> Prec {
     match token {
         T::LeftBracket | T::Dot | T::LeftParen => Prec::Index,
 
-        T::Star | T::Slash => Prec::Factor,
+        T::Star | T::Slash | T::Percent | T::Div => Prec::Factor,
 
         T::Minus | T::Plus => Prec::Term,
 
+        T::LessLess | T::GreaterGreater => Prec::Shift,
+        T::Ampersand => Prec::BitAnd,
+        T::Caret => Prec::BitXor,
+        T::Pipe => Prec::BitOr,
+
         T::EqualEqual
         | T::Greater
         | T::GreaterEqual
@@ -235,6 +271,8 @@ fn precedence(token: &T) -> Prec {
         | T::LessEqual
         | T::NotEqual => Prec::Comp,
 
+        T::DotDot | T::DotDotEqual => Prec::Range,
+
         T::And => Prec::BoolAnd,
         T::Or => Prec::BoolOr,
 
@@ -265,6 +303,61 @@ fn value<'t>(ctx: Context<'t>) -> Result<(Context<'t>, Expression), (Context<'t>
     Ok((ctx, Expression { span, kind }))
 }
 
+/// Parse a string with interpolations, e.g. `"hp: {guy.hp}!"`.
+///
+/// The string is lowered to concatenation, where the interpolated expressions
+/// are converted with [ExpressionKind::ToStr]: `"hp: " + "{guy.hp}" + "!"`.
+fn interpolated_string<'t>(ctx: Context<'t>) -> ParseResult<'t, Expression> {
+    use ExpressionKind::{Add, Str, ToStr};
+
+    let (token, span, ctx) = ctx.eat();
+    let mut parts = match token {
+        T::InterpolationStart(text) => vec![Expression { span, kind: Str(text.clone()) }],
+        _ => {
+            raise_syntax_error!(ctx, "Expected a string");
+        }
+    };
+
+    // The interpolated expressions can span multiple lines.
+    let (mut ctx, skip_newlines) = ctx.push_skip_newlines(true);
+    loop {
+        let (ctx_, expr) = expression(ctx)?;
+        ctx = ctx_; // assign to outer
+
+        parts.push(Expression { span: expr.span, kind: ToStr(Box::new(expr)) });
+
+        let span = ctx.span();
+        match ctx.token() {
+            T::InterpolationContinue(text) => {
+                parts.push(Expression { span, kind: Str(text.clone()) });
+                ctx = ctx.skip(1);
+            }
+            T::InterpolationEnd(text) => {
+                parts.push(Expression { span, kind: Str(text.clone()) });
+                ctx = ctx.pop_skip_newlines(skip_newlines).skip(1);
+                break;
+            }
+            t => {
+                raise_syntax_error!(
+                    ctx,
+                    "Expected '}}' after interpolated expression but got '{:?}'",
+                    t
+                );
+            }
+        }
+    }
+
+    let expr = parts
+        .into_iter()
+        .filter(|part| !matches!(&part.kind, Str(text) if text.is_empty()))
+        .reduce(|lhs, rhs| Expression {
+            span: lhs.span,
+            kind: Add(Box::new(lhs), Box::new(rhs)),
+        })
+        .unwrap();
+    Ok((ctx, Expression { span, ..expr }))
+}
+
 /// Parse something that begins at the start of an expression.
 fn prefix<'t>(ctx: Context<'t>) -> ParseResult<'t, Expression> {
     use ExpressionKind::Get;
@@ -277,7 +370,13 @@ fn prefix<'t>(ctx: Context<'t>) -> ParseResult<'t, Expression> {
         T::LeftBrace => set_or_dict(ctx),
 
         T::Float(_) | T::Int(_) | T::Bool(_) | T::String(_) | T::Nil => value(ctx),
+        T::InterpolationStart(_) => interpolated_string(ctx),
+        T::StringError(message) => {
+            raise_syntax_error!(ctx, "{}", message);
+        }
         T::Minus | T::Not => unary(ctx),
+        T::Yield => yield_expression(ctx),
+        T::Coroutine => coroutine(ctx),
 
         T::Identifier(_) => {
             let span = ctx.span();
@@ -305,12 +404,39 @@ fn prefix<'t>(ctx: Context<'t>) -> ParseResult<'t, Expression> {
     }
 }
 
+/// Parse an [ExpressionKind::Yield]: `yield <expression>`
+fn yield_expression<'t>(ctx: Context<'t>) -> ParseResult<'t, Expression> {
+    let span = ctx.span();
+    let ctx = expect!(ctx, T::Yield, "Expected 'yield'");
+    // Takes everything after it, like `ret`.
+    let (ctx, expr) = parse_precedence(ctx, Prec::No)?;
+    Ok((
+        ctx,
+        Expression { span, kind: ExpressionKind::Yield(Box::new(expr)) },
+    ))
+}
+
+/// Parse an [ExpressionKind::Coroutine]: `coroutine <expression>`
+fn coroutine<'t>(ctx: Context<'t>) -> ParseResult<'t, Expression> {
+    let span = ctx.span();
+    let ctx = expect!(ctx, T::Coroutine, "Expected 'coroutine'");
+    let (ctx, expr) = parse_precedence(ctx, Prec::Index)?;
+    Ok((
+        ctx,
+        Expression {
+            span,
+            kind: ExpressionKind::Coroutine(Box::new(expr)),
+        },
+    ))
+}
+
 /// Parse a unary operator followed by an expression, e.g. `-5`.
 fn unary<'t>(ctx: Context<'t>) -> ParseResult<'t, Expression> {
     use ExpressionKind::{Neg, Not};
 
     let (op, span, ctx) = ctx.eat();
-    let (ctx, expr) = parse_precedence(ctx, Prec::Factor)?;
+    // Binds tighter than factors, so `-7 % 3` is `(-7) % 3`.
+    let (ctx, expr) = parse_precedence(ctx, Prec::Index)?;
     let expr = Box::new(expr);
 
     let kind = match op {
@@ -426,6 +552,13 @@ fn infix<'t>(ctx: Context<'t>, lhs: &Expression) -> ParseResult<'t, Expression>
         | T::Minus
         | T::Star
         | T::Slash
+        | T::Percent
+        | T::Div
+        | T::Ampersand
+        | T::Pipe
+        | T::Caret
+        | T::LessLess
+        | T::GreaterGreater
         | T::EqualEqual
         | T::NotEqual
         | T::Greater
@@ -435,7 +568,9 @@ fn infix<'t>(ctx: Context<'t>, lhs: &Expression) -> ParseResult<'t, Expression>
         | T::And
         | T::Or
         | T::AssertEqual
-        | T::In => {}
+        | T::In
+        | T::DotDot
+        | T::DotDotEqual => {}
 
         // Unknown infix operator.
         _ => {
@@ -456,6 +591,15 @@ fn infix<'t>(ctx: Context<'t>, lhs: &Expression) -> ParseResult<'t, Expression>
         T::Minus => Sub(lhs, rhs),
         T::Star => Mul(lhs, rhs),
         T::Slash => Div(lhs, rhs),
+        T::Percent => Rem(lhs, rhs),
+        T::Div => FloorDiv(lhs, rhs),
+
+        // Bitwise operators.
+        T::Ampersand => BitAnd(lhs, rhs),
+        T::Pipe => BitOr(lhs, rhs),
+        T::Caret => BitXor(lhs, rhs),
+        T::LessLess => ShiftLeft(lhs, rhs),
+        T::GreaterGreater => ShiftRight(lhs, rhs),
 
         // Comparisons
         T::EqualEqual => Comparison(lhs, Equals, rhs),
@@ -472,6 +616,9 @@ fn infix<'t>(ctx: Context<'t>, lhs: &Expression) -> ParseResult<'t, Expression>
 
         T::AssertEqual => AssertEq(lhs, rhs),
 
+        T::DotDot => Range { start: lhs, end: rhs, inclusive: false },
+        T::DotDotEqual => Range { start: lhs, end: rhs, inclusive: true },
+
         // Unknown infix operator.
         _ => {
             unreachable!();
@@ -734,6 +881,14 @@ mod test {
     test!(expression, index_expr: "a[1 + 2 + 3]" => Get(Assignable { kind: Index(_, _), .. }));
     test!(expression, grouping: "(0 * 0) + 1" => Add(_, _));
     test!(expression, grouping_one: "(0)" => Parenthesis(_));
+    test!(expression, rem: "1 + 2 % 3" => Add(_, _));
+    test!(expression, floor_div: "1 div 2 * 3" => Mul(_, _));
+    test!(expression, bit_and: "1 & 2 + 3" => BitAnd(_, _));
+    test!(expression, bit_or: "1 | 2 ^ 3 & 4" => BitOr(_, _));
+    test!(expression, bit_xor: "1 ^ 2 & 3" => BitXor(_, _));
+    test!(expression, shift_left: "1 << 2 + 3" => ShiftLeft(_, _));
+    test!(expression, shift_right: "1 >> 2 & 3" => BitAnd(_, _));
+    test!(expression, bit_comparison: "1 | 2 == 3" => Comparison(_, ComparisonKind::Equals, _));
     test!(expression, tuple: "(0, 0)" => Tuple(_));
     test!(expression, tuple_one: "(0,)" => Tuple(_));
     test!(expression, tuple_empty: "()" => Tuple(_));
@@ -747,6 +902,17 @@ mod test {
     test!(expression, in_set: "2 in {1, 1, 2}" => Comparison(_, ComparisonKind::In, _));
     test!(expression, in_grouping: "1 + 2 in b" => Add(_, _));
     test!(expression, in_grouping_paren: "(1 + 2) in b" => Comparison(_, ComparisonKind::In, _));
+    test!(expression, in_range: "a in (0..10)" => Comparison(_, ComparisonKind::In, _));
+
+    test!(expression, range: "0..10" => Range { inclusive: false, .. });
+    test!(expression, range_inclusive: "0..=10" => Range { inclusive: true, .. });
+    test!(expression, range_term: "a + 1..b - 1" => Range { .. });
+    test!(expression, range_comparison: "0..1 == 0..1" => Comparison(_, ComparisonKind::Equals, _));
+    test!(expression, range_index: "a[1..3]" => Get(_));
+
+    test!(expression, yield_value: "yield 1 + 2" => Yield(_));
+    test!(expression, coroutine_fn: "coroutine fn do end" => Coroutine(_));
+    test!(expression, coroutine_call: "coroutine f()" => Coroutine(_));
 
     test!(expression, call_simple_paren: "a()" => Get(_));
     test!(expression, call_call: "a()()" => Get(_));
@@ -822,6 +988,18 @@ mod test {
     fail!(expression, set_funky: "{1 2}" => _);
     fail!(expression, dict_funky: "{1: 2 3: 4}" => _);
     fail!(expression, tuple_funky: "(1 2 3 4}" => _);
+
+    test!(expression, string_escapes: "\"a\\n\\{b\\}\"" => Str(_));
+    test!(expression, string_raw: "r\"{a}\"" => Str(_));
+    test!(expression, interpolation: "\"a {b} c\"" => Add(_, _));
+    test!(expression, interpolation_only: "\"{b}\"" => ToStr(_));
+    test!(expression, interpolation_many: "\"{a}{b + 1}{\"{c}\"}\"" => Add(_, _));
+    test!(expression, interpolation_lines: "\"{\na\n}\"" => ToStr(_));
+
+    fail!(expression, string_unknown_escape: "\"\\q\"" => _);
+    fail!(expression, interpolation_empty: "\"{ }\"" => _);
+    fail!(expression, interpolation_unmatched: "\"}\"" => _);
+    fail!(expression, interpolation_two_expressions: "\"{a b}\"" => _);
 }
 
 impl PrettyPrint for Expression {
@@ -854,6 +1032,41 @@ impl PrettyPrint for Expression {
                 a.pretty_print(f, indent + 1)?;
                 b.pretty_print(f, indent + 1)?;
             }
+            EK::Rem(a, b) => {
+                write!(f, "Rem\n")?;
+                a.pretty_print(f, indent + 1)?;
+                b.pretty_print(f, indent + 1)?;
+            }
+            EK::FloorDiv(a, b) => {
+                write!(f, "FloorDiv\n")?;
+                a.pretty_print(f, indent + 1)?;
+                b.pretty_print(f, indent + 1)?;
+            }
+            EK::BitAnd(a, b) => {
+                write!(f, "BitAnd\n")?;
+                a.pretty_print(f, indent + 1)?;
+                b.pretty_print(f, indent + 1)?;
+            }
+            EK::BitOr(a, b) => {
+                write!(f, "BitOr\n")?;
+                a.pretty_print(f, indent + 1)?;
+                b.pretty_print(f, indent + 1)?;
+            }
+            EK::BitXor(a, b) => {
+                write!(f, "BitXor\n")?;
+                a.pretty_print(f, indent + 1)?;
+                b.pretty_print(f, indent + 1)?;
+            }
+            EK::ShiftLeft(a, b) => {
+                write!(f, "ShiftLeft\n")?;
+                a.pretty_print(f, indent + 1)?;
+                b.pretty_print(f, indent + 1)?;
+            }
+            EK::ShiftRight(a, b) => {
+                write!(f, "ShiftRight\n")?;
+                a.pretty_print(f, indent + 1)?;
+                b.pretty_print(f, indent + 1)?;
+            }
             EK::Neg(a) => {
                 write!(f, "Neg\n")?;
                 a.pretty_print(f, indent + 1)?;
@@ -882,6 +1095,23 @@ impl PrettyPrint for Expression {
                 write!(f, "Not\n")?;
                 a.pretty_print(f, indent + 1)?;
             }
+            EK::Yield(a) => {
+                write!(f, "Yield\n")?;
+                a.pretty_print(f, indent + 1)?;
+            }
+            EK::Coroutine(a) => {
+                write!(f, "Coroutine\n")?;
+                a.pretty_print(f, indent + 1)?;
+            }
+            EK::ToStr(a) => {
+                write!(f, "ToStr\n")?;
+                a.pretty_print(f, indent + 1)?;
+            }
+            EK::Range { start, end, inclusive } => {
+                write!(f, "Range inclusive={}\n", inclusive)?;
+                start.pretty_print(f, indent + 1)?;
+                end.pretty_print(f, indent + 1)?;
+            }
             EK::Parenthesis(expr) => {
                 write!(f, "Paren\n")?;
                 expr.pretty_print(f, indent + 1)?;
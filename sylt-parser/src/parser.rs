@@ -3,14 +3,14 @@ use self::statement::outer_statement;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Debug, Display};
 use std::path::{Path, PathBuf};
-use sylt_common::error::Error;
+use sylt_common::error::{Error, Warning};
 use sylt_common::Type as RuntimeType;
 use sylt_tokenizer::{string_to_tokens, PlacedToken, Token};
 
 pub mod expression;
 pub mod statement;
 pub use self::expression::{Expression, ExpressionKind};
-pub use self::statement::{CaseBranch, Statement, StatementKind};
+pub use self::statement::{CaseBranch, Pattern, PatternKind, Statement, StatementKind};
 
 pub use sylt_tokenizer::Span;
 
@@ -30,11 +30,39 @@ pub struct AST {
     pub modules: Vec<(PathBuf, Module)>,
 }
 
+impl AST {
+    /// Checks if the warning is allowed by a `// allow(...)` comment.
+    pub fn is_allowed(&self, warning: &Error) -> bool {
+        match warning {
+            Error::Warning { kind, file, span, .. } => self
+                .modules
+                .iter()
+                .filter(|(path, _)| path == file)
+                .flat_map(|(_, module)| module.allows.iter())
+                .any(|allow| {
+                    (allow.line_start..=allow.line_end).contains(&span.line_start)
+                        && allow.warnings.iter().any(|name| name == kind.name())
+                }),
+            _ => false,
+        }
+    }
+}
+
 /// Contains statements.
 #[derive(Debug, Clone)]
 pub struct Module {
     pub span: Span,
     pub statements: Vec<Statement>,
+    pub allows: Vec<Allow>,
+}
+
+/// A `// allow(...)` comment. The warnings it names aren't reported
+/// between the lines.
+#[derive(Debug, Clone)]
+pub struct Allow {
+    pub warnings: Vec<String>,
+    pub line_start: usize,
+    pub line_end: usize,
 }
 
 /// The precedence of an operator.
@@ -53,6 +81,11 @@ pub enum Prec {
     BoolOr,
     BoolAnd,
     Comp,
+    Range,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
     Term,
     Factor,
     Index,
@@ -216,6 +249,10 @@ pub struct TypeConstraint {
     pub args: Vec<Identifier>,
 }
 
+/// The most syntax errors reported for a single file. A block stops
+/// recovering once it has this many errors.
+pub const MAX_SYNTAX_ERRORS: usize = 20;
+
 type ParseResult<'t, T> = Result<(Context<'t>, T), (Context<'t>, Vec<Error>)>;
 
 /// Keeps track of where the parser is currently parsing.
@@ -481,6 +518,60 @@ macro_rules! skip_until {
     };
 }
 
+/// Skip past a statement that failed to parse, starting from the first token
+/// of the statement. Stops where the next statement should start.
+///
+/// Blocks are skipped as a whole, so the `end` of a broken `if` doesn't end the
+/// block the `if` is in. An `end` or `else` that belongs to the surrounding
+/// block is left for the surrounding block to parse.
+pub(crate) fn skip_statement(ctx: Context) -> Context {
+    #[derive(PartialEq)]
+    enum Opened {
+        If,
+        Other,
+    }
+
+    let mut ctx = ctx.pop_skip_newlines(false);
+    let mut blocks = Vec::new();
+    let mut brackets = 0_usize;
+    // What the next `do` opens, `if`-chains share one `end`.
+    let mut next_block = Opened::Other;
+    let mut first = true;
+    loop {
+        match ctx.token() {
+            T::EOF => return ctx,
+            T::Newline if blocks.is_empty() && brackets == 0 => return ctx.skip(1),
+            T::End | T::Else if blocks.is_empty() && !first => return ctx,
+
+            T::If => next_block = Opened::If,
+            T::Do | T::Enum => {
+                blocks.push(std::mem::replace(&mut next_block, Opened::Other));
+            }
+            // `else do` and `else if` continue the `if`, while the `else` of an
+            // if-expression is followed by an expression.
+            T::Else
+                if matches!(ctx.skip(1).token(), T::Do | T::If)
+                    && blocks.last() == Some(&Opened::If) =>
+            {
+                blocks.pop();
+            }
+            T::End => {
+                blocks.pop();
+            }
+
+            T::LeftParen | T::LeftBracket | T::LeftBrace => brackets += 1,
+            T::RightParen | T::RightBracket | T::RightBrace => {
+                brackets = brackets.saturating_sub(1);
+            }
+
+            T::Newline => next_block = Opened::Other,
+            _ => {}
+        }
+        first = false;
+        ctx = ctx.skip(1);
+    }
+}
+
 /// Parse a constraint arguments `a b c`
 pub fn parse_type_constraint_argument<'t>(ctx: Context<'t>) -> ParseResult<'t, Vec<Identifier>> {
     let mut args = Vec::new();
@@ -942,20 +1033,25 @@ fn assignable<'t>(ctx: Context<'t>) -> ParseResult<'t, Assignable> {
 ///
 /// # Errors
 ///
-/// Returns any errors that occured when parsing the file. Basic error
-/// continuation is performed, so errored statements are skipped until a newline
-/// or EOF.
+/// Returns any errors that occured when parsing the file. Statements that fail
+/// to parse are skipped using [skip_statement], so all errors in the file are
+/// found. Errors on a line that already has an error are removed, and at most
+/// [MAX_SYNTAX_ERRORS] errors are returned.
+///
+/// The [Module] only contains the statements that parsed, and is returned even
+/// if there are errors.
 fn module(
     path: &Path,
     file_id: usize,
     root: &Path,
     token_stream: &[PlacedToken],
-) -> (Vec<PathBuf>, Result<Module, Vec<Error>>) {
+) -> (Vec<PathBuf>, Module, Vec<Error>) {
     let tokens: Vec<_> = token_stream.iter().map(|p| p.token.clone()).collect();
     let spans: Vec<_> = token_stream.iter().map(|p| p.span).collect();
     let mut errors = Vec::new();
     let mut use_files = Vec::new();
     let mut statements = Vec::new();
+    let mut seen_lines = HashSet::new();
     let mut ctx = Context::new(&tokens, &spans, path, file_id, root);
     while !matches!(ctx.token(), T::EOF) {
         // Ignore newlines.
@@ -964,6 +1060,13 @@ fn module(
             continue;
         }
 
+        if errors.len() >= MAX_SYNTAX_ERRORS {
+            errors.truncate(MAX_SYNTAX_ERRORS);
+            let error = syntax_error!(ctx, "Too many syntax errors, skipping the rest of the file");
+            errors.push(error);
+            break;
+        }
+
         // Parse an outer statement.
         ctx = match outer_statement(ctx) {
             Ok((ctx, statement)) => {
@@ -975,11 +1078,14 @@ fn module(
                 statements.push(statement);
                 ctx
             }
-            Err((ctx, mut errs)) => {
-                errors.append(&mut errs);
-
-                // "Error recovery"
-                skip_until!(ctx, T::Newline)
+            Err((_, errs)) => {
+                // Errors on a line that already has an error are usually
+                // caused by the first one.
+                errors.extend(errs.into_iter().filter(|err| match err {
+                    Error::SyntaxError { span, .. } => seen_lines.insert(span.line_start),
+                    _ => true,
+                }));
+                skip_statement(ctx)
             }
         }
     }
@@ -993,14 +1099,70 @@ fn module(
         });
     }
 
-    if errors.is_empty() {
-        (
-            use_files,
-            Ok(Module { span: Span::zero(file_id), statements }),
-        )
-    } else {
-        (use_files, Err(errors))
+    let (allows, mut warnings) = find_allows(Context::new(&tokens, &spans, path, file_id, root));
+    errors.append(&mut warnings);
+
+    (
+        use_files,
+        Module { span: Span::zero(file_id), statements, allows },
+        errors,
+    )
+}
+
+/// Finds the `// allow(...)` comments in a file. A comment after some code
+/// covers that line, and a comment on a line of its own covers the
+/// statement after it.
+///
+/// Returns a [Warning::UnknownWarning] for names that aren't warnings.
+fn find_allows(ctx: Context) -> (Vec<Allow>, Vec<Error>) {
+    let mut allows = Vec::new();
+    let mut warnings = Vec::new();
+    for (i, token) in ctx.tokens.iter().enumerate() {
+        let names = match token {
+            T::Comment(comment) => match comment
+                .strip_prefix("allow(")
+                .and_then(|names| names.strip_suffix(')'))
+            {
+                Some(names) => names,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        let span = ctx.spans[i];
+        let names: Vec<_> = names
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect();
+        let known = Warning::names();
+        for name in names.iter() {
+            if !known.contains(&name.as_str()) {
+                warnings.push(Error::Warning {
+                    kind: Warning::UnknownWarning(name.clone()),
+                    file: ctx.file.to_path_buf(),
+                    span,
+                    message: Some(format!("The warnings are: {}", known.join(", "))),
+                });
+            }
+        }
+
+        let after_code = i > 0 && !matches!(ctx.tokens[i - 1], T::Newline | T::Comment(_));
+        let (line_start, line_end) = if after_code {
+            (span.line_start, span.line_end)
+        } else {
+            let mut statement = ctx;
+            statement.curr = i;
+            statement.skip_newlines = true;
+            let statement = statement.skip(1);
+            if matches!(statement.token(), T::EOF) {
+                continue;
+            }
+            let end = skip_statement(statement).prev();
+            (statement.span().line_start, end.span().line_end)
+        };
+        allows.push(Allow { warnings: names, line_start, line_end });
     }
+    (allows, warnings)
 }
 
 /// Look for git conflict markers (`<<<<<<<`) in a file.
@@ -1044,9 +1206,26 @@ pub fn find_conflict_markers(file: &Path, file_id: usize, source: &str) -> Vec<E
 ///
 /// # Errors
 ///
-/// Returns any errors that occured when parsing the file(s). Basic error
-/// continuation is performed as documented in [module].
+/// Returns any errors that occured when parsing the file(s). Error recovery is
+/// performed as documented in [module].
 pub fn tree<F>(path: &Path, reader: F) -> Result<AST, Vec<Error>>
+where
+    F: Fn(&Path) -> Result<String, Error>,
+{
+    match partial_tree(path, reader) {
+        (tree, errors) if errors.is_empty() => Ok(tree),
+        (_, errors) => Err(errors),
+    }
+}
+
+/// Like [tree], but also returns the statements that parsed when there are
+/// errors. Files that couldn't be read, or that contain conflict markers, are
+/// left out of the [AST].
+///
+/// Recovery only finds more syntax errors. The statements that failed to
+/// parse are missing from the tree, so a broken tree isn't typechecked or
+/// compiled - it's only useful to look at, like `--dump-tree` does.
+pub fn partial_tree<F>(path: &Path, reader: F) -> (AST, Vec<Error>)
 where
     F: Fn(&Path) -> Result<String, Error>,
 {
@@ -1078,11 +1257,9 @@ where
 
                 let tokens = string_to_tokens(file_id, &source);
                 // Parse the module.
-                let (mut next, result) = module(&file, file_id, &root, &tokens);
-                match result {
-                    Ok(module) => modules.push((file.clone(), module)),
-                    Err(mut errs) => errors.append(&mut errs),
-                }
+                let (mut next, module, mut errs) = module(&file, file_id, &root, &tokens);
+                modules.push((file.clone(), module));
+                errors.append(&mut errs);
                 to_visit.append(&mut next);
             }
             Err(_) => {
@@ -1091,21 +1268,7 @@ where
         }
     }
 
-    if errors.is_empty() {
-        Ok(AST { modules })
-    } else {
-        // Filter out errors for already seen spans
-        let mut seen = HashSet::new();
-        let errors = errors
-            .into_iter()
-            .filter(|err| match err {
-                Error::SyntaxError { span, file, .. } => seen.insert((span.clone(), file.clone())),
-
-                _ => true,
-            })
-            .collect();
-        Err(errors)
-    }
+    (AST { modules }, errors)
 }
 
 #[cfg(test)]
@@ -1199,6 +1362,97 @@ mod test {
 
         test!(parse_type, type_dict_one: "{int : int}" => Dict(_, _));
     }
+
+    mod recovery {
+        use super::*;
+
+        /// Parses a module and returns the names of the parsed definitions
+        /// and the lines of the errors.
+        fn recover(source: &str) -> (Vec<String>, Vec<usize>) {
+            let path = PathBuf::from("recovery");
+            let tokens = string_to_tokens(0, source);
+            let (_, module, errors) = module(&path, 0, &path, &tokens);
+            let names = module
+                .statements
+                .iter()
+                .filter_map(|statement| match &statement.kind {
+                    StatementKind::Definition { ident, .. } => Some(ident.name.clone()),
+                    _ => None,
+                })
+                .collect();
+            let lines = errors
+                .iter()
+                .map(|error| match error {
+                    Error::SyntaxError { span, .. } => span.line_start,
+                    error => panic!("Expected a syntax error but got {:?}", error),
+                })
+                .collect();
+            (names, lines)
+        }
+
+        #[test]
+        fn every_broken_statement() {
+            let (names, lines) = recover("a :: 1 1\nb :: 2\nc :: )\nd :: 3\n");
+            assert_eq!(names, vec!["b", "d"]);
+            assert_eq!(lines, vec![1, 3]);
+        }
+
+        #[test]
+        fn broken_blocks_are_skipped() {
+            let source = "\
+f :: fn do
+    if a a do
+        b := 1
+    end
+    c := )
+end
+g :: 1
+";
+            let (names, lines) = recover(source);
+            assert_eq!(names, vec!["g"]);
+            assert_eq!(lines, vec![2, 5]);
+        }
+
+        #[test]
+        fn if_chains_share_an_end() {
+            let source = "\
+f :: fn do
+    if a a do
+        b := 1 if c else 2
+    else if d do
+    else do
+    end
+    e := )
+end
+g :: 1
+";
+            let (names, lines) = recover(source);
+            assert_eq!(names, vec!["g"]);
+            assert_eq!(lines, vec![2, 7]);
+        }
+
+        #[test]
+        fn stray_end() {
+            let (names, lines) = recover("end\na :: 1\n");
+            assert_eq!(names, vec!["a"]);
+            assert_eq!(lines, vec![1]);
+        }
+
+        #[test]
+        fn errors_are_capped() {
+            let source = "a :: )\n".repeat(MAX_SYNTAX_ERRORS * 2);
+            let (_, lines) = recover(&source);
+            assert_eq!(lines.len(), MAX_SYNTAX_ERRORS + 1);
+        }
+
+        #[test]
+        fn errors_in_a_block_are_capped() {
+            let body = "a := )\n".repeat(MAX_SYNTAX_ERRORS * 2);
+            let source = format!("f :: fn do\n{}end\n", body);
+            let (_, lines) = recover(&source);
+            assert_eq!(lines.len(), MAX_SYNTAX_ERRORS);
+        }
+    }
 }
 
 trait PrettyPrint {
@@ -1291,19 +1545,21 @@ impl PrettyPrint for Statement {
             SK::Case { to_match, branches, fall_through } => {
                 write!(f, "<Case>\n")?;
                 to_match.pretty_print(f, indent + 1)?;
-                for CaseBranch { pattern, variable, body } in branches.iter() {
+                for CaseBranch { pattern, guard, body } in branches.iter() {
                     write_indent(f, indent + 1)?;
-                    write!(
-                        f,
-                        "{} {:?}\n",
-                        pattern.name,
-                        variable.as_ref().map(|x| &x.name)
-                    )?;
+                    write!(f, "{}\n", pattern)?;
+                    if let Some(guard) = guard {
+                        write_indent(f, indent + 2)?;
+                        write!(f, "if\n")?;
+                        guard.pretty_print(f, indent + 3)?;
+                    }
                     body.pretty_print(f, indent + 2)?;
                 }
-                write_indent(f, indent + 1)?;
-                write!(f, "else")?;
-                fall_through.pretty_print(f, indent + 2)?;
+                if let Some(fall_through) = fall_through {
+                    write_indent(f, indent + 1)?;
+                    write!(f, "else")?;
+                    fall_through.pretty_print(f, indent + 2)?;
+                }
                 return Ok(());
             }
             SK::If { condition, pass, fail } => {
@@ -1319,6 +1575,14 @@ impl PrettyPrint for Statement {
                 body.pretty_print(f, indent + 1)?;
                 return Ok(());
             }
+            SK::LoopIn { pattern, iterable, body } => {
+                write!(f, "<LoopIn>\n")?;
+                write_indent(f, indent + 1)?;
+                write!(f, "{}\n", pattern)?;
+                iterable.pretty_print(f, indent + 1)?;
+                body.pretty_print(f, indent + 1)?;
+                return Ok(());
+            }
             SK::Break => {
                 write!(f, "<Break>")?;
             }
@@ -11,10 +11,80 @@ pub enum NameIdentifier {
 
 type Alias = Identifier;
 
+/// The different kinds of [Pattern]s a `case`-branch can match on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternKind {
+    /// Matches anything.
+    ///
+    /// `_`.
+    Wildcard,
+    /// Matches anything and binds it to a new variable.
+    ///
+    /// `a`.
+    Bind(Identifier),
+    /// `1`, `-1`.
+    Int(i64),
+    /// `"a"`.
+    Str(String),
+    /// `true`, `false`.
+    Bool(bool),
+    /// Matches a tuple element by element.
+    ///
+    /// `(<pattern>, <pattern>..)`.
+    Tuple(Vec<Pattern>),
+    /// Matches a variant of an enum, and optionally the value it holds.
+    ///
+    /// `A`, `A <pattern>`.
+    Variant(Identifier, Option<Box<Pattern>>),
+    /// Matches the pattern and binds the whole value to a new variable.
+    ///
+    /// `<pattern> as <variable>`.
+    As(Box<Pattern>, Identifier),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub span: Span,
+    pub kind: PatternKind,
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            PatternKind::Wildcard => write!(f, "_"),
+            PatternKind::Bind(ident) => write!(f, "{}", ident.name),
+            PatternKind::Int(i) => write!(f, "{}", i),
+            PatternKind::Str(s) => write!(f, "{:?}", s),
+            PatternKind::Bool(b) => write!(f, "{}", b),
+            PatternKind::Tuple(patterns) => {
+                write!(f, "(")?;
+                for (i, pattern) in patterns.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", pattern)?;
+                }
+                if patterns.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            PatternKind::Variant(variant, None) => write!(f, "{}", variant.name),
+            PatternKind::Variant(variant, Some(pattern)) => match pattern.kind {
+                PatternKind::Variant(..) | PatternKind::As(..) => {
+                    write!(f, "{} ({})", variant.name, pattern)
+                }
+                _ => write!(f, "{} {}", variant.name, pattern),
+            },
+            PatternKind::As(pattern, ident) => write!(f, "{} as {}", pattern, ident.name),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct CaseBranch {
-    pub pattern: Identifier,
-    pub variable: Option<Identifier>,
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
     pub body: Statement,
 }
 
@@ -110,13 +180,14 @@ pub enum StatementKind {
         fail: Box<Statement>,
     },
 
-    /// A super branchy branch.
+    /// A super branchy branch. The else can only be left out if the
+    /// branches cover every value.
     ///
-    /// `case <expression> do (<pattern> [<variable] <statement>)* [else <statement>] end`.
+    /// `case <expression> do (<pattern> [if <expression>] <statement>)* [else <statement>] end`.
     Case {
         to_match: Expression,
         branches: Vec<CaseBranch>,
-        fall_through: Box<Statement>,
+        fall_through: Option<Box<Statement>>,
     },
 
     /// Do something as long as something else evaluates to true.
@@ -127,6 +198,16 @@ pub enum StatementKind {
         body: Box<Statement>,
     },
 
+    /// Do something for each element in a list, set, dict or tuple.
+    /// The elements of a dict are `(key, value)` tuples.
+    ///
+    /// `loop <pattern> in <expression> <statement>`.
+    LoopIn {
+        pattern: Pattern,
+        iterable: Expression,
+        body: Box<Statement>,
+    },
+
     /// Jump out of a loop.
     ///
     /// `break`.
@@ -255,6 +336,122 @@ fn statement_or_block<'t>(ctx: Context<'t>) -> ParseResult<'t, Statement> {
     }
 }
 
+/// Parses the pattern of a `case`-branch.
+///
+/// `<Variant> <pattern>`, `<pattern> as <variable>` or a single
+/// [pattern_atom].
+pub fn pattern<'t>(ctx: Context<'t>) -> ParseResult<'t, Pattern> {
+    let span = ctx.span();
+    let (ctx, pattern) = match ctx.token() {
+        T::Identifier(name) if is_capitalized(name) => {
+            let variant = Identifier { name: name.clone(), span };
+            let ctx = ctx.skip(1);
+            if matches!(
+                ctx.token(),
+                T::Identifier(_) | T::Int(_) | T::Minus | T::String(_) | T::Bool(_) | T::LeftParen
+            ) {
+                let (ctx, value) = pattern_atom(ctx)?;
+                (
+                    ctx,
+                    Pattern {
+                        span,
+                        kind: PatternKind::Variant(variant, Some(Box::new(value))),
+                    },
+                )
+            } else {
+                (
+                    ctx,
+                    Pattern { span, kind: PatternKind::Variant(variant, None) },
+                )
+            }
+        }
+        _ => pattern_atom(ctx)?,
+    };
+
+    if matches!(ctx.token(), T::As) {
+        let ctx = ctx.skip(1);
+        match ctx.token() {
+            T::Identifier(name) if !is_capitalized(name) && name != "_" => {
+                let ident = Identifier { name: name.clone(), span: ctx.span() };
+                Ok((
+                    ctx.skip(1),
+                    Pattern {
+                        span,
+                        kind: PatternKind::As(Box::new(pattern), ident),
+                    },
+                ))
+            }
+            _ => {
+                raise_syntax_error!(ctx, "Expected a variable name after 'as'");
+            }
+        }
+    } else {
+        Ok((ctx, pattern))
+    }
+}
+
+/// Checks if the loop starting at `ctx` goes through the elements of something.
+/// `loop x in xs` does, while `loop (x in xs)` loops as long as `x` is in `xs`.
+fn is_loop_in(ctx: Context) -> bool {
+    matches!(pattern(ctx), Ok((ctx, _)) if matches!(ctx.token(), T::In))
+}
+
+/// Parses a pattern that doesn't need parenthesis to be the value of a variant.
+///
+/// `_`, `<variable>`, `<Variant>`, a literal or `(<pattern>, ..)`.
+fn pattern_atom<'t>(ctx: Context<'t>) -> ParseResult<'t, Pattern> {
+    let span = ctx.span();
+    let kind = match ctx.token().clone() {
+        T::Identifier(name) if name == "_" => PatternKind::Wildcard,
+        T::Identifier(name) if is_capitalized(&name) => {
+            PatternKind::Variant(Identifier { name, span }, None)
+        }
+        T::Identifier(name) => PatternKind::Bind(Identifier { name, span }),
+        T::Int(i) => PatternKind::Int(i),
+        T::Minus => match ctx.skip(1).token() {
+            T::Int(i) => {
+                return Ok((ctx.skip(2), Pattern { span, kind: PatternKind::Int(-i) }));
+            }
+            _ => {
+                raise_syntax_error!(ctx.skip(1), "Expected an integer after '-' in a pattern");
+            }
+        },
+        T::String(s) => PatternKind::Str(s),
+        T::Bool(b) => PatternKind::Bool(b),
+        T::LeftParen => {
+            let (mut ctx, skip_newlines) = ctx.skip(1).push_skip_newlines(true);
+            let mut patterns = Vec::new();
+            let mut is_tuple = false;
+            while !matches!(ctx.token(), T::RightParen | T::EOF) {
+                let (ctx_, pattern) = pattern(ctx)?;
+                patterns.push(pattern);
+                ctx = ctx_;
+                if matches!(ctx.token(), T::Comma) {
+                    is_tuple = true;
+                    ctx = ctx.skip(1);
+                } else {
+                    break;
+                }
+            }
+            let ctx = ctx.pop_skip_newlines(skip_newlines);
+            let ctx = expect!(ctx, T::RightParen, "Expected ')' after the tuple pattern");
+            // `(<pattern>)` is just parenthesis, but `()` and `(<pattern>,)` are tuples.
+            if !is_tuple && patterns.len() == 1 {
+                return Ok((ctx, patterns.pop().unwrap()));
+            }
+            return Ok((ctx, Pattern { span, kind: PatternKind::Tuple(patterns) }));
+        }
+        token => {
+            raise_syntax_error!(
+                ctx,
+                "Expected a pattern - but a pattern cannot start with {:?}",
+                token
+            );
+        }
+    };
+    Ok((ctx.skip(1), Pattern { span, kind }))
+}
+
 pub fn block<'t>(ctx: Context<'t>) -> ParseResult<'t, Vec<Statement>> {
     // To allow implicit block-openings, like "fn ->"
     let mut ctx = ctx.skip_if(T::Do);
@@ -268,10 +465,12 @@ pub fn block<'t>(ctx: Context<'t>) -> ParseResult<'t, Vec<Statement>> {
                 ctx = _ctx; // assign to outer
                 statements.push(stmt);
             }
-            Err((_ctx, mut err)) => {
-                ctx = _ctx.pop_skip_newlines(false); // assign to outer
-                ctx = skip_until!(ctx, T::Newline).skip_if(T::Newline);
+            Err((_, mut err)) => {
+                ctx = skip_statement(ctx);
                 errs.append(&mut err);
+                if errs.len() >= MAX_SYNTAX_ERRORS {
+                    return Err((ctx, errs));
+                }
             }
         }
     }
@@ -460,6 +659,17 @@ pub fn statement<'t>(ctx: Context<'t>) -> ParseResult<'t, Statement> {
             (ctx, Ret { value })
         }
 
+        // `loop <pattern> in <expression> <statement>`, e.g. `loop (k, v) in dict { a += v }`
+        [T::Loop, ..] if is_loop_in(ctx.skip(1)) => {
+            let (ctx, pattern) = pattern(ctx.skip(1))?;
+            let (ctx, iterable) = expression(ctx.skip(1))?;
+            let (ctx, body) = statement(ctx)?;
+            (
+                ctx.prev(),
+                LoopIn { pattern, iterable, body: Box::new(body) },
+            )
+        }
+
         // `loop <expression> <statement>`, e.g. `loop a < 10 { a += 1 }`
         [T::Loop, ..] => {
             let ctx = ctx.skip(1);
@@ -475,7 +685,7 @@ pub fn statement<'t>(ctx: Context<'t>) -> ParseResult<'t, Statement> {
             (ctx.prev(), Loop { condition, body: Box::new(body) })
         }
 
-        // `case <expression> do (<branch>)* [else <statement> end] end`
+        // `case <expression> do (<pattern> [if <expression>] <statement>)* [else <statement>] end`
         [T::Case, ..] => {
             let (ctx, skip_newlines) = ctx.push_skip_newlines(true);
             let (ctx, to_match) = expression(ctx.skip(1))?;
@@ -484,7 +694,7 @@ pub fn statement<'t>(ctx: Context<'t>) -> ParseResult<'t, Statement> {
             let mut branches = Vec::new();
             loop {
                 match ctx.token() {
-                    T::EOF | T::Else => {
+                    T::EOF | T::Else | T::End => {
                         break;
                     }
 
@@ -492,58 +702,32 @@ pub fn statement<'t>(ctx: Context<'t>) -> ParseResult<'t, Statement> {
                         ctx = ctx.skip(1);
                     }
 
-                    T::Identifier(pattern) if is_capitalized(pattern) => {
-                        let pattern = Identifier { name: pattern.clone(), span: ctx.span() };
-                        ctx = ctx.skip(1);
-                        let (ctx_, variable) = match ctx.token() {
-                            T::Identifier(capture) if !is_capitalized(capture) => (
-                                ctx.skip(1),
-                                Some(Identifier { name: capture.clone(), span: ctx.span() }),
-                            ),
-                            T::Identifier(_) => {
-                                raise_syntax_error!(
-                                    ctx,
-                                    "Variables have to start with a lowercase letter"
-                                );
-                            }
-                            _ => (ctx, None),
+                    _ => {
+                        let (ctx_, pattern) = pattern(ctx)?;
+                        let (ctx_, guard) = if matches!(ctx_.token(), T::If) {
+                            let (ctx_, guard) = expression(ctx_.skip(1))?;
+                            (ctx_, Some(guard))
+                        } else {
+                            (ctx_, None)
                         };
                         let (ctx_, body) = statement_or_block(ctx_)?;
                         ctx = ctx_;
 
-                        branches.push(CaseBranch { pattern, variable, body });
-                    }
-
-                    T::Identifier(_) => {
-                        raise_syntax_error!(
-                            ctx,
-                            "Enum variants have to start with a captial letter"
-                        );
-                    }
-
-                    _ => {
-                        raise_syntax_error!(
-                            ctx,
-                            "Expected a branch - but a branch cannot start with {:?}",
-                            ctx.token()
-                        );
+                        branches.push(CaseBranch { pattern, guard, body });
                     }
                 }
             }
-            let ctx = expect!(ctx, T::Else, "Expected - else on case-statement");
-            let (ctx, fall_through) = statement_or_block(ctx)?;
+            let (ctx, fall_through) = if matches!(ctx.token(), T::Else) {
+                let (ctx, fall_through) = statement_or_block(ctx.skip(1))?;
+                (ctx, Some(Box::new(fall_through)))
+            } else {
+                (ctx, None)
+            };
 
             let ctx = ctx.pop_skip_newlines(skip_newlines);
             let ctx = expect!(ctx, T::End, "Expected 'end' to finish of case-statement");
 
-            (
-                ctx,
-                Case {
-                    to_match,
-                    branches,
-                    fall_through: Box::new(fall_through),
-                },
-            )
+            (ctx, Case { to_match, branches, fall_through })
         }
 
         // `if <expression> <statement> [else <statement>]`. Note that the else is optional.
@@ -865,6 +1049,9 @@ mod test {
     test!(statement, statement_if_else: "if 1 do a else do b end\n" => _);
     test!(statement, statement_loop: "loop 1 { a }\n" => _);
     test!(statement, statement_loop_no_condition: "loop do a end\n" => _);
+    test!(statement, statement_loop_in: "loop x in xs do a end\n" => StatementKind::LoopIn { .. });
+    test!(statement, statement_loop_in_tuple: "loop (k, v) in d do a end\n" => StatementKind::LoopIn { .. });
+    test!(statement, statement_loop_contains: "loop (x in xs) do a end\n" => StatementKind::Loop { .. });
     test!(statement, statement_ret: "ret 1 + 1\n" => _);
     test!(statement, statement_ret_newline: "ret \n" => _);
     test!(statement, statement_unreach: "<!>\n" => _);
@@ -884,6 +1071,12 @@ mod test {
     test!(statement, statement_is_check: ":A is :B\n" => IsCheck { .. });
     test!(statement, statement_is_check_nested: ":a.c.D is :b.d.D\n" => IsCheck { .. });
 
+    test!(statement, statement_case: "case a do\n X x do end\n Y do end\n else do end\n end\n" => Case { .. });
+    test!(statement, statement_case_literals: "case a do\n 1 do end\n -1 do end\n \"a\" do end\n true do end\n else do end\n end\n" => Case { .. });
+    test!(statement, statement_case_nested: "case a do\n X (Y (a, _)) as b do end\n else do end\n end\n" => Case { .. });
+    test!(statement, statement_case_tuples: "case a do\n () do end\n (a,) do end\n ((a)) do end\n else do end\n end\n" => Case { .. });
+    test!(statement, statement_case_guard: "case a do\n (a, 1) if a > 2 do end\n _ ret 1\n else do end\n end\n" => Case { .. });
+
     test!(statement, statement_if_newline: "if 1 \n\n+\n 1\n\n < 2 do end\n" => _);
 
     test!(statement, statement_skip_newline: "(1 \n\n+\n 1\n\n)\n" => _);
@@ -913,9 +1106,12 @@ mod test {
 
     fail!(statement, statement_blob_newline: "A :: blob { a: int\n b: int }\n" => _);
     fail!(statement, statement_blob_self: "A :: blob { self: int }" => _);
+    fail!(statement, statement_loop_in_no_iterable: "loop x in do a end\n" => _);
     fail!(statement, statement_assign_self_const: "self :: 1" => _);
     fail!(statement, statement_assign_self_var: "self := 1" => _);
     fail!(statement, statement_assign_self_type: "self: int = 1" => _);
+    fail!(statement, statement_case_missing_pattern: "case a do\n (,) do end\n else do end\n end\n" => _);
+    fail!(statement, statement_case_as_variant: "case a do\n X as Y do end\n else do end\n end\n" => _);
     fail!(statement, outer_statement_from_invalid: "from b use a!" => _);
     fail!(statement, outer_statement_from_alias_invalid: "from b use a as !" => _);
 }
@@ -1,5 +1,7 @@
 #[cfg(feature = "lingon")]
 pub mod lingon;
+#[cfg(feature = "love")]
+pub mod love;
 #[cfg(feature = "network")]
 pub mod network;
 pub mod sylt;
@@ -0,0 +1,859 @@
+//! A headless stand-in for the parts of LÖVE that `fallgamejam/love.sy`
+//! declares, so the games written against it run on the VM.
+//!
+//! Nothing is drawn or played. The calls to `love.graphics` are written to a
+//! log of [Draw]s, the input comes from a queue that the host fills with
+//! [queue], and time moves [STEP] seconds every frame. The host moves to the
+//! next frame with [next_frame], and calls the callbacks the game put in
+//! [table].
+//!
+//! The state is kept per thread, [reset] starts over.
+
+use crate as sylt_std;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use sylt_common::error::RuntimeError;
+use sylt_common::{ExternalValue, Value};
+
+/// The time between two frames, in seconds.
+pub const STEP: f64 = 1.0 / 60.0;
+
+/// The size of the window, until the game sets it.
+const WINDOW: (i64, i64) = (800, 600);
+
+/// A call to `love.graphics` that draws something, or changes how
+/// things are drawn.
+#[derive(Debug, Clone)]
+pub struct Draw {
+    /// The name of the function in `love.graphics`.
+    pub call: &'static str,
+    pub args: Vec<Value>,
+}
+
+/// Input from the player. Keys have the names LÖVE gives them, and the
+/// mouse buttons are numbered like in `love.mouse.isDown`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    KeyPressed(String),
+    KeyReleased(String),
+    MouseMoved(f64, f64),
+    MousePressed(i64),
+    MouseReleased(i64),
+}
+
+struct Love {
+    /// The `love` blob, where the game puts its callbacks.
+    table: Value,
+    /// The functions that are put in the blobs made while the game runs,
+    /// looked up when the program is linked.
+    methods: HashMap<&'static str, Value>,
+    /// Where the files the game loads are read from.
+    root: PathBuf,
+    frame: usize,
+    draws: Vec<Draw>,
+    /// The inputs that haven't happened yet, and the frame they happen in.
+    inputs: Vec<(usize, Input)>,
+    keys: HashSet<String>,
+    buttons: HashSet<i64>,
+    mouse: (f64, f64),
+    color: (f64, f64, f64, f64),
+    window: (i64, i64),
+    /// The size of the images, by the id of their blob.
+    images: HashMap<i64, (f64, f64)>,
+    objects: i64,
+    random: u64,
+    quit: Option<i64>,
+}
+
+impl Love {
+    fn new(root: &Path) -> Self {
+        Self {
+            table: Value::Nil,
+            methods: HashMap::new(),
+            root: root.to_path_buf(),
+            frame: 0,
+            draws: Vec::new(),
+            inputs: Vec::new(),
+            keys: HashSet::new(),
+            buttons: HashSet::new(),
+            mouse: (0.0, 0.0),
+            color: (1.0, 1.0, 1.0, 1.0),
+            window: WINDOW,
+            images: HashMap::new(),
+            objects: 0,
+            random: 0x2545_f491_4f6c_dd1d,
+            quit: None,
+        }
+    }
+
+    /// A new id for the `_unique_type` field of a blob.
+    fn object(&mut self) -> i64 {
+        self.objects += 1;
+        self.objects
+    }
+
+    fn method(&self, name: &str) -> Value {
+        self.methods.get(name).cloned().unwrap_or(Value::Nil)
+    }
+
+    /// Xorshift, the same numbers come every time the game is run.
+    fn random(&mut self) -> u64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        self.random
+    }
+}
+
+std::thread_local! {
+    static LOVE: RefCell<Love> = RefCell::new(Love::new(Path::new(".")));
+}
+
+fn with<T>(f: impl FnOnce(&mut Love) -> T) -> T {
+    LOVE.with(|love| f(&mut love.borrow_mut()))
+}
+
+/// Starts over, without any input or draws. The files the game loads are
+/// read relative to `root`.
+pub fn reset(root: &Path) {
+    with(|love| *love = Love::new(root));
+}
+
+/// Makes the input happen at the start of `frame`. The first frame is 1.
+pub fn queue(frame: usize, input: Input) {
+    with(|love| love.inputs.push((frame, input)));
+}
+
+/// Moves time forward and forgets the draws of the last frame. Gives
+/// the inputs that happen in the new frame, the game is told about them
+/// by the host.
+pub fn next_frame() -> Vec<Input> {
+    with(|love| {
+        love.frame += 1;
+        love.draws.clear();
+        let frame = love.frame;
+        let (now, later) = love.inputs.drain(..).partition(|(at, _)| *at <= frame);
+        love.inputs = later;
+
+        let now: Vec<_> = now.into_iter().map(|(_, input)| input).collect();
+        for input in now.iter() {
+            match input {
+                Input::KeyPressed(key) => {
+                    love.keys.insert(key.clone());
+                }
+                Input::KeyReleased(key) => {
+                    love.keys.remove(key);
+                }
+                Input::MouseMoved(x, y) => love.mouse = (*x, *y),
+                Input::MousePressed(button) => {
+                    love.buttons.insert(*button);
+                }
+                Input::MouseReleased(button) => {
+                    love.buttons.remove(button);
+                }
+            }
+        }
+        now
+    })
+}
+
+/// The number of frames that have started.
+pub fn frame() -> usize {
+    with(|love| love.frame)
+}
+
+/// What was drawn in this frame, in the order it was drawn.
+pub fn draws() -> Vec<Draw> {
+    with(|love| love.draws.clone())
+}
+
+/// The exit code, if the game has quit.
+pub fn quit() -> Option<i64> {
+    with(|love| love.quit)
+}
+
+/// The `love` blob of the program that was linked last.
+pub fn table() -> Value {
+    with(|love| love.table.clone())
+}
+
+/// The values this module gives to `external` definitions, the `love`
+/// blob and `varargs`.
+pub fn externals() -> Vec<(String, ExternalValue, String)> {
+    let love: ExternalValue = Rc::new(|function| link(function));
+    let varargs: ExternalValue = Rc::new(|function| function("love_varargs").unwrap_or(Value::Nil));
+    vec![
+        ("love".to_string(), love, "Love".to_string()),
+        (
+            "varargs".to_string(),
+            varargs,
+            "fn fn * -> * -> fn [*] -> *".to_string(),
+        ),
+    ]
+}
+
+fn blob(name: &str, fields: Vec<(&str, Value)>) -> Value {
+    let mut values: HashMap<_, _> = fields
+        .into_iter()
+        .map(|(field, value)| (field.to_string(), value))
+        .collect();
+    values.insert("_name".to_string(), Value::from(name));
+    Value::Blob(Rc::new(RefCell::new(values)))
+}
+
+fn list(values: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(values)))
+}
+
+/// Builds the `love` blob out of the functions in this module.
+fn link(function: &dyn Fn(&str) -> Option<Value>) -> Value {
+    let f = |name: &str| function(name).unwrap_or(Value::Nil);
+
+    let graphics = blob(
+        "LoveGraphics",
+        vec![
+            ("draw", f("love_graphics_draw")),
+            ("line", f("love_graphics_line")),
+            ("rectangle", f("love_graphics_rectangle")),
+            ("circle", f("love_graphics_circle")),
+            ("arc", f("love_graphics_arc")),
+            ("points", f("love_graphics_points")),
+            ("setLineWidth", f("love_graphics_set_line_width")),
+            ("setColor", f("love_graphics_set_color")),
+            ("getColor", f("love_graphics_get_color")),
+            ("print", f("love_graphics_print")),
+            ("printf", f("love_graphics_printf")),
+            ("getFont", f("love_graphics_get_font")),
+            ("setFont", f("love_graphics_set_font")),
+            ("newImage", f("love_graphics_new_image")),
+            ("newFont", f("love_graphics_new_font")),
+            ("newParticleSystem", f("love_graphics_new_particle_system")),
+            ("newQuad", f("love_graphics_new_quad")),
+            ("newShader", f("love_graphics_new_shader")),
+            ("setShader", f("love_graphics_set_shader")),
+            ("getWidth", f("love_graphics_get_width")),
+            ("getHeight", f("love_graphics_get_height")),
+            ("origin", f("love_graphics_origin")),
+            ("translate", f("love_graphics_translate")),
+            ("scale", f("love_graphics_scale")),
+        ],
+    );
+    let keyboard = blob(
+        "LoveKeyboard",
+        vec![
+            ("isDown", f("love_keyboard_is_down")),
+            ("isScancodeDown", f("love_keyboard_is_down")),
+        ],
+    );
+    let mouse = blob(
+        "LoveMouse",
+        vec![
+            ("isDown", f("love_mouse_is_down")),
+            ("getX", f("love_mouse_get_x")),
+            ("getY", f("love_mouse_get_y")),
+            ("setPosition", f("love_mouse_set_position")),
+        ],
+    );
+    let timer = blob(
+        "LoveTimer",
+        vec![
+            ("getTime", f("love_timer_get_time")),
+            ("getDelta", f("love_timer_get_delta")),
+        ],
+    );
+    let window = blob(
+        "LoveWindow",
+        vec![
+            ("setMode", f("love_window_set_mode")),
+            ("setIcon", f("love_ok")),
+            ("setTitle", f("love_nothing")),
+            ("setFullscreen", f("love_ok")),
+            ("getFullscreenModes", f("love_window_get_fullscreen_modes")),
+            ("requestAttention", f("love_nothing")),
+            ("getDisplayCount", f("love_window_get_display_count")),
+        ],
+    );
+    let audio = blob(
+        "LoveAudio",
+        vec![
+            ("newSource", f("love_audio_new_source")),
+            ("play", f("love_nothing")),
+            ("pause", f("love_nothing")),
+            ("stop", f("love_nothing")),
+            ("rewind", f("love_nothing")),
+            ("resume", f("love_nothing")),
+            ("setEffect", f("love_ok")),
+        ],
+    );
+    let event = blob("LoveEvent", vec![("quit", f("love_event_quit"))]);
+    let math = blob("LoveMath", vec![("random", f("love_math_random"))]);
+
+    let callbacks = [
+        "init",
+        "draw",
+        "update",
+        "keypressed",
+        "keyreleased",
+        "resize",
+        "gamepadaxis",
+        "gamepadpressed",
+        "gamepadreleased",
+    ];
+    let mut fields: Vec<_> = callbacks.iter().map(|name| (*name, Value::Nil)).collect();
+    fields.extend(vec![
+        ("audio", audio),
+        ("graphics", graphics),
+        ("keyboard", keyboard),
+        ("math", math),
+        ("mouse", mouse),
+        ("timer", timer),
+        ("window", window),
+        ("event", event),
+    ]);
+    let table = blob("Love", fields);
+
+    let methods = [
+        "love_nothing",
+        "love_ok",
+        "love_image_get_width",
+        "love_image_get_height",
+        "love_source_clone",
+    ];
+    with(|love| {
+        love.methods = methods.iter().map(|name| (*name, f(name))).collect();
+        love.table = table.clone();
+    });
+    table
+}
+
+fn record(call: &'static str, args: &[Value]) -> Result<Value, RuntimeError> {
+    with(|love| love.draws.push(Draw { call, args: args.to_vec() }));
+    Ok(Value::Nil)
+}
+
+fn source() -> Value {
+    with(|love| {
+        let nothing = love.method("love_nothing");
+        blob(
+            "Source",
+            vec![
+                ("_002_unique_type", Value::Int(love.object())),
+                ("setEffect", love.method("love_ok")),
+                ("setPosition", nothing.clone()),
+                ("setVolume", nothing.clone()),
+                ("setPitch", nothing.clone()),
+                ("setLooping", nothing),
+                ("clone", love.method("love_source_clone")),
+            ],
+        )
+    })
+}
+
+/// Reads the size from the header of a PNG file.
+fn png_size(path: &Path) -> Option<(f64, f64)> {
+    let bytes = std::fs::read(path).ok()?;
+    if bytes.len() < 24 || &bytes[1..4] != b"PNG" || &bytes[12..16] != b"IHDR" {
+        return None;
+    }
+    let number =
+        |at: usize| u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    Some((number(16) as f64, number(20) as f64))
+}
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_nothing,
+    ? "Does nothing, for the parts of LÖVE that don't change what the game sees",
+    -> "fn -> void",
+    _ => {
+        Ok(Nil)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_ok,
+    ? "Does nothing, and says that it worked",
+    -> "fn -> bool",
+    _ => {
+        Ok(Bool(true))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_varargs,
+    ? "Passes a list to a function of LÖVE, which takes its arguments as they come",
+    -> "fn fn * -> * -> fn [*] -> *",
+    [ExternFunction(slot)] => {
+        Ok(ExternFunction(*slot))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_draw,
+    ? "Draws an image, a quad of an image or particles",
+    -> "fn *, float, float, float, float, float, float, float, float -> void",
+    args => {
+        record("draw", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_line,
+    ? "Draws a line between two points",
+    -> "fn float, float, float, float -> void",
+    args => {
+        record("line", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_rectangle,
+    ? "Draws a rectangle, the first argument is \"fill\" or \"line\"",
+    -> "fn str, float, float, float, float -> void",
+    args => {
+        record("rectangle", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_circle,
+    ? "Draws a circle, the first argument is \"fill\" or \"line\"",
+    -> "fn str, float, float, float -> void",
+    args => {
+        record("circle", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_arc,
+    ? "Draws an arc, the first argument is \"fill\" or \"line\"",
+    -> "fn str, float, float, float -> void",
+    args => {
+        record("arc", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_points,
+    ? "Draws points",
+    -> "fn [(float, float)] -> void",
+    args => {
+        record("points", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_set_line_width,
+    ? "Sets the width of the lines that are drawn",
+    -> "fn float -> void",
+    args => {
+        record("setLineWidth", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_set_color,
+    ? "Sets the color things are drawn with",
+    -> "fn (float, float, float, float) -> void",
+    [Tuple(color)] => {
+        if let [Float(r), Float(g), Float(b), Float(a)] = color.as_slice() {
+            with(|love| love.color = (*r, *g, *b, *a));
+        }
+        record("setColor", &[Tuple(Rc::clone(color))])
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_get_color,
+    ? "Returns the color things are drawn with",
+    -> "fn -> (float, float, float, float)",
+    [] => {
+        let (r, g, b, a) = with(|love| love.color);
+        Ok(Tuple(Rc::new(vec![Float(r), Float(g), Float(b), Float(a)])))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_print,
+    ? "Draws text",
+    -> "fn str, float, float, float, float, float -> void",
+    args => {
+        record("print", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_printf,
+    ? "Draws text that is wrapped and aligned",
+    -> "fn str, float, float, float, str, float, float, float -> void",
+    args => {
+        record("printf", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_get_font,
+    ? "Returns the font text is drawn with, which is the default font",
+    -> "fn -> *",
+    [] => {
+        Ok(blob("Font", vec![("_004_unique_type", Int(0))]))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_set_font,
+    ? "Sets the font text is drawn with",
+    -> "fn * -> void",
+    args => {
+        record("setFont", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_new_image,
+    ? "Loads an image, only the size is read from the file",
+    -> "fn str -> *",
+    [String(path)] => {
+        with(|love| {
+            let size = png_size(&love.root.join(path.as_str())).unwrap_or((0.0, 0.0));
+            let id = love.object();
+            love.images.insert(id, size);
+            Ok(blob(
+                "ImageData",
+                vec![
+                    ("_001_unique_type", Int(id)),
+                    ("getWidth", love.method("love_image_get_width")),
+                    ("getHeight", love.method("love_image_get_height")),
+                ],
+            ))
+        })
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_image_get_width,
+    ? "Returns the width of an image",
+    -> "fn * -> float",
+    [Blob(image)] => {
+        let id = image.borrow().get("_001_unique_type").cloned();
+        Ok(Float(with(|love| match id {
+            Some(Int(id)) => love.images.get(&id).map_or(0.0, |size| size.0),
+            _ => 0.0,
+        })))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_image_get_height,
+    ? "Returns the height of an image",
+    -> "fn * -> float",
+    [Blob(image)] => {
+        let id = image.borrow().get("_001_unique_type").cloned();
+        Ok(Float(with(|love| match id {
+            Some(Int(id)) => love.images.get(&id).map_or(0.0, |size| size.1),
+            _ => 0.0,
+        })))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_new_font,
+    ? "Loads a font",
+    -> "fn str, int -> *",
+    [String(_), Int(_)] => {
+        Ok(blob("Font", vec![("_004_unique_type", Int(with(Love::object)))]))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_new_particle_system,
+    ? "Creates particles, which are never drawn",
+    -> "fn *, int -> *",
+    [_, Int(_)] => {
+        let nothing = with(|love| love.method("love_nothing"));
+        let methods = [
+            "emit",
+            "setParticleLifetime",
+            "setEmissionRate",
+            "setLinearAcceleration",
+            "setLinearDamping",
+            "setPosition",
+            "setDirection",
+            "setSizes",
+            "setSpeed",
+            "setSpread",
+            "setColors",
+            "update",
+        ];
+        let fields = methods.iter().map(|name| (*name, nothing.clone())).collect();
+        Ok(blob("ParticleSystem", fields))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_new_quad,
+    ? "Creates a part of an image, to draw",
+    -> "fn int, int, int, int, * -> *",
+    [Int(_), Int(_), Int(_), Int(_), _] => {
+        Ok(blob("Quad", vec![("_003_unique_type", Int(with(Love::object)))]))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_new_shader,
+    ? "Creates a shader, which is never run",
+    -> "fn str -> *",
+    [String(_)] => {
+        with(|love| {
+            Ok(blob(
+                "Shader",
+                vec![
+                    ("_005_unique_type", Int(love.object())),
+                    ("send", love.method("love_nothing")),
+                ],
+            ))
+        })
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_set_shader,
+    ? "Sets the shader things are drawn with, or removes it",
+    -> "fn * -> void",
+    args => {
+        record("setShader", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_get_width,
+    ? "Returns the width of the window",
+    -> "fn -> int",
+    [] => {
+        Ok(Int(with(|love| love.window.0)))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_get_height,
+    ? "Returns the height of the window",
+    -> "fn -> int",
+    [] => {
+        Ok(Int(with(|love| love.window.1)))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_origin,
+    ? "Removes the translation and scaling",
+    -> "fn -> void",
+    args => {
+        record("origin", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_translate,
+    ? "Moves the things that are drawn",
+    -> "fn float, float -> void",
+    args => {
+        record("translate", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_graphics_scale,
+    ? "Scales the things that are drawn",
+    -> "fn float, float -> void",
+    args => {
+        record("scale", args)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_keyboard_is_down,
+    ? "Returns true if the key is held down",
+    -> "fn str -> bool",
+    [String(key)] => {
+        Ok(Bool(with(|love| love.keys.contains(key.as_str()))))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_mouse_is_down,
+    ? "Returns true if the mouse button is held down",
+    -> "fn int -> bool",
+    [Int(button)] => {
+        Ok(Bool(with(|love| love.buttons.contains(button))))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_mouse_get_x,
+    ? "Returns where the mouse is along the x-axis",
+    -> "fn -> float",
+    [] => {
+        Ok(Float(with(|love| love.mouse.0)))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_mouse_get_y,
+    ? "Returns where the mouse is along the y-axis",
+    -> "fn -> float",
+    [] => {
+        Ok(Float(with(|love| love.mouse.1)))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_mouse_set_position,
+    ? "Moves the mouse",
+    -> "fn float, float -> void",
+    [Float(x), Float(y)] => {
+        with(|love| love.mouse = (*x, *y));
+        Ok(Nil)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_timer_get_time,
+    ? "Returns the time since the game started, in seconds",
+    -> "fn -> float",
+    [] => {
+        Ok(Float(with(|love| love.frame as f64 * STEP)))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_timer_get_delta,
+    ? "Returns the time between two frames, in seconds",
+    -> "fn -> float",
+    [] => {
+        Ok(Float(STEP))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_window_set_mode,
+    ? "Sets the size of the window, a size of zero keeps the old size",
+    -> "fn int, int, * -> bool",
+    [Int(width), Int(height), _] => {
+        with(|love| {
+            if *width > 0 && *height > 0 {
+                love.window = (*width, *height);
+            }
+        });
+        Ok(Bool(true))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_window_get_fullscreen_modes,
+    ? "Returns the sizes the window can have, which is the size it has",
+    -> "fn int -> [*]",
+    [Int(_)] => {
+        let (width, height) = with(|love| love.window);
+        let mode = blob("WindowMode", vec![("width", Int(width)), ("height", Int(height))]);
+        Ok(list(vec![mode]))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_window_get_display_count,
+    ? "Returns the number of displays, there is one",
+    -> "fn -> int",
+    [] => {
+        Ok(Int(1))
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_audio_new_source,
+    ? "Creates a sound, which is never played",
+    -> "fn str, str -> *",
+    [String(_), String(_)] => {
+        Ok(source())
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_source_clone,
+    ? "Copies a sound",
+    -> "fn * -> *",
+    [Blob(_)] => {
+        Ok(source())
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_event_quit,
+    ? "Quits the game, after the frame",
+    -> "fn int -> void",
+    [Int(code)] => {
+        with(|love| love.quit = Some(*code));
+        Ok(Nil)
+    },
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::love",
+    love_math_random,
+    ? "Returns a random int between the bounds. The numbers are the same every time the game runs",
+    -> "fn int, int -> int",
+    [Int(lo), Int(hi)] => {
+        let range = (hi - lo + 1).max(1) as u64;
+        Ok(Int(lo + (with(Love::random) % range) as i64))
+    },
+    // LÖVE returns a float between 0 and 1 when there are no arguments. The
+    // signature can't say that, so games reach it through `unsafe_force`.
+    [] => {
+        Ok(Float(with(Love::random) as f64 / u64::MAX as f64))
+    },
+);
+
+sylt_macro::sylt_link_gen!("sylt_std::love");
@@ -6,9 +6,15 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use sungod::Ra;
-use sylt_common::error::RuntimeError;
+use sylt_common::error::{Error, RuntimeError};
 use sylt_common::{RuntimeContext, Type, Value};
 
+/// The VM reports the error from inside the callback, this only
+/// stops the function that called it.
+fn callback_failed(function: &str, _: Error) -> RuntimeError {
+    RuntimeError::ExternError(function.to_string(), "The callback failed".to_string())
+}
+
 sylt_macro::extern_function!(
     "sylt_std::sylt",
     atan2,
@@ -52,7 +58,9 @@ sylt_macro::extern_function!(
         let list = Rc::clone(list);
         let callable = callable.clone();
         for element in list.borrow().iter() {
-            ctx.machine.eval_call(callable.clone(), &[element]).unwrap();
+            ctx.machine
+                .eval_call(callable.clone(), &[element])
+                .map_err(|e| callback_failed("for_each", e))?;
         }
         Ok(Nil)
     }
@@ -69,8 +77,9 @@ sylt_macro::extern_function!(
         let mapped = list
             .borrow()
             .iter()
-            .map(|element| ctx.machine.eval_call(callable.clone(), &[element]).unwrap())
-            .collect();
+            .map(|element| ctx.machine.eval_call(callable.clone(), &[element]))
+            .collect::<Result<_, _>>()
+            .map_err(|e| callback_failed("map", e))?;
         Ok(List(Rc::new(RefCell::new(mapped))))
     }
 );
@@ -83,12 +92,14 @@ sylt_macro::extern_function!(
     [List(list), callable] => {
         let list = Rc::clone(list);
         let callable = callable.clone();
-        let reduced = list
-            .borrow()
-            .iter()
-            .cloned()
-            .reduce(|a, b| ctx.machine.eval_call(callable.clone(), &[&a, &b]).unwrap());
-        Ok(reduced.unwrap_or(Value::Nil))
+        let list = list.borrow();
+        let mut elements = list.iter();
+        match elements.next() {
+            Some(first) => elements
+                .try_fold(first.clone(), |a, b| ctx.machine.eval_call(callable.clone(), &[&a, b]))
+                .map_err(|e| callback_failed("reduce", e)),
+            None => Ok(Value::Nil),
+        }
     }
 );
 
@@ -103,7 +114,8 @@ sylt_macro::extern_function!(
         let folded = list
             .borrow()
             .iter()
-            .fold(start.clone(), |a, b| ctx.machine.eval_call(callable.clone(), &[&a, &b]).unwrap());
+            .try_fold(start.clone(), |a, b| ctx.machine.eval_call(callable.clone(), &[&a, b]))
+            .map_err(|e| callback_failed("fold", e))?;
         Ok(folded)
     }
 );
@@ -287,7 +299,7 @@ sylt_macro::extern_function!(
     "sylt_std::sylt",
     as_chars,
     ? "Converts an ASCII string into a list of chars. Non-ASCII is converted to '?'.",
-    -> "str -> [int]",
+    -> "fn str -> [int]",
     [Value::String(s)] => {
         let chars = s
             .chars()
@@ -552,4 +564,40 @@ sylt_macro::extern_function!(
     }
 );
 
+sylt_macro::extern_function!(
+    "sylt_std::sylt",
+    gc_collect,
+    ? "Frees values that are only kept alive by reference cycles",
+    -> "fn -> void",
+    [] => {
+        ctx.machine.collect_garbage();
+        Ok(Nil)
+    }
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::sylt",
+    gc_stats,
+    ? "Returns the number of living objects, collections run and objects freed by the collector",
+    -> "fn -> {str:int}",
+    [] => {
+        let stats = ctx.machine.gc_stats();
+        let mut dict = HashMap::new();
+        dict.insert(Value::from("allocated"), Int(stats.allocated as i64));
+        dict.insert(Value::from("collections"), Int(stats.collections as i64));
+        dict.insert(Value::from("freed"), Int(stats.freed as i64));
+        Ok(Dict(Rc::new(RefCell::new(dict))))
+    }
+);
+
+sylt_macro::extern_function!(
+    "sylt_std::sylt",
+    done,
+    ? "Returns true if the function of the coroutine has returned",
+    -> "fn *X -> bool",
+    [Value::Coroutine(coroutine)] => {
+        Ok(Bool(coroutine.borrow().is_done()))
+    }
+);
+
 sylt_macro::sylt_link_gen!("sylt_std::sylt");
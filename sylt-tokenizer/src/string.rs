@@ -0,0 +1,235 @@
+//! String literals.
+//!
+//! The lexer only finds where a string literal ends. Escapes and
+//! interpolations are resolved afterwards by [resolve], since the errors and
+//! the interpolated tokens need to know where in the file they are.
+//!
+//! ```ignored
+//! "a\tb"          // Escapes: \n \t \r \0 \\ \" \{ \} \u{1F980}
+//! "hp: {guy.hp}"  // Interpolation
+//! "a \
+//!  b"             // A '\' at the end of a line skips the line break
+//! r"C:\no\{escapes}"
+//! r#"can contain ""#
+//! ```
+use logos::Lexer;
+
+use crate::{lex_range, PlacedToken, Positions, Token};
+
+/// Finds the end of a string literal. The opening `"` has already been
+/// lexed. Returns the contents between the quotes, as they are written.
+pub(crate) fn unresolved(lex: &mut Lexer<Token>) -> Option<String> {
+    let rest = lex.remainder();
+    let len = string_length(rest.as_bytes())?;
+    lex.bump(len + 1);
+    Some(rest[..len].to_string())
+}
+
+/// Lexes a raw string literal, e.g. `r"a"` or `r##"a"##`. The `r`, the
+/// hashes and the opening `"` have already been lexed.
+pub(crate) fn raw(lex: &mut Lexer<Token>) -> Option<String> {
+    let rest = lex.remainder();
+    let len = raw_string_length(rest.as_bytes(), lex.slice().len() - 2)?;
+    lex.bump(len + lex.slice().len() - 1);
+    Some(rest[..len].to_string())
+}
+
+/// The length of the contents of a string, excluding the closing `"`.
+fn string_length(bytes: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'"' => return Some(i),
+            b'{' => i += interpolation_length(&bytes[i + 1..])? + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// The length of the contents of a raw string with `hashes` number of `#`,
+/// excluding the closing `"` and hashes.
+fn raw_string_length(bytes: &[u8], hashes: usize) -> Option<usize> {
+    (0..bytes.len()).find(|&i| {
+        bytes[i] == b'"'
+            && bytes.len() > i + hashes
+            && bytes[i + 1..=i + hashes].iter().all(|b| *b == b'#')
+    })
+}
+
+/// The length of an interpolated expression, excluding the closing `}`.
+///
+/// Braces and strings can be nested in the expression, e.g. `{ {1: "}"} }`.
+fn interpolation_length(bytes: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => i += string_length(&bytes[i + 1..])? + 1,
+            b'r' if i == 0 || !is_identifier_byte(bytes[i - 1]) => {
+                let hashes = bytes[i + 1..].iter().take_while(|b| **b == b'#').count();
+                if bytes.get(i + 1 + hashes) == Some(&b'"') {
+                    let start = i + hashes + 2;
+                    i = start + raw_string_length(&bytes[start..], hashes)? + hashes;
+                }
+            }
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some(i),
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+fn next_char(content: &str, at: usize) -> char {
+    content[at..].chars().next().unwrap()
+}
+
+/// Resolves escapes and interpolations of the string literal at
+/// `start..end` in `content`, quotes included.
+///
+/// A string without interpolations becomes a single [Token::String]. A string
+/// with interpolations is split up into [Token::InterpolationStart],
+/// [Token::InterpolationContinue] and [Token::InterpolationEnd] with the tokens
+/// of the interpolated expressions in between.
+pub(crate) fn resolve(
+    positions: &Positions,
+    content: &str,
+    start: usize,
+    end: usize,
+) -> Vec<PlacedToken> {
+    let error = |from: usize, to: usize, message: String| {
+        vec![PlacedToken {
+            token: Token::StringError(message),
+            span: positions.span(from, to),
+        }]
+    };
+
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut interpolated = false;
+    let mut segment_start = start;
+
+    let body_end = end - 1;
+    let mut i = start + 1;
+    while i < body_end {
+        match next_char(content, i) {
+            '\\' => {
+                let escaped = next_char(content, i + 1);
+                let mut escape_end = i + 1 + escaped.len_utf8();
+                match escaped {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    'r' => text.push('\r'),
+                    '0' => text.push('\0'),
+                    '\\' | '"' | '{' | '}' => text.push(escaped),
+                    'u' => {
+                        let digits = content[escape_end..body_end]
+                            .strip_prefix('{')
+                            .and_then(|rest| rest.split_once('}'))
+                            .map(|(digits, _)| digits);
+                        let c = digits
+                            .filter(|digits| (1..=6).contains(&digits.len()))
+                            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                            .and_then(std::char::from_u32);
+                        match (digits, c) {
+                            (Some(digits), Some(c)) => {
+                                text.push(c);
+                                escape_end += digits.len() + 2;
+                            }
+                            (Some(digits), None) => {
+                                return error(
+                                    i,
+                                    escape_end + digits.len() + 2,
+                                    format!("Invalid unicode escape '\\u{{{}}}'", digits),
+                                );
+                            }
+                            (None, _) => {
+                                return error(
+                                    i,
+                                    escape_end,
+                                    "Expected a unicode escape like '\\u{1F980}'".to_string(),
+                                );
+                            }
+                        }
+                    }
+                    // Line continuation, skip the line break and the indentation.
+                    '\n' | '\r' => {
+                        escape_end = content[i + 1..body_end]
+                            .find(|c: char| !c.is_whitespace())
+                            .map(|offset| i + 1 + offset)
+                            .unwrap_or(body_end);
+                    }
+                    c => {
+                        return error(
+                            i,
+                            escape_end,
+                            format!("Unknown escape sequence '\\{}'", c.escape_debug()),
+                        );
+                    }
+                }
+                i = escape_end;
+            }
+
+            '{' => {
+                let expr_start = i + 1;
+                let expr_end = expr_start
+                    + interpolation_length(&content.as_bytes()[expr_start..body_end])
+                        .expect("The lexer checks that interpolations are closed");
+                if content[expr_start..expr_end].trim().is_empty() {
+                    return error(
+                        i,
+                        expr_end + 1,
+                        "Expected an expression between '{' and '}' - write '\\{' for a literal '{'"
+                            .to_string(),
+                    );
+                }
+
+                let text = std::mem::take(&mut text);
+                let token = if interpolated {
+                    Token::InterpolationContinue(text)
+                } else {
+                    Token::InterpolationStart(text)
+                };
+                tokens.push(PlacedToken {
+                    token,
+                    span: positions.span(segment_start, expr_start),
+                });
+                lex_range(positions, content, expr_start, expr_end, &mut tokens);
+
+                interpolated = true;
+                segment_start = expr_end;
+                i = expr_end + 1;
+            }
+
+            '}' => {
+                return error(
+                    i,
+                    i + 1,
+                    "Unmatched '}' in string - write '\\}' for a literal '}'".to_string(),
+                );
+            }
+
+            c => {
+                text.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+
+    let token = if interpolated {
+        Token::InterpolationEnd(text)
+    } else {
+        Token::String(text)
+    };
+    tokens.push(PlacedToken { token, span: positions.span(segment_start, end) });
+    tokens
+}
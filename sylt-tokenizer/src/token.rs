@@ -1,5 +1,7 @@
 use logos::Logos;
 
+use crate::string;
+
 #[derive(Logos, Debug, PartialEq, Clone)]
 pub enum Token {
     #[regex(r"[A-Za-z_][A-Za-z0-9_]*", |lex| lex.slice().to_string())]
@@ -16,8 +18,23 @@ pub enum Token {
     #[token("str")]
     StrType,
 
-    #[regex(r#""[^"]*""#, |lex| { let mut s = lex.slice().to_string(); s.remove(0); s.pop(); s })]
+    /// A string literal without interpolations, escapes are resolved.
+    #[regex(r#"r#*""#, string::raw)]
     String(String),
+    /// A string literal as it's written in the source, without the quotes.
+    ///
+    /// Escapes and interpolations are resolved by [crate::string_to_tokens],
+    /// so the parser never sees this token.
+    #[token("\"", string::unresolved)]
+    UnresolvedString(String),
+    /// The text before the first interpolation, e.g. `"a {`.
+    InterpolationStart(String),
+    /// The text between two interpolations, e.g. `} b {`.
+    InterpolationContinue(String),
+    /// The text after the last interpolation, e.g. `} c"`.
+    InterpolationEnd(String),
+    /// A malformed string literal, placed where the problem is.
+    StringError(String),
 
     #[regex(r"[\d]+\.[\d]*|[\d]*\.[\d]+", |lex| lex.slice().parse(), priority=2)]
     Float(f64),
@@ -53,6 +70,10 @@ pub enum Token {
 
     #[token("ret")]
     Ret,
+    #[token("yield")]
+    Yield,
+    #[token("coroutine")]
+    Coroutine,
 
     #[token("+")]
     Plus,
@@ -62,6 +83,18 @@ pub enum Token {
     Star,
     #[token("/")]
     Slash,
+    #[token("%")]
+    Percent,
+    #[token("div")]
+    Div,
+    #[token("&")]
+    Ampersand,
+    #[token("^")]
+    Caret,
+    #[token("<<")]
+    LessLess,
+    #[token(">>")]
+    GreaterGreater,
     #[token("+=")]
     PlusEqual,
     #[token("-=")]
@@ -142,6 +175,10 @@ pub enum Token {
     Comma,
     #[token(".")]
     Dot,
+    #[token("..")]
+    DotDot,
+    #[token("..=")]
+    DotDotEqual,
     #[token("->")]
     Arrow,
     #[token("\n")]
@@ -1,6 +1,7 @@
 use logos::Logos;
 pub use token::Token;
 
+mod string;
 mod token;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -36,57 +37,120 @@ pub struct PlacedToken {
     pub span: Span,
 }
 
-pub fn string_to_tokens(file_id: usize, content: &str) -> Vec<PlacedToken> {
-    // A list containing which char index a specific byte index is at.
-    //
-    // Since &str contains UTF-8, a byte offset (which is what the lexer gives
-    // us) won't necessarily match up with the char index. For example, given
-    // the string "123", '3' has both byte index 3 and char index 3. However, in
-    // the string "ä23", '3' has char index 3 as before, but byte index 4 since
-    // 'ä' contains two bytes.
-    //
-    // This list ensures that the byte offset the lexer gives us can be matched
-    // with a char index. None means that the byte offset points inside a char
-    // which should not be possible.
-    let mut char_at_byte = vec![None; content.len()];
-    for (i, (pos, _)) in content.char_indices().enumerate() {
-        char_at_byte[pos] = Some(i + 1);
-    }
-    // Push a last value since the byte offset end is exclusive.
-    char_at_byte.push(Some(content.chars().count() + 1));
-
-    // We also need to keep track of the current line and which char index the
-    // previous newline was at. Given a char index we can then subtract the last
-    // newline char index and get the column in the current line of the char.
-    let mut line = 1;
-    let mut last_newline = 0;
-
-    Token::lexer(&content)
-        .spanned()
-        // Contains side-effects.
-        .map(|(token, byte_range)| {
-            let is_newline = token == Token::Newline;
-            let col_start = char_at_byte[byte_range.start].unwrap() - last_newline;
-            let col_end = char_at_byte[byte_range.end].unwrap() - last_newline;
-            let span = Span {
-                file_id,
-                col_start,
-                col_end,
-                line_start: line,
-                line_end: line,
-            };
-            if is_newline {
-                last_newline = char_at_byte[byte_range.start].unwrap();
-                line += 1;
+/// Translates byte offsets in a file into [Span]s.
+pub(crate) struct Positions {
+    file_id: usize,
+    /// A list containing which char index a specific byte index is at.
+    ///
+    /// Since &str contains UTF-8, a byte offset (which is what the lexer gives
+    /// us) won't necessarily match up with the char index. For example, given
+    /// the string "123", '3' has both byte index 3 and char index 3. However, in
+    /// the string "ä23", '3' has char index 3 as before, but byte index 4 since
+    /// 'ä' contains two bytes.
+    ///
+    /// This list ensures that the byte offset the lexer gives us can be matched
+    /// with a char index. None means that the byte offset points inside a char
+    /// which should not be possible.
+    char_at_byte: Vec<Option<usize>>,
+    /// The byte offset where each line starts.
+    line_starts: Vec<usize>,
+}
+
+impl Positions {
+    fn new(file_id: usize, content: &str) -> Self {
+        let mut char_at_byte = vec![None; content.len()];
+        for (i, (pos, _)) in content.char_indices().enumerate() {
+            char_at_byte[pos] = Some(i + 1);
+        }
+        // Push a last value since the byte offset end is exclusive.
+        char_at_byte.push(Some(content.chars().count() + 1));
+
+        let line_starts = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(pos, _)| pos + 1))
+            .collect();
+
+        Self { file_id, char_at_byte, line_starts }
+    }
+
+    /// Returns the line number and the byte offset the line starts at.
+    fn line(&self, byte: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&byte) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        (line + 1, self.line_starts[line])
+    }
+
+    /// The span of the bytes `start..end`. Tokens can cover multiple lines,
+    /// the end column is then counted from the start of the last line.
+    pub(crate) fn span(&self, start: usize, end: usize) -> Span {
+        let char_at = |byte: usize| self.char_at_byte[byte].unwrap();
+        let (line_start, start_of_first_line) = self.line(start);
+        let (line_end, start_of_last_line) = self.line(end.saturating_sub(1).max(start));
+        Span {
+            file_id: self.file_id,
+            line_start,
+            line_end,
+            col_start: char_at(start) - char_at(start_of_first_line) + 1,
+            col_end: char_at(end) - char_at(start_of_last_line) + 1,
+        }
+    }
+}
+
+/// Lexes the bytes `start..end` of `content` and appends the tokens.
+pub(crate) fn lex_range(
+    positions: &Positions,
+    content: &str,
+    mut start: usize,
+    end: usize,
+    tokens: &mut Vec<PlacedToken>,
+) {
+    'lex: loop {
+        for (token, byte_range) in Token::lexer(&content[start..end]).spanned() {
+            let (token_start, token_end) = (start + byte_range.start, start + byte_range.end);
+            match token {
+                Token::UnresolvedString(_) => {
+                    tokens.append(&mut string::resolve(
+                        positions,
+                        content,
+                        token_start,
+                        token_end,
+                    ));
+                }
+                // `1..2` is a range and not the float `1.` - so the integer is
+                // split off and the rest is lexed again.
+                Token::Float(_)
+                    if content[token_start..token_end].ends_with('.')
+                        && content[token_end..end].starts_with('.') =>
+                {
+                    let int = &content[token_start..token_end - 1];
+                    tokens.push(PlacedToken {
+                        token: Token::Int(int.parse().unwrap()),
+                        span: positions.span(token_start, token_end - 1),
+                    });
+                    start = token_end - 1;
+                    continue 'lex;
+                }
+                token => {
+                    let span = positions.span(token_start, token_end);
+                    tokens.push(PlacedToken { token, span });
+                }
             }
-            PlacedToken { token, span }
-        })
-        .collect()
+        }
+        return;
+    }
+}
+
+pub fn string_to_tokens(file_id: usize, content: &str) -> Vec<PlacedToken> {
+    let positions = Positions::new(file_id, content);
+    let mut tokens = Vec::new();
+    lex_range(&positions, content, 0, content.len(), &mut tokens);
+    tokens
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{string_to_tokens, Token};
+    use crate::{string_to_tokens, PlacedToken, Span, Token};
     use logos::Logos;
 
     fn lex(s: &str) -> Vec<Token> {
@@ -212,4 +276,127 @@ mod tests {
         assert_eq!(lex("1// a\n2").len(), 4);
         assert_eq!(lex("1\n// a\n2").len(), 5); // newline is also a token
     }
+
+    fn tokens(s: &str) -> Vec<Token> {
+        string_to_tokens(0, s)
+            .into_iter()
+            .map(|t| t.token)
+            .collect()
+    }
+
+    fn string(s: &str) -> Token {
+        Token::String(String::from(s))
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(tokens(r#""abc""#), vec![string("abc")]);
+        assert_eq!(tokens(r#""""#), vec![string("")]);
+        assert_eq!(tokens(r#""a\nb\t\"\\""#), vec![string("a\nb\t\"\\")]);
+        assert_eq!(tokens(r#""\{\}\u{e4}""#), vec![string("{}ä")]);
+        assert_eq!(tokens("\"a\\\n    b\""), vec![string("ab")]);
+        assert_eq!(tokens("\"a\nb\""), vec![string("a\nb")]);
+    }
+
+    #[test]
+    fn raw_strings() {
+        assert_eq!(tokens(r#"r"a\n{b}""#), vec![string(r"a\n{b}")]);
+        assert_eq!(tokens(r###"r#"a"b"#"###), vec![string(r#"a"b"#)]);
+        assert_eq!(tokens(r###"r##"a"#b"##"###), vec![string(r##"a"#b"##)]);
+    }
+
+    #[test]
+    fn string_errors() {
+        assert!(matches!(
+            tokens(r#""\q""#).as_slice(),
+            [Token::StringError(_)]
+        ));
+        assert!(matches!(
+            tokens(r#""\u{110000}""#).as_slice(),
+            [Token::StringError(_)]
+        ));
+        assert!(matches!(
+            tokens(r#""a}""#).as_slice(),
+            [Token::StringError(_)]
+        ));
+        assert!(matches!(
+            tokens(r#""{ }""#).as_slice(),
+            [Token::StringError(_)]
+        ));
+        assert!(matches!(tokens(r#""abc"#).first(), Some(Token::Error)));
+    }
+
+    #[test]
+    fn string_error_span() {
+        assert_placed_eq!(
+            string_to_tokens(0, "\n  \"ab\\qc\""),
+            (Token::Newline, 1, 1..2),
+            (
+                Token::StringError(String::from("Unknown escape sequence '\\q'")),
+                2,
+                6..8
+            ),
+        );
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(
+            tokens("0..10"),
+            vec![Token::Int(0), Token::DotDot, Token::Int(10)]
+        );
+        assert_eq!(
+            tokens("a..=b"),
+            vec![
+                Token::Identifier(String::from("a")),
+                Token::DotDotEqual,
+                Token::Identifier(String::from("b")),
+            ]
+        );
+        assert_eq!(
+            tokens("1...5"),
+            vec![Token::Int(1), Token::DotDot, Token::Float(0.5)]
+        );
+        assert_eq!(tokens("1. .5"), vec![Token::Float(1.0), Token::Float(0.5)]);
+    }
+
+    #[test]
+    fn interpolation() {
+        assert_eq!(
+            tokens(r#""a{b}c{1 + "{d}"}""#),
+            vec![
+                Token::InterpolationStart(String::from("a")),
+                Token::Identifier(String::from("b")),
+                Token::InterpolationContinue(String::from("c")),
+                Token::Int(1),
+                Token::Plus,
+                Token::InterpolationStart(String::from("")),
+                Token::Identifier(String::from("d")),
+                Token::InterpolationEnd(String::from("")),
+                Token::InterpolationEnd(String::from("")),
+            ]
+        );
+        assert_eq!(tokens(r#""{ {1: "}"} }""#).len(), 7,);
+    }
+
+    #[test]
+    fn multi_line_string_span() {
+        let placed = string_to_tokens(0, "\"a\nbc\" 1");
+        let span = Span {
+            file_id: 0,
+            line_start: 1,
+            line_end: 2,
+            col_start: 1,
+            col_end: 4,
+        };
+        assert_eq!(placed[0], PlacedToken { token: string("a\nbc"), span });
+        assert_eq!(placed[1].span.line_start, 2);
+        assert_placed_eq!(
+            string_to_tokens(0, "\"x{\na}\""),
+            (Token::InterpolationStart(String::from("x")), 1, 1..4),
+            (Token::Newline, 1, 4..5),
+            (Token::Identifier(String::from("a")), 2, 1..2),
+            (Token::InterpolationEnd(String::from("")), 2, 2..4),
+        );
+    }
 }
@@ -0,0 +1,434 @@
+//! Runs programs compiled to Lua in-process, instead of piping them to the
+//! `lua` on the PATH.
+//!
+//! The extern functions of the program are put in `__SYLT_STD`, where the
+//! preamble picks up the ones it can't write in Lua. Errors come back as
+//! values that point into the Sylt files, and `os.exit` stops the program
+//! instead of the process.
+
+use mlua::{Function, Lua, MultiValue, Table, Value as LuaValue};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use sylt_common::error::Error;
+use sylt_common::prog::{Prog, SourceMap};
+use sylt_common::{GcStats, Machine, Op, OpResult, RuntimeContext, RustFunction, Value};
+
+use crate::{compile_and_warn, Args, ExternFunctionList};
+
+/// What Lua calls the program in errors, it's replaced through the source map.
+const CHUNK: &str = "sylt.lua";
+
+/// Holds the program while it's compiled.
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Raised by `os.exit`, so the program stops without taking the process with it.
+#[derive(Debug)]
+struct Exit(i64);
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Exited with status {}", self.0)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// What an extern function sees of the program, which is only its arguments.
+struct Arguments<'a> {
+    values: Vec<Value>,
+    args: &'a [String],
+}
+
+impl Machine for Arguments<'_> {
+    fn stack_from_base(&self, base: usize) -> Cow<[Value]> {
+        Cow::Borrowed(&self.values[base..])
+    }
+
+    fn constants(&self) -> &[Value] {
+        &[]
+    }
+
+    fn eval_op(&mut self, _: Op) -> Result<OpResult, Error> {
+        unreachable!("There are no ops in Lua")
+    }
+
+    /// Lua functions are never turned into values, so there is nothing to call.
+    fn eval_call(&mut self, _: Value, _: &[&Value]) -> Result<Value, Error> {
+        unreachable!("Lua functions can't be given to extern functions")
+    }
+
+    fn args(&self) -> &[String] {
+        self.args
+    }
+
+    fn gc_stats(&self) -> GcStats {
+        GcStats::default()
+    }
+
+    fn collect_garbage(&mut self) {}
+
+    fn hold(&self, _value: &Value) {}
+}
+
+/// Compiles the file in the args to Lua, and runs it.
+pub fn run_file_with_reader<R>(
+    args: &Args,
+    functions: ExternFunctionList,
+    reader: R,
+) -> Result<(), Vec<Error>>
+where
+    R: Fn(&Path) -> Result<String, Error>,
+{
+    let buffer = Buffer::default();
+    let writer: Box<dyn Write> = Box::new(buffer.clone());
+    let source_map = match compile_and_warn(args, functions.clone(), reader, Some(writer))? {
+        Prog::Lua(source_map) => source_map,
+        Prog::Bytecode(_) => unreachable!(),
+    };
+    let source = buffer.0.take();
+    run(&source, &source_map, &functions, &args.args).map_err(|e| vec![e])
+}
+
+/// Runs a program that was compiled to Lua. The extern functions are given
+/// to the program, and the source map is used to point errors at the Sylt
+/// files. Exiting with a status other than 0 is an error.
+pub fn run(
+    source: &[u8],
+    source_map: &SourceMap,
+    functions: &[(String, RustFunction, String)],
+    args: &[String],
+) -> Result<(), Error> {
+    let lua = Lua::new();
+    let result = link(&lua, functions, args)
+        .and_then(|_| lua.load(source).set_name(format!("={}", CHUNK)).exec());
+    let error = match result {
+        Ok(()) => return Ok(()),
+        Err(error) => error,
+    };
+    match exit_status(&error) {
+        Some(0) => Ok(()),
+        Some(status) => Err(Error::LuaError {
+            stderr: Exit(status).to_string(),
+            positions: Vec::new(),
+        }),
+        None => {
+            let (stderr, positions) = source_map.rewrite(CHUNK, &error.to_string());
+            Err(Error::LuaError { stderr, positions })
+        }
+    }
+}
+
+/// The status the program gave `os.exit`, if that is why it stopped.
+fn exit_status(error: &mlua::Error) -> Option<i64> {
+    match error {
+        mlua::Error::CallbackError { cause, .. } => exit_status(cause),
+        error => error.downcast_ref::<Exit>().map(|exit| exit.0),
+    }
+}
+
+fn link(
+    lua: &Lua,
+    functions: &[(String, RustFunction, String)],
+    args: &[String],
+) -> mlua::Result<()> {
+    let std = lua.create_table()?;
+    for (name, function, _) in functions.iter() {
+        let (called, function, args) = (name.clone(), *function, args.to_vec());
+        let function = lua.create_function(move |lua, values: MultiValue| {
+            call(lua, &called, function, values, &args)
+        })?;
+        std.set(name.as_str(), function)?;
+    }
+    lua.globals().set("__SYLT_STD", std)?;
+
+    let exit = lua.create_function(|_, status: LuaValue| {
+        let status = match status {
+            LuaValue::Nil | LuaValue::Boolean(true) => 0,
+            LuaValue::Integer(status) => status,
+            LuaValue::Number(status) => status as i64,
+            _ => 1,
+        };
+        Err::<(), _>(mlua::Error::external(Exit(status)))
+    })?;
+    lua.globals().get::<_, Table>("os")?.set("exit", exit)
+}
+
+fn call<'lua>(
+    lua: &'lua Lua,
+    name: &str,
+    function: RustFunction,
+    values: MultiValue<'lua>,
+    args: &[String],
+) -> mlua::Result<LuaValue<'lua>> {
+    let values = values
+        .into_iter()
+        .map(|value| to_sylt(lua, value))
+        .collect::<mlua::Result<_>>()?;
+    let mut machine = Arguments { values, args };
+    let ctx = RuntimeContext { stack_base: 0, machine: &mut machine };
+    match function(ctx) {
+        Ok(value) => to_lua(lua, value),
+        Err(error) => Err(mlua::Error::runtime(format!("{}: {}", name, error))),
+    }
+}
+
+/// Sylt values are tables in Lua, with a metatable that says what they are.
+fn to_sylt<'lua>(lua: &'lua Lua, value: LuaValue<'lua>) -> mlua::Result<Value> {
+    let table = match value {
+        LuaValue::Nil => return Ok(Value::Nil),
+        LuaValue::Boolean(b) => return Ok(Value::Bool(b)),
+        LuaValue::Integer(i) => return Ok(Value::Int(i)),
+        LuaValue::Number(f) => return Ok(Value::Float(f)),
+        LuaValue::String(s) => return Ok(Value::from(s.to_str()?)),
+        LuaValue::Table(table) => table,
+        value => {
+            return Err(mlua::Error::runtime(format!(
+                "A {} can't be given to an extern function",
+                value.type_name()
+            )))
+        }
+    };
+    let kind = match table.get_metatable() {
+        Some(meta) => meta.raw_get::<_, Option<String>>("_type")?,
+        None => None,
+    };
+    let values = |table: Table<'lua>| {
+        table
+            .sequence_values()
+            .map(|value| to_sylt(lua, value?))
+            .collect::<mlua::Result<Vec<_>>>()
+    };
+    let pairs = |table: Table<'lua>| {
+        table
+            .pairs::<LuaValue, LuaValue>()
+            .map(|pair| {
+                let (key, value) = pair?;
+                Ok((to_sylt(lua, key)?, to_sylt(lua, value)?))
+            })
+            .collect::<mlua::Result<Vec<_>>>()
+    };
+    Ok(match kind.as_deref() {
+        Some("tuple") => Value::Tuple(Rc::new(values(table)?)),
+        Some("list") => Value::List(Rc::new(RefCell::new(values(table)?))),
+        Some("range") => Value::Range(table.raw_get(1)?, table.raw_get(2)?),
+        Some("variant") => Value::Variant(
+            Rc::new(table.raw_get(1)?),
+            Box::new(to_sylt(lua, table.raw_get(2)?)?),
+        ),
+        Some("set") => {
+            let values = pairs(table)?.into_iter().map(|(value, _)| value).collect();
+            Value::Set(Rc::new(RefCell::new(values)))
+        }
+        Some("dict") => Value::Dict(Rc::new(RefCell::new(pairs(table)?.into_iter().collect()))),
+        Some("blob") => Value::Blob(Rc::new(RefCell::new(
+            table
+                .pairs::<String, LuaValue>()
+                .map(|pair| {
+                    let (field, value) = pair?;
+                    Ok((field, to_sylt(lua, value)?))
+                })
+                .collect::<mlua::Result<_>>()?,
+        ))),
+        _ if table == lua.globals().get::<_, Table>("__NIL")? => Value::Nil,
+        _ => {
+            return Err(mlua::Error::runtime(
+                "A table that isn't a Sylt value can't be given to an extern function",
+            ))
+        }
+    })
+}
+
+fn to_lua<'lua>(lua: &'lua Lua, value: Value) -> mlua::Result<LuaValue<'lua>> {
+    let globals = lua.globals();
+    let wrap = |constructor: &str, table: Table<'lua>| -> mlua::Result<LuaValue<'lua>> {
+        globals.get::<_, Function>(constructor)?.call(table)
+    };
+    let table = |pairs: Vec<(Value, Value)>| -> mlua::Result<Table<'lua>> {
+        let table = lua.create_table()?;
+        for (key, value) in pairs {
+            table.raw_set(to_lua(lua, key)?, to_lua(lua, value)?)?;
+        }
+        Ok(table)
+    };
+    let sequence = |values: &[Value]| -> mlua::Result<Table<'lua>> {
+        let values = values
+            .iter()
+            .map(|value| to_lua(lua, value.clone()))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        lua.create_sequence_from(values)
+    };
+    Ok(match value {
+        Value::Nil => globals.get("__NIL")?,
+        Value::Bool(b) => LuaValue::Boolean(b),
+        Value::Int(i) => LuaValue::Integer(i),
+        Value::Float(f) => LuaValue::Number(f),
+        Value::String(s) => LuaValue::String(lua.create_string(s.as_str())?),
+        Value::Tuple(values) => wrap("__TUPLE", sequence(&values)?)?,
+        Value::List(values) => wrap("__LIST", sequence(&values.borrow())?)?,
+        Value::Range(start, stop) => globals.get::<_, Function>("__RANGE")?.call((start, stop))?,
+        Value::Variant(name, value) => {
+            let name = Value::String(Rc::new(name.to_string()));
+            wrap("__VARIANT", sequence(&[name, *value])?)?
+        }
+        Value::Set(values) => {
+            let pairs = values
+                .borrow()
+                .iter()
+                .map(|v| (v.clone(), Value::Bool(true)))
+                .collect();
+            wrap("__SET", table(pairs)?)?
+        }
+        Value::Dict(values) => {
+            let pairs = values
+                .borrow()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            wrap("__DICT", table(pairs)?)?
+        }
+        Value::Blob(fields) => {
+            let pairs = fields
+                .borrow()
+                .iter()
+                .map(|(field, value)| (Value::from(field.as_str()), value.clone()))
+                .collect();
+            wrap("__BLOB", table(pairs)?)?
+        }
+        value => {
+            return Err(mlua::Error::runtime(format!(
+                "{:?} can't be given to Lua",
+                value
+            )))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn run_str(source: &str) -> Result<(), Vec<Error>> {
+        let args = Args {
+            args: vec!["game.sy".to_string()],
+            ..Args::default()
+        };
+        let reader = |_: &Path| Ok(source.to_string());
+        run_file_with_reader(&args, crate::lib_bindings(), reader)
+    }
+
+    #[test]
+    fn extern_functions() {
+        let source = "
+start :: fn do
+    reflect((1., 1.), (0., 1.)) <=> (1., -1.)
+    args()[\"prog\"] <=> \"game.sy\"
+    thread_sleep(0.)
+end
+";
+        assert!(run_str(source).is_ok());
+    }
+
+    #[test]
+    fn lua_5_1_functions() {
+        let source = "
+varargs: fn fn * -> * -> fn [*] -> *: external
+
+start :: fn do
+    varargs(abs)([-2]) <=> 2
+    abs(atan2(0., 1.) - 1.5707963267948966) < 0.0000001 <=> true
+    pow(2., 3.) <=> 8.
+end
+";
+        assert!(run_str(source).is_ok());
+    }
+
+    #[test]
+    fn errors_point_at_the_program() {
+        let source = "
+start :: fn do
+    a := [1, 2]
+    a[5] <=> 1
+end
+";
+        match run_str(source).unwrap_err().as_slice() {
+            [Error::LuaError { stderr, positions }] => {
+                assert!(stderr.contains("game.sy:4:"), "{}", stderr);
+                assert_eq!(positions[0], (PathBuf::from("game.sy"), 4));
+            }
+            errors => panic!("Expected a Lua error, got {:?}", errors),
+        }
+    }
+
+    #[test]
+    fn exit_status() {
+        let map = SourceMap::default();
+        assert!(run(b"os.exit(0) error('unreachable')", &map, &[], &[]).is_ok());
+        assert!(run(b"os.exit(true)", &map, &[], &[]).is_ok());
+        assert!(matches!(
+            run(b"os.exit(3)", &map, &[], &[]),
+            Err(Error::LuaError { stderr, .. }) if stderr == "Exited with status 3"
+        ));
+        assert!(matches!(
+            run(b"error('crash')", &map, &[], &[]),
+            Err(Error::LuaError { stderr, .. }) if stderr.starts_with("runtime error: sylt.lua:1: crash")
+        ));
+    }
+
+    #[test]
+    fn modules() {
+        let source = "
+score := 1
+
+add :: fn a: int, b: int -> int do
+    score += 1
+    ret a + b
+end
+";
+        let dir = std::env::temp_dir().join(format!("sylt_module_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let args = Args {
+            args: vec!["game.sy".to_string()],
+            lua_compile: Some(dir.join("game.lua").to_string_lossy().into_owned()),
+            lua_module: true,
+            ..Args::default()
+        };
+        let reader = |_: &Path| Ok(source.to_string());
+        crate::run_file_with_reader(&args, crate::lib_bindings(), reader).unwrap();
+
+        let lua = Lua::new();
+        lua.globals()
+            .get::<_, Table>("package")
+            .unwrap()
+            .set("path", format!("{}/?.lua", dir.display()))
+            .unwrap();
+        let loaded: (i64, i64, i64, String, bool) = lua
+            .load(
+                "GLOBAL_1 = \"mine\"
+                local game = require \"game\"
+                local sum, score = game.add(1, 2), game.score
+                game.score = 10
+                game.add(0, 0)
+                return sum, score, game.score, GLOBAL_1, push == nil",
+            )
+            .eval()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, (3, 2, 11, "mine".to_string(), true));
+    }
+}
@@ -0,0 +1,562 @@
+//! Embedding Sylt in a Rust program.
+//!
+//! An [Engine] loads a program and defines its globals, without calling
+//! `start`. The host can then read the globals and call the functions of
+//! the program, and the program can call the functions the host registered.
+//! Registered functions are closures, so they can share state with the host.
+
+use std::path::Path;
+use std::rc::Rc;
+use sylt_common::error::{Error, RuntimeError};
+use sylt_common::prog::Prog;
+use sylt_common::{RuntimeContext, RustClosure, Value};
+use sylt_machine::VM;
+
+use crate::{
+    compile_with_externals, lib_bindings, lib_externals, read_file, Args, ExternFunctionList,
+    ExternalList,
+};
+
+/// Rust values that can be given to a Sylt program.
+pub trait IntoSylt {
+    fn into_sylt(self) -> Value;
+}
+
+/// Rust values that can be read from a Sylt program.
+pub trait FromSylt: Sized {
+    /// `None` if the value has another type.
+    fn from_sylt(value: Value) -> Option<Self>;
+}
+
+/// The arguments to a function, a tuple of [IntoSylt] values.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+impl IntoSylt for Value {
+    fn into_sylt(self) -> Value {
+        self
+    }
+}
+
+impl FromSylt for Value {
+    fn from_sylt(value: Value) -> Option<Self> {
+        Some(value)
+    }
+}
+
+impl IntoSylt for () {
+    fn into_sylt(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl FromSylt for () {
+    fn from_sylt(value: Value) -> Option<Self> {
+        value.is_nil().then(|| ())
+    }
+}
+
+macro_rules! convert {
+    ($rust:ty, $variant:ident) => {
+        impl IntoSylt for $rust {
+            fn into_sylt(self) -> Value {
+                Value::$variant(self)
+            }
+        }
+
+        impl FromSylt for $rust {
+            fn from_sylt(value: Value) -> Option<Self> {
+                match value {
+                    Value::$variant(value) => Some(value),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+convert!(i64, Int);
+convert!(f64, Float);
+convert!(bool, Bool);
+
+impl IntoSylt for &str {
+    fn into_sylt(self) -> Value {
+        Value::from(self)
+    }
+}
+
+impl IntoSylt for String {
+    fn into_sylt(self) -> Value {
+        Value::String(Rc::new(self))
+    }
+}
+
+impl FromSylt for String {
+    fn from_sylt(value: Value) -> Option<Self> {
+        match value {
+            Value::String(s) => Some(String::clone(&s)),
+            _ => None,
+        }
+    }
+}
+
+impl<T: IntoSylt> IntoSylt for Vec<T> {
+    fn into_sylt(self) -> Value {
+        let values = self.into_iter().map(IntoSylt::into_sylt).collect();
+        Value::List(Rc::new(std::cell::RefCell::new(values)))
+    }
+}
+
+impl<T: FromSylt> FromSylt for Vec<T> {
+    fn from_sylt(value: Value) -> Option<Self> {
+        match value {
+            Value::List(list) => list.borrow().iter().cloned().map(T::from_sylt).collect(),
+            _ => None,
+        }
+    }
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Vec<Value> {
+        self
+    }
+}
+
+macro_rules! args {
+    ($( $arg:ident ),*) => {
+        impl<$( $arg: IntoSylt ),*> IntoArgs for ($( $arg, )*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Vec<Value> {
+                let ($( $arg, )*) = self;
+                vec![$( $arg.into_sylt() ),*]
+            }
+        }
+    };
+}
+
+args!();
+args!(A);
+args!(A, B);
+args!(A, B, C);
+args!(A, B, C, D);
+
+/// Calls to functions that were registered with [Engine::register]
+/// end up here if the function was never linked.
+fn unlinked(_: RuntimeContext) -> Result<Value, RuntimeError> {
+    Err(RuntimeError::ExternError(
+        "?".to_string(),
+        "The function was registered after the program was loaded".to_string(),
+    ))
+}
+
+/// Loads a program and runs its functions, the globals keep their
+/// values between calls.
+pub struct Engine {
+    functions: ExternFunctionList,
+    externals: ExternalList,
+    /// The functions given to [Engine::register], and their slot.
+    closures: Vec<(usize, RustClosure)>,
+    vm: VM,
+    loaded: bool,
+    /// The warnings from compiling the program that was loaded last.
+    warnings: Vec<Error>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    /// An engine with the standard library, and the external values it gives.
+    pub fn new() -> Self {
+        Self {
+            functions: lib_bindings(),
+            externals: lib_externals(),
+            closures: Vec::new(),
+            vm: VM::new(),
+            loaded: false,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Adds extern functions, like the ones from [sylt_macro::extern_function].
+    /// Has to be done before the program is loaded.
+    pub fn link(&mut self, mut functions: ExternFunctionList) {
+        self.functions.append(&mut functions);
+    }
+
+    /// Makes `function` callable from the program, typed by `signature`,
+    /// for example `"fn int, int -> int"`. A function with the same name
+    /// is replaced. Has to be done before the program is loaded.
+    pub fn register<F>(&mut self, name: &str, signature: &str, function: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        let slot = match self
+            .functions
+            .iter()
+            .position(|(other, _, _)| other == name)
+        {
+            Some(slot) => {
+                self.functions[slot] = (name.to_string(), unlinked, signature.to_string());
+                slot
+            }
+            None => {
+                self.functions
+                    .push((name.to_string(), unlinked, signature.to_string()));
+                self.functions.len() - 1
+            }
+        };
+        let closure: RustClosure = Rc::new(move |ctx: RuntimeContext| {
+            let args = ctx.machine.stack_from_base(ctx.stack_base);
+            // The function can keep the arguments, like a callback.
+            args.iter().for_each(|arg| ctx.machine.hold(arg));
+            function(&args)
+        });
+        self.closures.retain(|(other, _)| *other != slot);
+        self.closures.push((slot, closure));
+    }
+
+    /// Gives the `external` definitions called `name` their value, which has to
+    /// have the type in `signature`. Extern functions and functions given to
+    /// [Engine::register] can be put in the value, `value` is called with a
+    /// function that looks them up by name. Has to be done before the program
+    /// is loaded.
+    pub fn external<F>(&mut self, name: &str, signature: &str, value: F)
+    where
+        F: Fn(&dyn Fn(&str) -> Option<Value>) -> Value + 'static,
+    {
+        self.externals.retain(|(other, _, _)| other != name);
+        self.externals
+            .push((name.to_string(), Rc::new(value), signature.to_string()));
+    }
+
+    /// Compiles the file, and defines the globals in it.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Vec<Error>> {
+        self.load(path.as_ref(), read_file)
+    }
+
+    /// Compiles the source as if it was read from `path`. Other
+    /// files that are used are read relative to `path`.
+    pub fn load_str<P: AsRef<Path>>(&mut self, path: P, source: &str) -> Result<(), Vec<Error>> {
+        let main = path.as_ref().to_path_buf();
+        self.load(&main, |file: &Path| {
+            if file == main {
+                Ok(source.to_string())
+            } else {
+                read_file(file)
+            }
+        })
+    }
+
+    fn load<R>(&mut self, path: &Path, reader: R) -> Result<(), Vec<Error>>
+    where
+        R: Fn(&Path) -> Result<String, Error>,
+    {
+        let args = Args {
+            args: vec![path.to_string_lossy().into_owned()],
+            ..Args::default()
+        };
+        let functions = self.functions.clone();
+        let externals = self.externals.clone();
+        let (prog, warnings) = compile_with_externals(&args, functions, externals, reader, None)?;
+        let prog = match prog {
+            Prog::Bytecode(prog) => prog,
+            Prog::Lua(_) => unreachable!(),
+        };
+
+        self.warnings = warnings;
+        self.loaded = false;
+        self.vm = VM::new();
+        self.vm.init(&prog, &args.args);
+        for (slot, closure) in self.closures.iter() {
+            self.vm.link_closure(*slot, Rc::clone(closure));
+        }
+        self.vm.define_globals().map_err(|e| vec![e])?;
+        self.loaded = true;
+        Ok(())
+    }
+
+    /// The warnings from compiling the program that was loaded last,
+    /// nothing is printed.
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
+    /// Reads a global from the main file of the program.
+    pub fn global<T: FromSylt>(&self, name: &str) -> Result<T, Error> {
+        let value = self.lookup(name)?;
+        convert(&format!("'{}'", name), value)
+    }
+
+    /// Calls the function called `name` in the main file of the program.
+    pub fn call<R: FromSylt, A: IntoArgs>(&mut self, name: &str, args: A) -> Result<R, Error> {
+        let function = self.lookup(name)?;
+        let value = self.vm.call(&function, &args.into_args())?;
+        convert(&format!("'{}'", name), value)
+    }
+
+    /// Calls a function the program gave to the host, like a callback.
+    pub fn call_value<R: FromSylt, A: IntoArgs>(
+        &mut self,
+        function: &Value,
+        args: A,
+    ) -> Result<R, Error> {
+        if !self.loaded {
+            return Err(Error::EngineError("No program is loaded".to_string()));
+        }
+        let value = self.vm.call(function, &args.into_args())?;
+        convert("the function", value)
+    }
+
+    /// The VM the program runs in.
+    pub fn vm(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+
+    fn lookup(&self, name: &str) -> Result<Value, Error> {
+        if !self.loaded {
+            return Err(Error::EngineError("No program is loaded".to_string()));
+        }
+        self.vm
+            .global(name)
+            .ok_or_else(|| Error::EngineError(format!("There is no global called '{}'", name)))
+    }
+}
+
+/// `what` is the thing the value came from, used in the error.
+fn convert<T: FromSylt>(what: &str, value: Value) -> Result<T, Error> {
+    let message = format!(
+        "Expected {} to give a {}, but got {:?}",
+        what,
+        std::any::type_name::<T>(),
+        value
+    );
+    T::from_sylt(value).ok_or(Error::EngineError(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use sylt_common::error::Warning;
+    use sylt_common::Machine;
+
+    const GAME: &str = "
+score := 0
+name :: \"game\"
+
+add :: fn a: int, b: int -> int do
+    ret a + b
+end
+
+hit :: fn do
+    score += points()
+end
+
+words :: fn -> [str] do
+    ret [name, \"over\"]
+end
+
+crash :: fn do
+    <!>
+end
+
+start :: fn do
+    score = -1
+end
+";
+
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.register("points", "fn -> int", |_| Ok(Value::Int(10)));
+        engine.load_str("game.sy", GAME).unwrap();
+        engine
+    }
+
+    #[test]
+    fn call_with_arguments() {
+        let mut engine = engine();
+        assert_eq!(engine.call::<i64, _>("add", (1, 2)).unwrap(), 3);
+        assert_eq!(
+            engine.call::<Vec<String>, _>("words", ()).unwrap(),
+            ["game", "over"]
+        );
+    }
+
+    #[test]
+    fn globals_keep_their_values() {
+        let mut engine = engine();
+        assert_eq!(engine.global::<String>("name").unwrap(), "game");
+        assert_eq!(engine.global::<i64>("score").unwrap(), 0);
+        engine.call::<(), _>("hit", ()).unwrap();
+        engine.call::<(), _>("hit", ()).unwrap();
+        assert_eq!(engine.global::<i64>("score").unwrap(), 20);
+        engine.call::<(), _>("start", ()).unwrap();
+        assert_eq!(engine.global::<i64>("score").unwrap(), -1);
+    }
+
+    #[test]
+    fn closures_share_state() {
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = Engine::new();
+        let log = Rc::clone(&hits);
+        engine.register("points", "fn -> int", move |_| {
+            log.borrow_mut().push("hit");
+            Ok(Value::Int(log.borrow().len() as i64))
+        });
+        engine.load_str("game.sy", GAME).unwrap();
+        engine.call::<(), _>("hit", ()).unwrap();
+        engine.call::<(), _>("hit", ()).unwrap();
+        assert_eq!(engine.global::<i64>("score").unwrap(), 3);
+        assert_eq!(hits.borrow().len(), 2);
+    }
+
+    #[test]
+    fn errors() {
+        let mut engine = engine();
+        assert!(matches!(
+            engine.call::<(), _>("missing", ()),
+            Err(Error::EngineError(_))
+        ));
+        assert!(matches!(
+            engine.call::<bool, _>("add", (1, 2)),
+            Err(Error::EngineError(_))
+        ));
+        assert!(matches!(
+            engine.call::<(), _>("crash", ()),
+            Err(Error::RuntimeError { kind: RuntimeError::Unreachable, .. })
+        ));
+        // The engine can be used after a call fails.
+        assert_eq!(engine.call::<i64, _>("add", (2, 2)).unwrap(), 4);
+    }
+
+    const HELD: &str = "
+counter :: fn -> fn -> int do
+    count := 0
+    next :: fn -> int do
+        count += 1
+        ret count
+    end
+    ret next
+end
+
+numbers :: fn -> [int] do
+    ret [1, 2, 3]
+end
+
+size :: fn xs: [int] -> int do
+    ret len(xs)
+end
+
+start :: fn do
+    keep(counter())
+end
+";
+
+    #[test]
+    fn held_values_are_not_collected() {
+        let kept = Rc::new(RefCell::new(None));
+        let mut engine = Engine::new();
+        let keep = Rc::clone(&kept);
+        engine.register("keep", "fn fn -> int -> void", move |args| {
+            *keep.borrow_mut() = Some(args[0].clone());
+            Ok(Value::Nil)
+        });
+        engine.load_str("game.sy", HELD).unwrap();
+        let counter: Value = engine.call("counter", ()).unwrap();
+        let numbers: Value = engine.call("numbers", ()).unwrap();
+        engine.call::<(), _>("start", ()).unwrap();
+        let callback = kept.borrow().clone().unwrap();
+        for i in 1..=100 {
+            engine.vm().collect_garbage();
+            assert_eq!(engine.call_value::<i64, _>(&counter, ()).unwrap(), i);
+            assert_eq!(engine.call_value::<i64, _>(&callback, ()).unwrap(), i);
+            assert_eq!(
+                engine.call::<i64, _>("size", (numbers.clone(),)).unwrap(),
+                3
+            );
+        }
+    }
+
+    const EXTERNALS: &str = "
+Player :: blob {
+    name: str,
+    hurt: fn int -> int,
+}
+player: Player : external
+greeting: str : external
+
+start :: fn do
+    player.hurt(2) <=> 8
+end
+";
+
+    fn player(engine: &mut Engine) {
+        engine.register("hurt", "fn int -> int", |args| match args {
+            [Value::Int(damage)] => Ok(Value::Int(10 - damage)),
+            _ => Ok(Value::Nil),
+        });
+        engine.external("player", "Player", |function| {
+            let mut fields = std::collections::HashMap::new();
+            fields.insert("_name".to_string(), Value::from("Player"));
+            fields.insert("name".to_string(), Value::from("player 1"));
+            fields.insert("hurt".to_string(), function("hurt").unwrap());
+            Value::Blob(Rc::new(RefCell::new(fields)))
+        });
+    }
+
+    #[test]
+    fn external_values() {
+        let mut engine = Engine::new();
+        player(&mut engine);
+        engine.external("greeting", "str", |_| Value::from("hi"));
+        engine.load_str("game.sy", EXTERNALS).unwrap();
+        assert_eq!(engine.global::<String>("greeting").unwrap(), "hi");
+        engine.call::<(), _>("start", ()).unwrap();
+    }
+
+    #[test]
+    fn missing_external() {
+        let mut engine = Engine::new();
+        player(&mut engine);
+        let errors = engine.load_str("game.sy", EXTERNALS).err().unwrap();
+        assert!(matches!(
+            errors.as_slice(),
+            [Error::MissingExternal { name, .. }] if name == "greeting"
+        ));
+    }
+
+    #[test]
+    fn external_with_wrong_type() {
+        let mut engine = Engine::new();
+        player(&mut engine);
+        engine.external("greeting", "int", |_| Value::Int(1));
+        let errors = engine.load_str("game.sy", EXTERNALS).err().unwrap();
+        assert!(matches!(errors.as_slice(), [Error::TypeError { .. }]));
+    }
+
+    #[test]
+    fn warnings_are_returned() {
+        let mut engine = Engine::new();
+        engine
+            .load_str("game.sy", "start :: fn do\n    unused := 1\nend\n")
+            .unwrap();
+        assert!(matches!(
+            engine.warnings(),
+            [Error::Warning { kind: Warning::UnusedVariable(name), .. }] if name == "unused"
+        ));
+    }
+
+    #[test]
+    fn nothing_loaded() {
+        let mut engine = Engine::new();
+        assert!(matches!(
+            engine.call::<(), _>("start", ()),
+            Err(Error::EngineError(_))
+        ));
+    }
+}
@@ -4,8 +4,9 @@ use sylt_common::{Error, Type as RuntimeType};
 use sylt_parser::expression::ComparisonKind;
 use sylt_parser::statement::NameIdentifier;
 use sylt_parser::{
-    Assignable, AssignableKind, Expression, ExpressionKind, Identifier, Module, Op, Statement,
-    StatementKind, Type, TypeAssignable, TypeAssignableKind, TypeConstraint, TypeKind, VarKind,
+    Assignable, AssignableKind, Expression, ExpressionKind, Identifier, Module, Op, Pattern,
+    PatternKind, Statement, StatementKind, Type, TypeAssignable, TypeAssignableKind,
+    TypeConstraint, TypeKind, VarKind,
 };
 
 use crate::Args;
@@ -36,6 +37,66 @@ fn write_identifier<W: Write>(dest: &mut W, identifier: Identifier) -> fmt::Resu
     write!(dest, "{}", identifier.name)
 }
 
+fn write_string<W: Write>(dest: &mut W, string: &str) -> fmt::Result {
+    write!(dest, "\"")?;
+    for c in string.chars() {
+        match c {
+            '\\' => write!(dest, "\\\\")?,
+            '"' => write!(dest, "\\\"")?,
+            '{' => write!(dest, "\\{{")?,
+            '}' => write!(dest, "\\}}")?,
+            '\n' => write!(dest, "\\n")?,
+            '\r' => write!(dest, "\\r")?,
+            '\t' => write!(dest, "\\t")?,
+            '\0' => write!(dest, "\\0")?,
+            c if c.is_control() => write!(dest, "\\u{{{:x}}}", c as u32)?,
+            c => write!(dest, "{}", c)?,
+        }
+    }
+    write!(dest, "\"")
+}
+
+fn write_pattern<W: Write>(dest: &mut W, pattern: Pattern) -> fmt::Result {
+    match pattern.kind {
+        PatternKind::Wildcard => write!(dest, "_"),
+        PatternKind::Bind(ident) => write_identifier(dest, ident),
+        PatternKind::Int(i) => write!(dest, "{}", i),
+        PatternKind::Str(s) => write_string(dest, &s),
+        PatternKind::Bool(b) => write!(dest, "{}", b),
+        PatternKind::Tuple(patterns) => {
+            let single = patterns.len() == 1;
+            write!(dest, "(")?;
+            write_comma_separated!(
+                dest,
+                (),
+                |dest, _, pattern| write_pattern(dest, pattern),
+                patterns
+            );
+            if single {
+                write!(dest, ",")?;
+            }
+            write!(dest, ")")
+        }
+        PatternKind::Variant(variant, None) => write_identifier(dest, variant),
+        PatternKind::Variant(variant, Some(value)) => {
+            write_identifier(dest, variant)?;
+            if matches!(value.kind, PatternKind::Variant(..) | PatternKind::As(..)) {
+                write!(dest, " (")?;
+                write_pattern(dest, *value)?;
+                write!(dest, ")")
+            } else {
+                write!(dest, " ")?;
+                write_pattern(dest, *value)
+            }
+        }
+        PatternKind::As(pattern, ident) => {
+            write_pattern(dest, *pattern)?;
+            write!(dest, " as ")?;
+            write_identifier(dest, ident)
+        }
+    }
+}
+
 fn write_parameters<W: Write>(
     dest: &mut W,
     indent: u32,
@@ -265,6 +326,27 @@ fn write_expression<W: Write>(dest: &mut W, indent: u32, expression: Expression)
         ExpressionKind::Div(lhs, rhs) => {
             expr_binary_op!(dest, indent, *lhs, " / ", *rhs);
         }
+        ExpressionKind::Rem(lhs, rhs) => {
+            expr_binary_op!(dest, indent, *lhs, " % ", *rhs);
+        }
+        ExpressionKind::FloorDiv(lhs, rhs) => {
+            expr_binary_op!(dest, indent, *lhs, " div ", *rhs);
+        }
+        ExpressionKind::BitAnd(lhs, rhs) => {
+            expr_binary_op!(dest, indent, *lhs, " & ", *rhs);
+        }
+        ExpressionKind::BitOr(lhs, rhs) => {
+            expr_binary_op!(dest, indent, *lhs, " | ", *rhs);
+        }
+        ExpressionKind::BitXor(lhs, rhs) => {
+            expr_binary_op!(dest, indent, *lhs, " ^ ", *rhs);
+        }
+        ExpressionKind::ShiftLeft(lhs, rhs) => {
+            expr_binary_op!(dest, indent, *lhs, " << ", *rhs);
+        }
+        ExpressionKind::ShiftRight(lhs, rhs) => {
+            expr_binary_op!(dest, indent, *lhs, " >> ", *rhs);
+        }
         ExpressionKind::Neg(expr) => {
             write!(dest, "-")?;
             write_expression(dest, indent, *expr)?;
@@ -305,6 +387,24 @@ fn write_expression<W: Write>(dest: &mut W, indent: u32, expression: Expression)
             write!(dest, "not ")?;
             write_expression(dest, indent, *expr)?;
         }
+        ExpressionKind::Yield(expr) => {
+            write!(dest, "yield ")?;
+            write_expression(dest, indent, *expr)?;
+        }
+        ExpressionKind::Coroutine(expr) => {
+            write!(dest, "coroutine ")?;
+            write_expression(dest, indent, *expr)?;
+        }
+        ExpressionKind::ToStr(expr) => {
+            write!(dest, "\"{{")?;
+            write_expression(dest, indent, *expr)?;
+            write!(dest, "}}\"")?;
+        }
+        ExpressionKind::Range { start, end, inclusive } => {
+            write_expression(dest, indent, *start)?;
+            write!(dest, "{}", if inclusive { "..=" } else { ".." })?;
+            write_expression(dest, indent, *end)?;
+        }
         ExpressionKind::Parenthesis(expr) => {
             write!(dest, "(")?;
             write_expression(dest, indent, *expr)?;
@@ -390,7 +490,7 @@ fn write_expression<W: Write>(dest: &mut W, indent: u32, expression: Expression)
         }
         ExpressionKind::Float(f) => write!(dest, "{:?}", f)?,
         ExpressionKind::Int(i) => write!(dest, "{}", i)?,
-        ExpressionKind::Str(s) => write!(dest, "\"{}\"", s)?,
+        ExpressionKind::Str(s) => write_string(dest, &s)?,
         ExpressionKind::Bool(b) => write!(dest, "{}", b)?,
         ExpressionKind::Nil => write!(dest, "nil")?,
     }
@@ -497,17 +597,19 @@ fn write_statement<W: Write>(dest: &mut W, indent: u32, statement: Statement) ->
             write!(dest, " do\n")?;
             for branch in branches {
                 write_indents(dest, indent + 1)?;
-                write_identifier(dest, branch.pattern)?;
-                if let Some(var) = branch.variable {
-                    write!(dest, " ")?;
-                    write_identifier(dest, var)?;
+                write_pattern(dest, branch.pattern)?;
+                if let Some(guard) = branch.guard {
+                    write!(dest, " if ")?;
+                    write_expression(dest, indent + 1, guard)?;
                 }
                 write!(dest, "\n")?;
                 write_statement(dest, indent + 1, branch.body)?;
             }
-            write_indents(dest, indent + 1)?;
-            write!(dest, "else\n")?;
-            write_statement(dest, indent + 1, *fall_through)?;
+            if let Some(fall_through) = fall_through {
+                write_indents(dest, indent + 1)?;
+                write!(dest, "else\n")?;
+                write_statement(dest, indent + 1, *fall_through)?;
+            }
             write_indents(dest, indent)?;
             write!(dest, "end\n")?;
         }
@@ -543,6 +645,15 @@ fn write_statement<W: Write>(dest: &mut W, indent: u32, statement: Statement) ->
             write!(dest, " ")?;
             write_statement(dest, indent, *body)?;
         }
+        StatementKind::LoopIn { pattern, iterable, body } => {
+            write_indents(dest, indent)?;
+            write!(dest, "loop ")?;
+            write_pattern(dest, pattern)?;
+            write!(dest, " in ")?;
+            write_expression(dest, indent, iterable)?;
+            write!(dest, " ")?;
+            write_statement(dest, indent, *body)?;
+        }
         StatementKind::Ret { value } => {
             write_indents(dest, indent)?;
             write!(dest, "ret ")?;
@@ -643,13 +754,13 @@ pub fn format(args: &Args) -> Result<String, Vec<Error>> {
 
 #[cfg(test)]
 macro_rules! test_formatter_on_file {
-    ($fn:ident, $path:literal, $print:expr, $errs:pat, $_:expr) => {
+    ($fn:ident, $path:literal, $print:expr, $deny_warnings:expr, $errs:pat, $_:expr) => {
         #[test]
         fn $fn() {
             use std::path::{Path, PathBuf};
             #[allow(unused_imports)]
             use sylt_common::{
-                error::{Error, RuntimeError, TypeError},
+                error::{Error, RuntimeError, TypeError, Warning},
                 Type,
             };
             #[allow(unused_imports)]
@@ -660,6 +771,7 @@ macro_rules! test_formatter_on_file {
             // Run the file before the formatter.
             let mut args = $crate::Args::default();
             args.args = vec![path.clone()];
+            args.deny_warnings = $deny_warnings;
             let before = $crate::run_file(&args, ::sylt_std::sylt::_sylt_link());
             // If the test fails here, we already have / will have prettified output.
             assert!(
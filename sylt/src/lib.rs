@@ -1,18 +1,31 @@
 /// Re-export of derived functions for [Args].
 pub use gumdrop::Options;
 
+use std::cell::RefCell;
+use std::ffi::OsStr;
 use std::fmt::Debug;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use sylt_common::error::Error;
 use sylt_common::prog::{BytecodeProg, Prog};
-use sylt_common::RustFunction;
+use sylt_common::{ExternalValue, OpResult, RustFunction};
+use sylt_compiler::CompileOptions;
 
+#[cfg(feature = "embedded-lua")]
+pub mod embedded_lua;
+pub mod engine;
 pub mod formatter;
+#[cfg(any(feature = "love", test))]
+pub mod love;
+
+pub use engine::Engine;
 
 type ExternFunctionList = Vec<(String, RustFunction, String)>;
+type ExternalList = Vec<(String, ExternalValue, String)>;
 
-/// Generates the linking for the standard library, and lingon if it's active.
+/// Generates the linking for the standard library, and the modules that are active.
 pub fn lib_bindings() -> ExternFunctionList {
     let mut lib = Vec::new();
 
@@ -24,6 +37,20 @@ pub fn lib_bindings() -> ExternFunctionList {
     #[cfg(feature = "network")]
     lib.append(&mut sylt_std::network::_sylt_link());
 
+    #[cfg(any(feature = "love", test))]
+    lib.append(&mut sylt_std::love::_sylt_link());
+
+    lib
+}
+
+/// The values the standard library gives to `external` definitions.
+pub fn lib_externals() -> ExternalList {
+    #[allow(unused_mut)]
+    let mut lib = Vec::new();
+
+    #[cfg(any(feature = "love", test))]
+    lib.append(&mut sylt_std::love::externals());
+
     lib
 }
 
@@ -31,21 +58,90 @@ pub fn read_file(path: &Path) -> Result<String, Error> {
     std::fs::read_to_string(path).map_err(|_| Error::FileNotFound(path.to_path_buf()))
 }
 
+/// Reads a bytecode file written with `--emit-bytecode`, and links it
+/// against the supplied functions and external values.
+pub fn load_bytecode(
+    path: &Path,
+    functions: &ExternFunctionList,
+    externals: &ExternalList,
+) -> Result<BytecodeProg, Vec<Error>> {
+    let bytes = std::fs::read(path).map_err(|_| vec![Error::FileNotFound(path.to_path_buf())])?;
+    let mut prog = BytecodeProg::from_bytes(path, &bytes, functions).map_err(|e| vec![e])?;
+    prog.link_externals(functions, externals)?;
+    Ok(prog)
+}
+
+/// Bytecode files are run as they are, instead of being compiled.
+fn is_bytecode_file(args: &Args) -> bool {
+    args.args
+        .first()
+        .map(|file| Path::new(file).extension() == Some(OsStr::new("syb")))
+        .unwrap_or(false)
+}
+
+/// Compiles the program, and returns it with the warnings.
 pub fn compile_with_reader_to_writer<R>(
     args: &Args,
     functions: ExternFunctionList,
     reader: R,
     write_file: Option<Box<dyn Write>>,
+) -> Result<(Prog, Vec<Error>), Vec<Error>>
+where
+    R: Fn(&Path) -> Result<String, Error>,
+{
+    compile_with_externals(args, functions, lib_externals(), reader, write_file)
+}
+
+/// Compiles like [compile_with_reader_to_writer], and writes the
+/// warnings to stderr.
+pub(crate) fn compile_and_warn<R>(
+    args: &Args,
+    functions: ExternFunctionList,
+    reader: R,
+    write_file: Option<Box<dyn Write>>,
 ) -> Result<Prog, Vec<Error>>
+where
+    R: Fn(&Path) -> Result<String, Error>,
+{
+    let (prog, warnings) = compile_with_reader_to_writer(args, functions, reader, write_file)?;
+    for warning in warnings.iter() {
+        eprintln!("{}", warning);
+    }
+    Ok(prog)
+}
+
+/// Compiles like [compile_with_reader_to_writer], where the
+/// `external` definitions are given by `externals`.
+///
+/// Nothing is typechecked or compiled if there are syntax errors,
+/// so only the syntax errors are returned in that case.
+pub fn compile_with_externals<R>(
+    args: &Args,
+    functions: ExternFunctionList,
+    externals: ExternalList,
+    reader: R,
+    write_file: Option<Box<dyn Write>>,
+) -> Result<(Prog, Vec<Error>), Vec<Error>>
 where
     R: Fn(&Path) -> Result<String, Error>,
 {
     let file = PathBuf::from(args.args.first().expect("No file to run"));
-    let tree = sylt_parser::tree(&file, reader)?;
+    let (tree, errors) = sylt_parser::partial_tree(&file, reader);
+    // The tree is dumped even if it's broken, since it helps finding out why.
     if args.dump_tree {
         println!("{}", tree);
     }
-    sylt_compiler::compile(!args.skip_typecheck, write_file, tree, &functions)
+    if errors.iter().any(|err| !err.is_warning()) {
+        return Err(errors);
+    }
+    let options = CompileOptions {
+        typecheck: !args.skip_typecheck,
+        deny_warnings: args.deny_warnings,
+        optimize: args.optimize,
+        lua_file: write_file,
+        lua_module: args.lua_module,
+    };
+    sylt_compiler::compile(options, errors, tree, &functions, &externals)
 }
 
 // TODO(ed): This name isn't true anymore - since it can compile
@@ -58,6 +154,10 @@ where
     R: Fn(&Path) -> Result<String, Error>,
 {
     match (&args.lua_run, &args.lua_compile) {
+        #[cfg(feature = "embedded-lua")]
+        (true, _) => embedded_lua::run_file_with_reader(args, functions, reader)?,
+
+        #[cfg(not(feature = "embedded-lua"))]
         (true, _) => {
             use std::process::{Command, Stdio};
             let mut child = Command::new("lua")
@@ -66,15 +166,15 @@ where
                 .spawn()
                 .expect("Failed to start lua - make sure it's installed correctly");
             let stdin = child.stdin.take().unwrap();
-            match compile_with_reader_to_writer(args, functions, reader, Some(Box::new(stdin)))? {
-                Prog::Lua => {
+            match compile_and_warn(args, functions, reader, Some(Box::new(stdin)))? {
+                Prog::Lua(source_map) => {
                     let output = child.wait_with_output().unwrap();
                     // NOTE(ed): Status is always 0 when piping to STDIN, atleast on my version of lua,
                     // so we check stderr - which is a bad idea.
                     if !output.stderr.is_empty() {
-                        return Err(vec![Error::LuaError(
-                            String::from_utf8(output.stderr).unwrap(),
-                        )]);
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        let (stderr, positions) = source_map.rewrite("stdin", &stderr);
+                        return Err(vec![Error::LuaError { stderr, positions }]);
                     }
                 }
                 Prog::Bytecode(_) => unreachable!(),
@@ -84,7 +184,7 @@ where
         (false, Some(s)) if s == "%" => {
             use std::io;
             // NOTE(ed): Lack of running
-            compile_with_reader_to_writer(args, functions, reader, Some(Box::new(io::stdout())))?;
+            compile_and_warn(args, functions, reader, Some(Box::new(io::stdout())))?;
         }
 
         (false, Some(s)) => {
@@ -93,13 +193,36 @@ where
                 File::create(PathBuf::from(s)).expect(&format!("Failed to create file: {}", s));
             let writer: Option<Box<dyn Write>> = Some(Box::new(file));
             // NOTE(ed): Lack of running
-            compile_with_reader_to_writer(args, functions, reader, writer)?;
+            let prog = compile_and_warn(args, functions, reader, writer)?;
+            if let (true, Prog::Lua(source_map)) = (args.source_map, prog) {
+                std::fs::write(format!("{}.map", s), source_map.to_string())
+                    .map_err(|e| vec![Error::IOError(Rc::new(e))])?;
+            }
+            if args.lua_module {
+                let runtime = PathBuf::from(s)
+                    .with_file_name(sylt_compiler::LUA_RUNTIME_MODULE)
+                    .with_extension("lua");
+                std::fs::write(runtime, sylt_compiler::LUA_RUNTIME_AS_MODULE)
+                    .map_err(|e| vec![Error::IOError(Rc::new(e))])?;
+            }
+        }
+
+        (_, _) if is_bytecode_file(args) => {
+            let file = PathBuf::from(&args.args[0]);
+            let prog = load_bytecode(&file, &functions, &lib_externals())?;
+            run(&prog, args)?;
         }
 
+        (_, _) if args.watch => watch(args, functions, reader)?,
+
         (_, _) => {
-            match compile_with_reader_to_writer(args, functions, reader, None)? {
-                Prog::Bytecode(prog) => run(&prog, &args)?,
-                Prog::Lua => unreachable!(),
+            match compile_and_warn(args, functions, reader, None)? {
+                Prog::Bytecode(prog) => match &args.emit_bytecode {
+                    Some(s) => std::fs::write(PathBuf::from(s), prog.to_bytes())
+                        .map_err(|e| vec![Error::IOError(Rc::new(e))])?,
+                    None => run(&prog, &args)?,
+                },
+                Prog::Lua(_) => unreachable!(),
             };
         }
     };
@@ -117,13 +240,96 @@ pub fn run(prog: &BytecodeProg, args: &Args) -> Result<(), Vec<Error>> {
     vm.print_bytecode = args.verbosity >= 1;
     vm.print_exec = args.verbosity >= 2;
     vm.init(&prog, &args.args);
-    if let Err(e) = vm.run() {
+    if args.profile {
+        vm.start_profiling();
+    }
+    let result = if args.debug {
+        let stdin = std::io::stdin();
+        vm.debug(&mut stdin.lock(), &mut std::io::stdout())
+    } else {
+        vm.run()
+    };
+    if let Some(profiler) = vm.stop_profiling() {
+        let folded = PathBuf::from(&args.args[0]).with_extension("folded");
+        let written = profiler
+            .write_report(&mut std::io::stderr())
+            .and_then(|_| profiler.write_folded(&mut std::fs::File::create(&folded)?));
+        match written {
+            Ok(()) => eprintln!("\nwrote the stacks to {}", folded.display()),
+            Err(e) => eprintln!("failed to write the profile: {}", e),
+        }
+    }
+    if let Err(e) = result {
         Err(vec![e])
     } else {
         Ok(())
     }
 }
 
+/// How many ops are run between looking for changed files.
+const WATCH_OPS: usize = 10000;
+/// How often to look for changed files.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runs the program, and reloads it when one of its files changes. Errors
+/// in the new code are written to stderr, and the old code keeps running.
+fn watch<R>(args: &Args, functions: ExternFunctionList, reader: R) -> Result<(), Vec<Error>>
+where
+    R: Fn(&Path) -> Result<String, Error>,
+{
+    let files = RefCell::new(Vec::new());
+    let compile = || {
+        files.borrow_mut().clear();
+        let reader = |path: &Path| {
+            files.borrow_mut().push(path.to_path_buf());
+            reader(path)
+        };
+        match compile_and_warn(args, functions.clone(), reader, None)? {
+            Prog::Bytecode(prog) => Ok(prog),
+            Prog::Lua(_) => unreachable!(),
+        }
+    };
+    let modified = || -> Vec<_> {
+        files
+            .borrow()
+            .iter()
+            .map(|file| {
+                std::fs::metadata(file)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+            })
+            .collect()
+    };
+
+    let prog = compile()?;
+    let mut seen = modified();
+    let mut vm = sylt_machine::VM::new();
+    vm.print_bytecode = args.verbosity >= 1;
+    vm.print_exec = args.verbosity >= 2;
+    vm.init(&prog, &args.args);
+
+    let mut last_check = Instant::now();
+    loop {
+        match vm.run_for(WATCH_OPS) {
+            Ok(OpResult::Done) => return Ok(()),
+            Ok(_) => {}
+            Err(e) => return Err(vec![e]),
+        }
+        if last_check.elapsed() < WATCH_INTERVAL {
+            continue;
+        }
+        last_check = Instant::now();
+        if modified() == seen {
+            continue;
+        }
+        match compile().and_then(|prog| vm.reload(&prog)) {
+            Ok(()) => eprintln!("reloaded {}", args.args[0]),
+            Err(errors) => errors.iter().for_each(|e| eprintln!("{}", e)),
+        }
+        seen = modified();
+    }
+}
+
 #[derive(Default, Debug, Options)]
 pub struct Args {
     #[options(
@@ -133,6 +339,13 @@ pub struct Args {
     )]
     pub skip_typecheck: bool,
 
+    #[options(
+        long = "deny-warnings",
+        no_short,
+        help = "Fails to compile if there are any warnings"
+    )]
+    pub deny_warnings: bool,
+
     #[options(long = "dump-tree", no_short, help = "Writes the tree to stdout")]
     pub dump_tree: bool,
 
@@ -146,6 +359,51 @@ pub struct Args {
     )]
     pub lua_compile: Option<String>,
 
+    #[options(
+        long = "source-map",
+        no_short,
+        help = "Write which lines of the compiled lua file come from where to <file>.map"
+    )]
+    pub source_map: bool,
+
+    #[options(
+        long = "lua-module",
+        no_short,
+        help = "Compile to a lua module that returns the globals, and write the runtime it requires next to it"
+    )]
+    pub lua_module: bool,
+
+    #[options(
+        long = "emit-bytecode",
+        no_short,
+        help = "Compile to a bytecode file, run it with 'sylt run <file>.syb'"
+    )]
+    pub emit_bytecode: Option<String>,
+
+    #[options(short = "O", long = "optimize", help = "Optimize the bytecode")]
+    pub optimize: bool,
+
+    #[options(
+        long = "debug",
+        no_short,
+        help = "Run in the interactive debugger, same as 'sylt debug <file>'"
+    )]
+    pub debug: bool,
+
+    #[options(
+        long = "profile",
+        no_short,
+        help = "Report where the time is spent, and write the stacks to <file>.folded"
+    )]
+    pub profile: bool,
+
+    #[options(
+        long = "watch",
+        no_short,
+        help = "Reload the code when a file changes, and keep the state of the program"
+    )]
+    pub watch: bool,
+
     #[options(short = "v", no_long, count, help = "Increase verbosity, up to max 2")]
     pub verbosity: u32,
 
@@ -162,10 +420,46 @@ pub struct Args {
     pub args: Vec<String>,
 }
 
+/// The commands `sylt` takes before the file, `sylt <file>` is the
+/// same as `sylt run <file>`.
+#[derive(Debug, Options)]
+pub enum Command {
+    #[options(help = "Run a source or bytecode file")]
+    Run(Args),
+    #[options(help = "Run a file in the interactive debugger, same as 'sylt --debug <file>'")]
+    Debug(Args),
+}
+
+impl From<Command> for Args {
+    fn from(command: Command) -> Self {
+        match command {
+            Command::Run(args) => args,
+            Command::Debug(args) => Args { debug: true, ..args },
+        }
+    }
+}
+
 impl Args {
-    /// Wraps the function with the same name from [gumdrop] for convenience.
+    /// Parses the arguments, which may start with a [Command].
+    /// A file that has the same name as a command can be run
+    /// with `sylt run <file>`.
+    pub fn parse_with_command<S: AsRef<str>>(args: &[S]) -> Result<Args, gumdrop::Error> {
+        match args.first() {
+            Some(name) if Command::command_usage(name.as_ref()).is_some() => {
+                Command::parse_args_default(args).map(Args::from)
+            }
+            _ => Args::parse_args_default(args),
+        }
+    }
+
+    /// Wraps the function with the same name from [gumdrop], with
+    /// support for the [Command]s.
     pub fn parse_args_default_or_exit() -> Args {
-        <Args as Options>::parse_args_default_or_exit()
+        let args: Vec<_> = std::env::args().collect();
+        Args::parse_with_command(&args[1..]).unwrap_or_else(|e| {
+            eprintln!("{}: {}", args[0], e);
+            std::process::exit(2);
+        })
     }
 }
 
@@ -199,9 +493,44 @@ macro_rules! assert_errs {
 
 #[cfg(test)]
 mod bytecode {
+    use std::io::Write;
+    use std::path::Path;
+    use sylt_common::error::Error;
+    use sylt_common::prog::{BytecodeProg, Prog};
+
+    /// Compiles like [crate::compile_with_externals], and shows the
+    /// errors if it fails.
+    pub(crate) fn compile_with<R>(
+        args: &crate::Args,
+        functions: crate::ExternFunctionList,
+        externals: crate::ExternalList,
+        reader: R,
+        write_file: Option<Box<dyn Write>>,
+    ) -> Prog
+    where
+        R: Fn(&Path) -> Result<String, Error>,
+    {
+        match crate::compile_with_externals(args, functions, externals, reader, write_file) {
+            Ok((prog, _)) => prog,
+            Err(errs) => panic!("{:?}", errs),
+        }
+    }
+
+    /// Compiles the program to bytecode with the standard library.
+    pub(crate) fn compile<R>(args: &crate::Args, reader: R) -> BytecodeProg
+    where
+        R: Fn(&Path) -> Result<String, Error>,
+    {
+        let functions = ::sylt_std::sylt::_sylt_link();
+        match compile_with(args, functions, crate::lib_externals(), reader, None) {
+            Prog::Bytecode(prog) => prog,
+            Prog::Lua(_) => unreachable!(),
+        }
+    }
+
     #[macro_export]
     macro_rules! test_file_run {
-        ($fn:ident, $path:literal, $print:expr, $errs:pat, $_:expr) => {
+        ($fn:ident, $path:literal, $print:expr, $deny_warnings:expr, $errs:pat, $_:expr) => {
             #[test]
             fn $fn() {
                 #[allow(unused_imports)]
@@ -209,11 +538,14 @@ mod bytecode {
                 #[allow(unused_imports)]
                 use sylt_common::error::TypeError;
                 #[allow(unused_imports)]
+                use sylt_common::error::Warning;
+                #[allow(unused_imports)]
                 use sylt_common::Type;
 
                 let mut args = $crate::Args::default();
                 args.args = vec![format!("../{}", $path)];
                 args.verbosity = if $print { 1 } else { 0 };
+                args.deny_warnings = $deny_warnings;
                 let res = $crate::run_file(&args, ::sylt_std::sylt::_sylt_link());
                 $crate::assert_errs!(res, $errs);
             }
@@ -223,11 +555,528 @@ mod bytecode {
     sylt_macro::find_tests!(test_file_run);
 }
 
+#[cfg(test)]
+mod optimized {
+    #[macro_export]
+    macro_rules! test_file_optimized {
+        ($fn:ident, $path:literal, $print:expr, $deny_warnings:expr, $errs:pat, $_:expr) => {
+            #[test]
+            fn $fn() {
+                #[allow(unused_imports)]
+                use sylt_common::error::RuntimeError;
+                #[allow(unused_imports)]
+                use sylt_common::error::TypeError;
+                #[allow(unused_imports)]
+                use sylt_common::error::Warning;
+                #[allow(unused_imports)]
+                use sylt_common::Type;
+
+                let mut args = $crate::Args::default();
+                args.args = vec![format!("../{}", $path)];
+                args.verbosity = if $print { 1 } else { 0 };
+                args.deny_warnings = $deny_warnings;
+                args.optimize = true;
+                let res = $crate::run_file(&args, ::sylt_std::sylt::_sylt_link());
+                $crate::assert_errs!(res, $errs);
+            }
+        };
+    }
+
+    sylt_macro::find_tests!(test_file_optimized);
+
+    fn ops_run(file: &str, optimize: bool) -> usize {
+        let args = crate::Args {
+            args: vec![file.to_string()],
+            optimize,
+            ..crate::Args::default()
+        };
+        let prog = crate::bytecode::compile(&args, crate::read_file);
+        let mut vm = sylt_machine::VM::new();
+        vm.init(&prog, &args.args);
+        assert!(vm.run().is_ok(), "Failed to run {}", file);
+        vm.ops_run
+    }
+
+    macro_rules! test_ops_run {
+        ($fn:ident, $path:literal, $cmp:tt) => {
+            #[test]
+            fn $fn() {
+                let unoptimized = ops_run($path, false);
+                let optimized = ops_run($path, true);
+                assert!(
+                    optimized $cmp unoptimized,
+                    "Optimized ran {} ops, unoptimized ran {} ops",
+                    optimized,
+                    unoptimized
+                );
+            }
+        };
+    }
+
+    test_ops_run!(ops_run_factorial, "../tests/bench/factorial.sy", <);
+    // Only dead code is removed from 'fib'.
+    test_ops_run!(ops_run_fib, "../tests/bench/fib.sy", <=);
+    test_ops_run!(ops_run_fib_iter, "../tests/bench/fib_iter.sy", <);
+    test_ops_run!(ops_run_sum, "../tests/bench/sum.sy", <);
+}
+
+#[cfg(test)]
+mod bytecode_file {
+    use std::path::Path;
+    use sylt_common::error::Error;
+    use sylt_common::prog::{BytecodeProg, Prog};
+    use sylt_common::Value;
+
+    #[macro_export]
+    macro_rules! test_file_syb {
+        ($fn:ident, $path:literal, $print:expr, $deny_warnings:expr, $errs:pat, $_:expr) => {
+            #[test]
+            fn $fn() {
+                #[allow(unused_imports)]
+                use sylt_common::error::RuntimeError;
+                #[allow(unused_imports)]
+                use sylt_common::error::TypeError;
+                #[allow(unused_imports)]
+                use sylt_common::error::Warning;
+                #[allow(unused_imports)]
+                use sylt_common::Type;
+
+                let file = format!("../{}", $path);
+                let mut args = $crate::Args::default();
+                args.args = vec![file.clone()];
+                args.verbosity = if $print { 1 } else { 0 };
+                args.deny_warnings = $deny_warnings;
+                let functions = ::sylt_std::sylt::_sylt_link();
+                let res = $crate::compile_with_reader_to_writer(
+                    &args,
+                    functions.clone(),
+                    $crate::read_file,
+                    None,
+                )
+                .and_then(|(prog, _)| match prog {
+                    sylt_common::prog::Prog::Bytecode(prog) => {
+                        let bytes = prog.to_bytes();
+                        let prog = sylt_common::prog::BytecodeProg::from_bytes(
+                            std::path::Path::new(&file),
+                            &bytes,
+                            &functions,
+                        )
+                        .map_err(|e| vec![e])?;
+                        $crate::run(&prog, &args)
+                    }
+                    sylt_common::prog::Prog::Lua(_) => unreachable!(),
+                });
+                $crate::assert_errs!(res, $errs);
+            }
+        };
+    }
+
+    sylt_macro::find_tests!(test_file_syb);
+
+    fn compile(file: &str) -> BytecodeProg {
+        let args = crate::Args {
+            args: vec![file.to_string()],
+            ..crate::Args::default()
+        };
+        crate::bytecode::compile(&args, crate::read_file)
+    }
+
+    #[test]
+    fn missing_extern_function() {
+        let bytes = compile("../tests/core/string_conversion.sy").to_bytes();
+        let res = BytecodeProg::from_bytes(Path::new("a.syb"), &bytes, &[]);
+        assert!(
+            matches!(&res, Err(Error::MissingExternFunction { name, .. }) if name == "as_str"),
+            "Expected a missing 'as_str'"
+        );
+    }
+
+    #[test]
+    fn only_used_extern_functions() {
+        let bytes = compile("../tests/core/string_conversion.sy").to_bytes();
+        let prog =
+            BytecodeProg::from_bytes(Path::new("a.syb"), &bytes, &::sylt_std::sylt::_sylt_link())
+                .ok()
+                .unwrap();
+        assert!(prog.function_names.len() < ::sylt_std::sylt::_sylt_link().len());
+        assert!(prog.function_names.contains(&"as_str".to_string()));
+    }
+
+    #[test]
+    fn externals_are_linked_again() {
+        let args = crate::Args {
+            args: vec!["game.sy".to_string()],
+            ..crate::Args::default()
+        };
+        let reader = |_: &Path| Ok("size: int : external\nstart :: fn do\nend\n".to_string());
+        let size = |value| {
+            let value: sylt_common::ExternalValue = std::rc::Rc::new(move |_| Value::Int(value));
+            vec![("size".to_string(), value, "int".to_string())]
+        };
+        let bytes = match crate::bytecode::compile_with(&args, Vec::new(), size(1), reader, None) {
+            Prog::Bytecode(prog) => prog.to_bytes(),
+            Prog::Lua(_) => unreachable!(),
+        };
+
+        let mut prog = BytecodeProg::from_bytes(Path::new("a.syb"), &bytes, &[])
+            .ok()
+            .unwrap();
+        let errors = prog.link_externals(&[], &[]).err().unwrap();
+        assert!(matches!(
+            errors.as_slice(),
+            [Error::MissingExternal { name, .. }] if name == "size"
+        ));
+
+        prog.link_externals(&[], &size(2)).ok().unwrap();
+        let slot = prog.externals[0].slot;
+        assert!(matches!(prog.constants[slot], Value::Int(2)));
+    }
+
+    #[test]
+    fn wrong_version() {
+        let mut bytes = compile("../tests/bench/fib.sy").to_bytes();
+        bytes[4] += 1;
+        let res = BytecodeProg::from_bytes(Path::new("a.syb"), &bytes, &[]);
+        assert!(matches!(res, Err(Error::BytecodeError { .. })));
+    }
+
+    #[test]
+    fn not_bytecode() {
+        let res = BytecodeProg::from_bytes(Path::new("a.syb"), b"start :: fn do end", &[]);
+        assert!(matches!(res, Err(Error::BytecodeError { .. })));
+    }
+}
+
+#[cfg(test)]
+mod command {
+    use crate::Args;
+
+    #[test]
+    fn file_without_a_command() {
+        let args = Args::parse_with_command(&["-O", "game.sy"]).unwrap();
+        assert_eq!(args.args, vec!["game.sy"]);
+        assert!(args.optimize && !args.debug);
+    }
+
+    #[test]
+    fn run_and_debug() {
+        let args = Args::parse_with_command(&["run", "game.syb"]).unwrap();
+        assert_eq!(args.args, vec!["game.syb"]);
+        assert!(!args.debug);
+
+        let args = Args::parse_with_command(&["debug", "-O", "game.sy"]).unwrap();
+        assert_eq!(args.args, vec!["game.sy"]);
+        assert!(args.optimize && args.debug);
+    }
+
+    #[test]
+    fn file_named_like_a_command() {
+        let args = Args::parse_with_command(&["run", "run"]).unwrap();
+        assert_eq!(args.args, vec!["run"]);
+        let args = Args::parse_with_command(&["run", "debug"]).unwrap();
+        assert_eq!(args.args, vec!["debug"]);
+        assert!(!args.debug);
+    }
+}
+
+#[cfg(test)]
+mod profiler {
+    fn profile(file: &str) -> sylt_machine::Profiler {
+        let args = crate::Args {
+            args: vec![file.to_string()],
+            ..crate::Args::default()
+        };
+        let prog = crate::bytecode::compile(&args, crate::read_file);
+        let mut vm = sylt_machine::VM::new();
+        vm.init(&prog, &args.args);
+        vm.start_profiling();
+        assert!(vm.run().is_ok(), "Failed to run {}", file);
+        let ops_run = vm.ops_run;
+        let profiler = vm.stop_profiling().unwrap();
+        let ops: usize = profiler.blocks().iter().map(|block| block.ops).sum();
+        assert_eq!(ops, ops_run);
+        profiler
+    }
+
+    #[test]
+    fn counts_calls() {
+        let profiler = profile("../tests/bench/fib.sy");
+        let fib = profiler
+            .blocks()
+            .iter()
+            .find(|block| block.name.ends_with("fib.sy:4"))
+            .unwrap();
+        // fib(23) calls itself fib(24) * 2 - 1 times.
+        assert_eq!(fib.calls, 92735);
+        assert!(fib.time <= profiler.total_time());
+    }
+
+    #[test]
+    fn writes_folded_stacks() {
+        let profiler = profile("../tests/bench/fib.sy");
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded.lines().any(|line| line.starts_with(
+            "/preamble/;fn lambda ../tests/bench/fib.sy:12;fn lambda ../tests/bench/fib.sy:4;fn lambda "
+        )));
+        for line in folded.lines() {
+            let (_, time) = line.rsplit_once(' ').unwrap();
+            assert!(time.parse::<u128>().is_ok());
+        }
+    }
+
+    #[test]
+    fn counts_tail_calls() {
+        let profiler = profile("../tests/core/tail_call.sy");
+        let count = profiler
+            .blocks()
+            .iter()
+            .find(|block| block.name.ends_with("tail_call.sy:3"))
+            .unwrap();
+        assert_eq!(count.calls, 100001);
+
+        // Tail calls replace the function on the stack.
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(!folded.contains("tail_call.sy:3;fn lambda"));
+    }
+}
+
+#[cfg(test)]
+mod limits {
+    use sylt_common::error::{Error, RuntimeError};
+    use sylt_machine::{Limits, VM};
+
+    fn vm(file: &str, limits: Limits) -> VM {
+        let args = crate::Args {
+            args: vec![file.to_string()],
+            ..crate::Args::default()
+        };
+        let prog = crate::bytecode::compile(&args, crate::read_file);
+        let mut vm = VM::new();
+        vm.init(&prog, &args.args);
+        vm.set_limits(limits);
+        vm
+    }
+
+    fn kind(error: Error) -> RuntimeError {
+        match error {
+            Error::RuntimeError { kind, .. } => kind,
+            error => panic!("Expected a runtime error, got {}", error),
+        }
+    }
+
+    #[test]
+    fn resumes_after_running_out_of_ops() {
+        let mut vm = vm(
+            "../tests/bench/fib.sy",
+            Limits { ops: Some(10000), ..Limits::default() },
+        );
+        let mut slices = 1;
+        while let Err(error) = vm.run() {
+            assert!(matches!(kind(error), RuntimeError::OpLimit(10000)));
+            vm.grant_ops(10000);
+            slices += 1;
+        }
+        assert_eq!(slices, vm.ops_run / 10000 + 1);
+    }
+
+    #[test]
+    fn call_depth() {
+        let mut vm = vm(
+            "../tests/bench/fib.sy",
+            Limits { call_depth: Some(10), ..Limits::default() },
+        );
+        let error = vm.run().err().unwrap();
+        assert!(format!("{}", error).contains("in fn lambda ../tests/bench/fib.sy:4 at line 8"));
+        assert!(matches!(kind(error), RuntimeError::CallDepthLimit(10)));
+    }
+
+    #[test]
+    fn stack_size() {
+        let mut vm = vm(
+            "../tests/bench/fib.sy",
+            Limits { stack_size: Some(20), ..Limits::default() },
+        );
+        assert!(matches!(
+            kind(vm.run().err().unwrap()),
+            RuntimeError::StackLimit(20)
+        ));
+    }
+
+    #[test]
+    fn heap_size() {
+        let mut vm = vm(
+            "../tests/gc/cycles.sy",
+            Limits { heap_size: Some(100), ..Limits::default() },
+        );
+        assert!(matches!(
+            kind(vm.run().err().unwrap()),
+            RuntimeError::HeapLimit(100)
+        ));
+        vm.set_limits(Limits::default());
+        assert!(vm.run().is_ok());
+    }
+
+    #[test]
+    fn limit_in_callback() {
+        let mut vm = vm(
+            "../tests/for_each/correct_num_calls.sy",
+            Limits { call_depth: Some(2), ..Limits::default() },
+        );
+        assert!(matches!(
+            kind(vm.run().err().unwrap()),
+            RuntimeError::CallDepthLimit(2)
+        ));
+        // The call to `for_each` has failed, so the program can't continue.
+        vm.set_limits(Limits::default());
+        assert!(matches!(
+            kind(vm.run().err().unwrap()),
+            RuntimeError::CallDepthLimit(2)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod debugger {
+    use std::io::Cursor;
+    use std::path::Path;
+    use sylt_common::error::Error;
+
+    /// Runs the file in the debugger, with the commands as input.
+    fn debug(file: &str, commands: &str) -> String {
+        let args = crate::Args {
+            args: vec![file.to_string()],
+            ..crate::Args::default()
+        };
+        debug_with(&args, crate::read_file, commands)
+    }
+
+    fn debug_with<R>(args: &crate::Args, reader: R, commands: &str) -> String
+    where
+        R: Fn(&Path) -> Result<String, Error>,
+    {
+        let prog = crate::bytecode::compile(args, reader);
+        let mut vm = sylt_machine::VM::new();
+        vm.init(&prog, &args.args);
+        let mut output = Vec::new();
+        assert!(vm.debug(&mut Cursor::new(commands), &mut output).is_ok());
+        String::from_utf8(output).unwrap()
+    }
+
+    const CLOSURES: &str = "../tests/closures/close_over_mutation.sy";
+
+    #[test]
+    fn breakpoint_and_upvalues() {
+        let output = debug(CLOSURES, "break 5\ncontinue\nupvalues\nbacktrace\nquit\n");
+        assert!(output.contains("stopped at ../tests/closures/close_over_mutation.sy:5"));
+        assert!(output.contains("b = 3"));
+        assert!(output.contains("*2 fn lambda ../tests/closures/close_over_mutation.sy:3"));
+    }
+
+    #[test]
+    fn step_over_and_locals() {
+        let output = debug(
+            CLOSURES,
+            "break 11\ncontinue\nnext\nlocals\nglobals\nquit\n",
+        );
+        assert!(output.contains("close_over_mutation.sy:12\n  12 |     3 <=> a()"));
+        assert!(output.contains("a = <fn"));
+        assert!(output.contains("start = <fn"));
+    }
+
+    #[test]
+    fn finish_returns_to_caller() {
+        let output = debug(
+            CLOSURES,
+            "break 4\ncontinue\nclear 4\nfinish\nprint a\nquit\n",
+        );
+        assert!(output.contains("stopped at ../tests/closures/close_over_mutation.sy:4"));
+        assert!(output.contains("stopped at ../tests/closures/close_over_mutation.sy:12"));
+        assert!(output.contains("a = <fn"));
+    }
+
+    #[test]
+    fn runs_to_the_end() {
+        let output = debug(CLOSURES, "continue\n");
+        assert!(output.ends_with("exited\n"));
+    }
+
+    #[test]
+    fn locals_when_optimized() {
+        let source = "
+start :: fn do
+    a := 1
+    1 + 1
+    b := a + 1
+    b <=> 2
+end
+";
+        let args = crate::Args {
+            args: vec!["optimized.sy".to_string()],
+            optimize: true,
+            ..crate::Args::default()
+        };
+        let output = debug_with(
+            &args,
+            |_| Ok(source.to_string()),
+            "break 6\ncontinue\nlocals\nquit\n",
+        );
+        assert!(output.contains("stopped at optimized.sy:6"));
+        assert!(output.contains("a = 1"));
+        assert!(output.contains("b = 2"));
+    }
+}
+
 #[cfg(test)]
 mod lua {
+    #[cfg(feature = "embedded-lua")]
+    #[macro_export]
+    macro_rules! test_file_lua {
+        ($fn:ident, $path:literal, $print:expr, $deny_warnings:expr, $errs:pat, $any_runtime_errors:expr) => {
+            #[test]
+            fn $fn() {
+                #[allow(unused_imports)]
+                use sylt_common::error::RuntimeError;
+                #[allow(unused_imports)]
+                use sylt_common::error::TypeError;
+                #[allow(unused_imports)]
+                use sylt_common::error::Warning;
+                #[allow(unused_imports)]
+                use sylt_common::Type;
+
+                let mut args = $crate::Args::default();
+                args.args = vec![format!("../{}", $path)];
+                args.verbosity = if $print { 1 } else { 0 };
+                args.deny_warnings = $deny_warnings;
+
+                let res = $crate::embedded_lua::run_file_with_reader(
+                    &args,
+                    ::sylt_std::sylt::_sylt_link(),
+                    $crate::read_file,
+                );
+                if $any_runtime_errors {
+                    assert!(
+                        matches!(
+                            res.as_ref().map_err(Vec::as_slice),
+                            Err([sylt_common::error::Error::LuaError { .. }])
+                        ),
+                        "Expected the program to crash, got {:?}",
+                        res
+                    );
+                } else {
+                    assert_errs!(res, $errs);
+                }
+            }
+        };
+    }
+
+    #[cfg(not(feature = "embedded-lua"))]
     #[macro_export]
     macro_rules! test_file_lua {
-        ($fn:ident, $path:literal, $print:expr, $errs:pat, $any_runtime_errors:expr) => {
+        ($fn:ident, $path:literal, $print:expr, $deny_warnings:expr, $errs:pat, $any_runtime_errors:expr) => {
             #[test]
             fn $fn() {
                 use std::io::Write;
@@ -237,12 +1086,15 @@ mod lua {
                 #[allow(unused_imports)]
                 use sylt_common::error::TypeError;
                 #[allow(unused_imports)]
+                use sylt_common::error::Warning;
+                #[allow(unused_imports)]
                 use sylt_common::Type;
 
                 let file = format!("../{}", $path);
                 let mut args = $crate::Args::default();
                 args.args = vec![file.clone()];
                 args.verbosity = if $print { 1 } else { 0 };
+                args.deny_warnings = $deny_warnings;
 
                 let mut child = Command::new("lua")
                     .stdin(Stdio::piped())
@@ -296,3 +1148,240 @@ mod lua {
 
     sylt_macro::find_tests!(test_file_lua);
 }
+
+#[cfg(test)]
+mod source_map {
+    use std::path::PathBuf;
+    use sylt_common::prog::{Prog, SourceMap};
+
+    const PROGRAM: &str = "
+start :: fn do
+    a := 1
+    b := a + 2
+    if b == 3 do
+        print(b)
+    end
+end
+";
+
+    /// Compiles the program to Lua, and gives the lines of Lua with the map.
+    fn compile() -> (Vec<String>, SourceMap) {
+        let args = crate::Args {
+            args: vec!["game.sy".to_string()],
+            ..crate::Args::default()
+        };
+        let out = std::env::temp_dir().join(format!("sylt_source_map_{}.lua", std::process::id()));
+        let file = std::fs::File::create(&out).unwrap();
+        let reader = |_: &std::path::Path| Ok(PROGRAM.to_string());
+        let functions = ::sylt_std::sylt::_sylt_link();
+        let externals = crate::lib_externals();
+        let map = match crate::bytecode::compile_with(
+            &args,
+            functions,
+            externals,
+            reader,
+            Some(Box::new(file)),
+        ) {
+            Prog::Lua(map) => map,
+            Prog::Bytecode(_) => unreachable!(),
+        };
+        let lua = std::fs::read_to_string(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        (lua.lines().map(String::from).collect(), map)
+    }
+
+    fn line_of(lua: &[String], code: &str) -> usize {
+        lua.iter().position(|line| line.contains(code)).unwrap() + 1
+    }
+
+    #[test]
+    fn lines_come_from_statements() {
+        let (lua, map) = compile();
+        assert_eq!(map.lines.len(), lua.len());
+        assert_eq!(map.lookup(1), None);
+
+        let line = |code| {
+            map.lookup(line_of(&lua, code))
+                .map(|(file, span)| (file.clone(), span.line_start))
+        };
+        let game = PathBuf::from("game.sy");
+        assert_eq!(line("__ADD( ( local_"), Some((game.clone(), 4)));
+        assert_eq!(line("print ( ( local_"), Some((game.clone(), 6)));
+        assert_eq!(line("-- End Sylt preamble"), None);
+    }
+
+    #[test]
+    fn writes_a_line_per_lua_line() {
+        let (lua, map) = compile();
+        let written = map.to_string();
+        assert_eq!(written.lines().count(), lua.len());
+        let print = line_of(&lua, "print ( ( local_");
+        assert_eq!(written.lines().nth(print - 1), Some("game.sy:6:9"));
+    }
+
+    #[test]
+    fn rewrites_tracebacks() {
+        let (lua, map) = compile();
+        let add = line_of(&lua, "__ADD( ( local_");
+        let stderr = format!(
+            "lua: stdin:{0}: attempt to perform arithmetic\nstack traceback:\n\t[C]: in ?\n\tstdin:{0}: in function 'GLOBAL_1'\n\tstdin:{1}: in main chunk\n",
+            add,
+            lua.len(),
+        );
+        let (rewritten, positions) = map.rewrite("stdin", &stderr);
+        assert!(rewritten.starts_with("lua: game.sy:4: attempt to perform arithmetic\n"));
+        assert!(rewritten.contains("\tgame.sy:4: in function 'GLOBAL_1'"));
+        // The postamble doesn't come from a statement.
+        assert!(rewritten.contains(&format!("\tstdin:{}: in main chunk", lua.len())));
+        let game = PathBuf::from("game.sy");
+        assert_eq!(positions, vec![(game.clone(), 4), (game, 4)]);
+    }
+}
+
+#[cfg(test)]
+mod reload {
+    use sylt_common::error::Error;
+    use sylt_common::prog::BytecodeProg;
+    use sylt_common::OpResult;
+    use sylt_machine::VM;
+
+    fn compile(source: &str) -> BytecodeProg {
+        let args = crate::Args {
+            args: vec!["game.sy".to_string()],
+            ..crate::Args::default()
+        };
+        crate::bytecode::compile(&args, |_| Ok(source.to_string()))
+    }
+
+    /// Starts the program, and runs it for a while.
+    fn started(source: &str) -> VM {
+        let mut vm = VM::new();
+        vm.init(&compile(source), &[]);
+        assert!(matches!(vm.run_for(10000), Ok(OpResult::Continue)));
+        vm
+    }
+
+    fn messages(errors: Vec<Error>) -> Vec<String> {
+        errors
+            .into_iter()
+            .map(|error| match error {
+                Error::ReloadError { message, .. } => message,
+                error => panic!("Expected a reload error, got {}", error),
+            })
+            .collect()
+    }
+
+    const COUNTER: &str = "
+count := 1
+step :: fn do
+    count += 1
+end
+start :: fn do
+    loop count > 0 do
+        step()
+    end
+end
+";
+
+    #[test]
+    fn replaces_functions_and_keeps_state() {
+        let mut vm = started(COUNTER);
+        // The loop only stops if the count was kept.
+        let reloaded = COUNTER.replace("count += 1", "count = 1 - count");
+        assert!(vm.reload(&compile(&reloaded)).is_ok());
+        assert!(matches!(vm.run_for(10000), Ok(OpResult::Done)));
+    }
+
+    #[test]
+    fn adds_functions() {
+        let mut vm = started(COUNTER);
+        let reloaded =
+            COUNTER.replace("count += 1", "stop()") + "stop :: fn do\n    count = 0\nend\n";
+        assert!(vm.reload(&compile(&reloaded)).is_ok());
+        assert!(matches!(vm.run_for(10000), Ok(OpResult::Done)));
+    }
+
+    #[test]
+    fn keeps_globals_the_old_code_assigns() {
+        let mut vm = started(COUNTER);
+        // The new code never assigns the count, but the old code did.
+        let reloaded = COUNTER.replace("count += 1", "if count == 1 do <!> end");
+        assert!(vm.reload(&compile(&reloaded)).is_ok());
+        assert!(matches!(vm.run_for(10000), Ok(OpResult::Continue)));
+    }
+
+    #[test]
+    fn frees_the_old_code() {
+        let mut vm = started(COUNTER);
+        // The code of a reload is freed by the reload after it.
+        for _ in 0..2 {
+            assert!(vm.reload(&compile(COUNTER)).is_ok());
+        }
+        let size = vm.code_size();
+        for _ in 0..10 {
+            assert!(vm.reload(&compile(COUNTER)).is_ok());
+        }
+        assert_eq!(vm.code_size(), size);
+        assert!(matches!(vm.run_for(10000), Ok(OpResult::Continue)));
+    }
+
+    #[test]
+    fn changed_type() {
+        let mut vm = started(COUNTER);
+        let reloaded = COUNTER
+            .replace("count := 1", "count := 1.0")
+            .replace("count += 1", "count += 1.0")
+            .replace("count > 0", "count > 0.0");
+        let errors = messages(vm.reload(&compile(&reloaded)).err().unwrap());
+        assert_eq!(
+            errors,
+            ["The type of 'count' changed from int to float, restart the program to use it"]
+        );
+        // The old code keeps running.
+        assert!(matches!(vm.run_for(10000), Ok(OpResult::Continue)));
+    }
+
+    #[test]
+    fn new_global_that_changes() {
+        let mut vm = started(COUNTER);
+        let reloaded = COUNTER.replace("count += 1", "count += 1\n    steps += 1") + "steps := 0\n";
+        let errors = messages(vm.reload(&compile(&reloaded)).err().unwrap());
+        assert_eq!(
+            errors,
+            ["'steps' is a new global that changes, restart the program to add it"]
+        );
+    }
+
+    const BLOBS: &str = "
+A :: blob {
+    x: int,
+}
+a := A { x: 1 }
+step :: fn do
+    a = A { x: a.x }
+end
+start :: fn do
+    loop a.x > 0 do
+        step()
+    end
+end
+";
+
+    #[test]
+    fn changed_blob() {
+        let mut vm = started(BLOBS);
+        let reloaded = BLOBS
+            .replace("x: int,", "x: int,\n    y: int,")
+            .replace("A { x: 1 }", "A { x: 1, y: 0 }")
+            .replace("A { x: a.x }", "A { x: a.x, y: 0 }");
+        let errors = messages(vm.reload(&compile(&reloaded)).err().unwrap());
+        assert_eq!(
+            errors,
+            ["The fields of the blob 'A' changed, restart the program to use them"]
+        );
+
+        let reloaded = BLOBS.replace("A { x: a.x }", "A { x: 0 }");
+        assert!(vm.reload(&compile(&reloaded)).is_ok());
+        assert!(matches!(vm.run_for(10000), Ok(OpResult::Done)));
+    }
+}
@@ -0,0 +1,219 @@
+//! Runs games written against `fallgamejam/love.sy` without LÖVE.
+//!
+//! A [Harness] loads the game and calls `start`, and then does what LÖVE
+//! does every frame: it tells the game about the input, and calls
+//! `love.update` and `love.draw`. What the game drew, and the globals of
+//! the game, can be looked at between the frames.
+
+use std::path::Path;
+use sylt_common::error::Error;
+use sylt_common::Value;
+use sylt_std::love::{self, Draw, Input, STEP};
+
+use crate::engine::IntoArgs;
+use crate::Engine;
+
+/// Drives a game through its frames. The state of [sylt_std::love] is kept
+/// per thread, so there can only be one harness per thread.
+pub struct Harness {
+    engine: Engine,
+}
+
+impl Harness {
+    /// Loads the game and calls `start`. The files the game loads are
+    /// read relative to the file.
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self, Vec<Error>> {
+        let path = path.as_ref();
+        Self::load(path, |engine| engine.load_file(path))
+    }
+
+    /// Loads the game from the source, as if it was read from `path`.
+    pub fn load_str<P: AsRef<Path>>(path: P, source: &str) -> Result<Self, Vec<Error>> {
+        let path = path.as_ref();
+        Self::load(path, |engine| engine.load_str(path, source))
+    }
+
+    fn load<F>(path: &Path, load: F) -> Result<Self, Vec<Error>>
+    where
+        F: FnOnce(&mut Engine) -> Result<(), Vec<Error>>,
+    {
+        love::reset(path.parent().unwrap_or_else(|| Path::new(".")));
+        let mut engine = Engine::new();
+        load(&mut engine)?;
+        engine.call::<Value, _>("start", ()).map_err(|e| vec![e])?;
+        Ok(Self { engine })
+    }
+
+    /// Gives the input to the game at the start of `frame`, the first
+    /// frame is 1.
+    pub fn input(&mut self, frame: usize, input: Input) {
+        love::queue(frame, input);
+    }
+
+    /// Runs the frames, or until the game quits.
+    pub fn run(&mut self, frames: usize) -> Result<(), Error> {
+        for _ in 0..frames {
+            if love::quit().is_some() {
+                break;
+            }
+            for input in love::next_frame() {
+                match input {
+                    Input::KeyPressed(key) => {
+                        self.callback("keypressed", (key.as_str(), key.as_str(), false))?
+                    }
+                    Input::KeyReleased(key) => {
+                        self.callback("keyreleased", (key.as_str(), key.as_str()))?
+                    }
+                    _ => {}
+                }
+            }
+            self.callback("update", (STEP,))?;
+            self.callback("draw", ())?;
+        }
+        Ok(())
+    }
+
+    /// Calls the function the game put in `love`, if there is one.
+    fn callback<A: IntoArgs>(&mut self, name: &str, args: A) -> Result<(), Error> {
+        let callback = match love::table() {
+            Value::Blob(table) => table.borrow().get(name).cloned(),
+            _ => None,
+        };
+        match callback {
+            Some(callback) if !callback.is_nil() => {
+                self.engine.call_value::<Value, _>(&callback, args)?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The number of frames that have been run.
+    pub fn frame(&self) -> usize {
+        love::frame()
+    }
+
+    /// What was drawn in the last frame.
+    pub fn draws(&self) -> Vec<Draw> {
+        love::draws()
+    }
+
+    /// The exit code, if the game has quit.
+    pub fn quit(&self) -> Option<i64> {
+        love::quit()
+    }
+
+    /// The engine the game runs in, to read its globals.
+    pub fn engine(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME: &str = "
+from love use love
+
+x := 0.
+frames := 0
+pressed := \"\"
+
+start :: fn do
+    love.update = fn delta do
+        frames += 1
+        if love.keyboard.isDown' \"right\" do
+            x += 60. * delta
+        end
+        if frames == 100 do
+            love.event.quit' 3
+        end
+    end
+    love.draw = fn do
+        love.graphics.setColor' (1., 0., 0., 1.)
+        love.graphics.rectangle' \"fill\", x, 10., 20., 20.
+    end
+    love.keypressed = fn key, _, _ do
+        pressed = key
+    end
+end
+";
+
+    fn game() -> Harness {
+        Harness::load_str("../fallgamejam/harness.sy", GAME).unwrap()
+    }
+
+    #[test]
+    fn frames_update_and_draw() {
+        let mut harness = game();
+        harness.run(10).unwrap();
+        assert_eq!(harness.frame(), 10);
+        assert_eq!(harness.engine().global::<i64>("frames").unwrap(), 10);
+
+        let draws = harness.draws();
+        let calls: Vec<_> = draws.iter().map(|draw| draw.call).collect();
+        assert_eq!(calls, ["setColor", "rectangle"]);
+        assert!(matches!(draws[1].args[0], Value::String(ref mode) if mode.as_str() == "fill"));
+    }
+
+    #[test]
+    fn scripted_input() {
+        let mut harness = game();
+        harness.input(11, Input::KeyPressed("right".to_string()));
+        harness.input(31, Input::KeyReleased("right".to_string()));
+        harness.run(60).unwrap();
+
+        let x = harness.engine().global::<f64>("x").unwrap();
+        assert!((x - 20.0).abs() < 1e-9, "Moved to {}", x);
+        assert_eq!(
+            harness.engine().global::<String>("pressed").unwrap(),
+            "right"
+        );
+        assert!(matches!(harness.draws()[1].args[1], Value::Float(drawn) if drawn == x));
+    }
+
+    #[test]
+    fn stops_when_the_game_quits() {
+        let mut harness = game();
+        harness.run(1000).unwrap();
+        assert_eq!(harness.frame(), 100);
+        assert_eq!(harness.quit(), Some(3));
+    }
+
+    #[test]
+    fn age_of_sylt_has_no_warnings() {
+        let args = crate::Args {
+            args: vec!["../fallgamejam/main.sy".to_string()],
+            deny_warnings: true,
+            ..crate::Args::default()
+        };
+        let functions = crate::lib_bindings();
+        let externals = crate::lib_externals();
+        crate::bytecode::compile_with(&args, functions, externals, crate::read_file, None);
+    }
+
+    #[test]
+    fn age_of_sylt() {
+        let mut harness = Harness::load_file("../fallgamejam/main.sy").unwrap();
+        harness.run(2).unwrap();
+        let printed = |harness: &Harness, text: &str| {
+            harness.draws().iter().any(
+                |draw| matches!(draw.args.first(), Some(Value::String(s)) if s.as_str() == text),
+            )
+        };
+        assert!(printed(&harness, "Age of Sylt"));
+        assert!(printed(&harness, "2 Players"));
+
+        // Starts a game with two players.
+        harness.input(3, Input::KeyPressed("return".to_string()));
+        harness.run(120).unwrap();
+        assert!(!printed(&harness, "Age of Sylt"));
+        assert!(harness.draws().iter().any(|draw| draw.call == "draw"));
+
+        harness.input(124, Input::KeyPressed("escape".to_string()));
+        harness.run(10).unwrap();
+        assert_eq!(harness.quit(), Some(0));
+        assert_eq!(harness.frame(), 124);
+    }
+}
@@ -1,9 +1,10 @@
-use sylt::{lib_bindings, Args, Options};
+use sylt::{lib_bindings, Args, Command, Options};
 
 fn main() -> Result<(), String> {
     let args = Args::parse_args_default_or_exit();
     if args.help {
         println!("{}", Args::usage());
+        println!("\nCommands:\n{}", Command::usage());
         return Ok(());
     }
     if args.args.len() == 0 {